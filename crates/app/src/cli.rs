@@ -0,0 +1,277 @@
+//! Command-line interface.
+//!
+//! Without a subcommand the application runs the HTTP server and the Kafka
+//! consumer. `replay` re-processes a range of the orders topic and exits;
+//! `export` writes stored orders to a file and exits.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use clap::{ArgGroup, Args, Parser, Subcommand};
+use export::{ExportFilter, ExportFormat, RowLayout};
+use kafka_consumer::ReplayBound;
+use service::DuplicatePolicy;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Shopping Cart Backend
+#[derive(Debug, Parser)]
+#[command(name = "app")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// What the application was asked to do.
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the server and the consumer (default).
+    #[command(skip)]
+    Serve,
+    /// Re-process orders from a range of the Kafka topic, then exit
+    Replay(ReplayArgs),
+    /// Export stored orders to a file, then exit
+    Export(ExportArgs),
+}
+
+/// Arguments of the `replay` subcommand.
+#[derive(Debug, Args)]
+#[command(
+    group(ArgGroup::new("from").required(true)),
+    group(ArgGroup::new("to"))
+)]
+pub struct ReplayArgs {
+    /// Start at the first message at or after this time
+    #[arg(long, value_name = "RFC3339", group = "from", value_parser = timestamp_bound)]
+    from_timestamp: Option<ReplayBound>,
+    /// Start at these offsets; only the listed partitions are replayed
+    #[arg(long, value_name = "PARTITION:OFFSET,...", group = "from", value_parser = offsets_bound)]
+    from_offsets: Option<ReplayBound>,
+    /// Stop before the first message at or after this time
+    #[arg(long, value_name = "RFC3339", group = "to", value_parser = timestamp_bound)]
+    to_timestamp: Option<ReplayBound>,
+    /// Stop before these offsets (default: the current end of each partition)
+    #[arg(long, value_name = "PARTITION:OFFSET,...", group = "to", value_parser = offsets_bound)]
+    to_offsets: Option<ReplayBound>,
+    /// What to do with orders that already exist: skip, overwrite or fail
+    #[arg(long = "on-duplicate", value_name = "POLICY", default_value = "skip")]
+    pub duplicate_policy: DuplicatePolicy,
+    /// Topic to replay (default: KAFKA_TOPIC)
+    #[arg(long)]
+    pub topic: Option<String>,
+    /// Consumer group for the replay (default: <KAFKA_GROUP_ID>-replay)
+    #[arg(long)]
+    pub group_id: Option<String>,
+    /// Stop if no message arrives for this long
+    #[arg(long, value_name = "DURATION", default_value = "30s", value_parser = humantime::parse_duration)]
+    pub idle_timeout: Duration,
+}
+
+impl ReplayArgs {
+    /// Where the replay starts.
+    pub fn start(&self) -> ReplayBound {
+        self.from_timestamp
+            .clone()
+            .or_else(|| self.from_offsets.clone())
+            .expect("the `from` group is required")
+    }
+
+    /// Where the replay stops, if not at the current end of each partition.
+    pub fn end(&self) -> Option<ReplayBound> {
+        self.to_timestamp
+            .clone()
+            .or_else(|| self.to_offsets.clone())
+    }
+}
+
+/// Arguments of the `export` subcommand.
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    /// Output format: csv, ndjson or parquet
+    #[arg(long, default_value = "csv")]
+    pub format: ExportFormat,
+    /// Rows of csv and parquet output: one per order or per item
+    #[arg(long = "rows", value_name = "ROWS", default_value = "order")]
+    pub layout: RowLayout,
+    /// Only orders created at or after this time
+    #[arg(long, value_name = "RFC3339", value_parser = parse_time)]
+    from: Option<DateTime<Utc>>,
+    /// Only orders created before this time
+    #[arg(long, value_name = "RFC3339", value_parser = parse_time)]
+    to: Option<DateTime<Utc>>,
+    /// Only orders shipped by these delivery services
+    #[arg(long, value_name = "SERVICE,...", value_delimiter = ',', value_parser = trimmed)]
+    delivery_service: Vec<String>,
+    /// Only orders paid in these currencies
+    #[arg(long, value_name = "CURRENCY,...", value_delimiter = ',', value_parser = trimmed)]
+    currency: Vec<String>,
+    /// Only orders of this customer
+    #[arg(long)]
+    customer_id: Option<String>,
+    /// File to write; replaced if it exists
+    #[arg(long, short, value_name = "PATH")]
+    pub output: PathBuf,
+    /// Mask customer PII as for callers without the `pii:read` scope
+    #[arg(long)]
+    pub masked: bool,
+}
+
+impl ExportArgs {
+    /// The orders to export.
+    pub fn filter(&self) -> ExportFilter {
+        let list = |items: &[String]| {
+            items
+                .iter()
+                .filter(|item| !item.is_empty())
+                .cloned()
+                .collect()
+        };
+        ExportFilter {
+            from: self.from,
+            to: self.to,
+            delivery_services: list(&self.delivery_service),
+            currencies: list(&self.currency),
+            customer_id: self.customer_id.clone(),
+        }
+    }
+}
+
+/// Parses the process arguments, exiting with usage information on invalid input.
+pub fn parse() -> Command {
+    Cli::parse().command.unwrap_or(Command::Serve)
+}
+
+fn timestamp_bound(value: &str) -> Result<ReplayBound> {
+    parse_timestamp(value).map(ReplayBound::Timestamp)
+}
+
+fn offsets_bound(value: &str) -> Result<ReplayBound> {
+    parse_offsets(value).map(ReplayBound::Offsets)
+}
+
+fn parse_time(value: &str) -> Result<DateTime<Utc>> {
+    let time = humantime::parse_rfc3339_weak(value)
+        .with_context(|| format!("Invalid timestamp '{value}'"))?;
+    Ok(time.into())
+}
+
+fn trimmed(value: &str) -> Result<String> {
+    Ok(value.trim().to_string())
+}
+
+/// Parses an RFC 3339 timestamp into milliseconds since the Unix epoch.
+fn parse_timestamp(value: &str) -> Result<i64> {
+    let time = humantime::parse_rfc3339_weak(value)
+        .with_context(|| format!("Invalid timestamp '{value}'"))?;
+    let millis = time
+        .duration_since(UNIX_EPOCH)
+        .with_context(|| format!("Timestamp '{value}' is before the Unix epoch"))?
+        .as_millis();
+    Ok(i64::try_from(millis)?)
+}
+
+/// Parses `PARTITION:OFFSET` pairs separated by commas.
+fn parse_offsets(value: &str) -> Result<HashMap<i32, i64>> {
+    value
+        .split(',')
+        .map(|pair| {
+            let (partition, offset) = pair
+                .trim()
+                .split_once(':')
+                .with_context(|| format!("Expected PARTITION:OFFSET, got '{pair}'"))?;
+            Ok((
+                partition.trim().parse().context("Invalid partition")?,
+                offset.trim().parse().context("Invalid offset")?,
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Command, clap::Error> {
+        Cli::try_parse_from(args).map(|cli| cli.command.unwrap_or(Command::Serve))
+    }
+
+    #[test]
+    fn test_no_subcommand_serves() {
+        assert!(matches!(parse_args(&["app"]).unwrap(), Command::Serve));
+    }
+
+    #[test]
+    fn test_replay_args() {
+        let Command::Replay(args) = parse_args(&[
+            "app",
+            "replay",
+            "--from-offsets",
+            "0:10, 1:20",
+            "--to-timestamp",
+            "2024-01-01T00:00:00Z",
+            "--on-duplicate",
+            "overwrite",
+        ])
+        .unwrap() else {
+            panic!("expected replay");
+        };
+        assert_eq!(
+            args.start(),
+            ReplayBound::Offsets(HashMap::from([(0, 10), (1, 20)]))
+        );
+        assert_eq!(args.end(), Some(ReplayBound::Timestamp(1_704_067_200_000)));
+        assert_eq!(args.duplicate_policy, DuplicatePolicy::Overwrite);
+        assert_eq!(args.idle_timeout, Duration::from_secs(30));
+
+        assert!(parse_args(&["app", "replay"]).is_err());
+        assert!(parse_args(&["app", "replay", "--from-offsets", "0=10"]).is_err());
+        assert!(
+            parse_args(&[
+                "app",
+                "replay",
+                "--from-offsets",
+                "0:10",
+                "--from-timestamp",
+                "2024-01-01T00:00:00Z",
+            ])
+            .is_err(),
+            "the start bounds are exclusive"
+        );
+    }
+
+    #[test]
+    fn test_export_args() {
+        let Command::Export(args) = parse_args(&[
+            "app",
+            "export",
+            "--format",
+            "parquet",
+            "--rows",
+            "item",
+            "--from",
+            "2024-01-01T00:00:00Z",
+            "--currency",
+            "EUR, USD",
+            "--output",
+            "orders.parquet",
+        ])
+        .unwrap() else {
+            panic!("expected export");
+        };
+        assert_eq!(args.format, ExportFormat::Parquet);
+        assert_eq!(args.layout, RowLayout::Item);
+        let filter = args.filter();
+        assert_eq!(filter.from.map(|t| t.timestamp()), Some(1_704_067_200));
+        assert_eq!(filter.to, None);
+        assert_eq!(filter.currencies, ["EUR", "USD"]);
+        assert!(filter.delivery_services.is_empty());
+        assert_eq!(args.output, PathBuf::from("orders.parquet"));
+        assert!(!args.masked);
+
+        assert!(
+            parse_args(&["app", "export"]).is_err(),
+            "--output is required"
+        );
+        assert!(parse_args(&["app", "export", "--format", "xlsx", "-o", "x"]).is_err());
+    }
+}
@@ -0,0 +1,252 @@
+//! Logging setup: output format, level filter, file rotation and PII redaction.
+//!
+//! Every log line passes through a [`Redactor`] before it is written, which masks
+//! customer data that may end up in logs through `Debug` or JSON output of
+//! orders: the whole `Delivery` structure (name, phone, address, email, ...) and
+//! `Payment.transaction`, wherever they appear in the line.
+
+use anyhow::{Context, Result, bail};
+use app_config::AppConfig;
+use regex::Regex;
+use std::io::{self, Write};
+use std::sync::LazyLock;
+use telemetry::{TelemetryConfig, TelemetryGuard};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+/// Placeholder written in place of redacted values.
+const REDACTED: &str = "[REDACTED]";
+
+/// Keeps log file writing and span export running; flushes both when dropped.
+pub struct LoggingGuard {
+    _file: Option<WorkerGuard>,
+    _telemetry: TelemetryGuard,
+}
+
+/// Installs the global subscriber configured by `config`.
+///
+/// # Errors
+/// Returns an error for an unknown log format or rotation, an invalid level
+/// filter, or if a global subscriber is already installed.
+pub fn init(config: &AppConfig) -> Result<LoggingGuard> {
+    let filter = EnvFilter::try_new(&config.log_level)
+        .with_context(|| format!("Invalid log level filter '{}'", config.log_level))?;
+
+    let (writer, file_guard) = if config.log_dir.is_empty() {
+        (BoxMakeWriter::new(io::stdout), None)
+    } else {
+        let appender = RollingFileAppender::builder()
+            .rotation(parse_rotation(&config.log_rotation)?)
+            .filename_prefix("shoppingcart")
+            .filename_suffix("log")
+            .build(&config.log_dir)
+            .with_context(|| format!("Failed to open log directory {}", config.log_dir))?;
+        let (writer, guard) = tracing_appender::non_blocking(appender);
+        (BoxMakeWriter::new(writer), Some(guard))
+    };
+    let writer = RedactingMakeWriter::new(writer);
+    let ansi = config.log_dir.is_empty();
+
+    let fmt = tracing_subscriber::fmt::layer().with_ansi(ansi);
+    let fmt = match config.log_format.as_str() {
+        "text" => fmt.with_writer(writer).boxed(),
+        "pretty" => fmt.pretty().with_writer(writer).boxed(),
+        "json" => fmt
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_writer(writer)
+            .boxed(),
+        other => bail!("Unknown log format '{other}' (expected text, pretty or json)"),
+    };
+
+    let (otel, telemetry_guard) = telemetry::layer(&TelemetryConfig {
+        service_name: config.otel_service_name.clone(),
+        otlp_endpoint: Some(config.otel_exporter_otlp_endpoint.clone())
+            .filter(|endpoint| !endpoint.is_empty()),
+    })?;
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt)
+        .with(otel)
+        .try_init()
+        .context("Failed to install tracing subscriber")?;
+
+    Ok(LoggingGuard {
+        _file: file_guard,
+        _telemetry: telemetry_guard,
+    })
+}
+
+fn parse_rotation(rotation: &str) -> Result<Rotation> {
+    Ok(match rotation {
+        "minutely" => Rotation::MINUTELY,
+        "hourly" => Rotation::HOURLY,
+        "daily" => Rotation::DAILY,
+        "never" => Rotation::NEVER,
+        other => {
+            bail!("Unknown log rotation '{other}' (expected minutely, hourly, daily or never)")
+        }
+    })
+}
+
+/// Masks PII in formatted log lines.
+///
+/// Handles `Debug` output (`Delivery { .. }`, `transaction: ".."`), JSON output
+/// (`"delivery":{..}`, `"transaction":".."`), the same inside JSON-escaped strings,
+/// and `transaction=..` fields.
+pub struct Redactor {
+    rules: Vec<(Regex, String)>,
+}
+
+static REDACTOR: LazyLock<Redactor> = LazyLock::new(Redactor::new);
+
+impl Redactor {
+    fn new() -> Self {
+        let mut rules = vec![
+            (
+                Regex::new(r"Delivery \{[^}]*\}").unwrap(),
+                format!("Delivery {{ {REDACTED} }}"),
+            ),
+            (
+                Regex::new(r#"\btransaction=("(?:[^"\\]|\\.)*"|[^\s,}]+)"#).unwrap(),
+                format!("transaction={REDACTED}"),
+            ),
+        ];
+        // Plain and JSON-escaped quotes, e.g. for Debug output inside a JSON log line.
+        for quote in [r#"""#, r#"\""#] {
+            let q = regex::escape(quote);
+            let value = format!(r#"{q}(?:[^"\\]|\\.)*?{q}"#);
+            rules.push((
+                Regex::new(&format!(r"{q}delivery{q}\s*:\s*\{{[^}}]*\}}")).unwrap(),
+                format!("{quote}delivery{quote}:{quote}{REDACTED}{quote}"),
+            ));
+            rules.push((
+                Regex::new(&format!(r"{q}transaction{q}\s*:\s*{value}")).unwrap(),
+                format!("{quote}transaction{quote}:{quote}{REDACTED}{quote}"),
+            ));
+            rules.push((
+                Regex::new(&format!(r"\btransaction: {value}")).unwrap(),
+                format!("transaction: {quote}{REDACTED}{quote}"),
+            ));
+        }
+        Self { rules }
+    }
+
+    /// Returns the line with all PII masked.
+    pub fn redact(&self, line: &str) -> String {
+        let mut line = line.to_string();
+        for (pattern, replacement) in &self.rules {
+            if pattern.is_match(&line) {
+                line = pattern
+                    .replace_all(&line, regex::NoExpand(replacement))
+                    .into_owned();
+            }
+        }
+        line
+    }
+}
+
+/// [`MakeWriter`] whose writers redact each log line before passing it on.
+struct RedactingMakeWriter<M> {
+    inner: M,
+}
+
+impl<M> RedactingMakeWriter<M> {
+    fn new(inner: M) -> Self {
+        Self { inner }
+    }
+}
+
+impl<'a, M> MakeWriter<'a> for RedactingMakeWriter<M>
+where
+    M: MakeWriter<'a> + 'static,
+{
+    type Writer = RedactingWriter<M::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter {
+            inner: self.inner.make_writer(),
+            buf: Vec::new(),
+        }
+    }
+}
+
+/// Buffers one formatted event and writes it, redacted, when dropped.
+struct RedactingWriter<W: Write> {
+    inner: W,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> Write for RedactingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<W: Write> Drop for RedactingWriter<W> {
+    fn drop(&mut self) {
+        if self.buf.is_empty() {
+            return;
+        }
+        let line = String::from_utf8_lossy(&self.buf);
+        let _ = self.inner.write_all(REDACTOR.redact(&line).as_bytes());
+        let _ = self.inner.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::{Delivery, Order, Payment};
+
+    fn order() -> Order {
+        Order {
+            order_uid: "b563feb7b2b84b6test".into(),
+            delivery: Delivery {
+                name: "Test Testov".into(),
+                phone: "+9720000000".into(),
+                email: "test@gmail.com".into(),
+                ..Default::default()
+            },
+            payment: Payment {
+                transaction: "txn-secret".into(),
+                currency: "USD".into(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn assert_redacted(line: &str) {
+        let redacted = REDACTOR.redact(line);
+        for secret in ["Test Testov", "+9720000000", "test@gmail.com", "txn-secret"] {
+            assert!(!redacted.contains(secret), "{secret} leaked: {redacted}");
+        }
+        assert!(redacted.contains("b563feb7b2b84b6test"), "{redacted}");
+        assert!(redacted.contains("USD"), "{redacted}");
+    }
+
+    #[test]
+    fn test_redacts_debug_and_json_output() {
+        let debug = format!("Failed to save order {:?}", order());
+        assert_redacted(&debug);
+        assert_redacted(&serde_json::to_string(&order()).unwrap());
+        // Debug output inside a JSON log line has its quotes escaped.
+        assert_redacted(&serde_json::json!({ "message": debug }).to_string());
+        assert_redacted(
+            "INFO saving payment transaction=txn-secret currency=USD order=b563feb7b2b84b6test",
+        );
+    }
+}
@@ -21,35 +21,164 @@ use anyhow::{Context, Result};
 /// - Caching for performance optimization
 /// - Metrics for monitoring
 ///
+mod cli;
+mod logging;
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
 use std::sync::Arc;
 use tokio::signal;
 use tokio::sync::Notify;
 use tokio::task::JoinSet;
-use tracing::{error, info};
-
-use app_config::AppConfig;
-use cache::OrderCache;
-use kafka_consumer::KafkaConsumer;
+use tracing::{error, info, warn};
+
+use app_config::{AppConfig, SubscriptionConfig};
+use cache::{ConsistencyChecker, OrderCache, OrderFeed, ScanMode};
+use codec::registry::SchemaRegistry;
+use deadpool_postgres::Pool;
+use export::{Encoder, OrderPages};
+use kafka_consumer::{
+    BatchConfig, ConsumerConfig, KafkaConsumer, ReplayConfig, Subscription, build_decoder,
+};
+use kafka_producer::OrderProducer;
+use model::PiiView;
+use prometheus::Registry;
 use repository::{
     PgDeliveriesRepository, PgItemsRepository, PgOrdersRepository, PgPaymentsRepository,
 };
-use server::Server;
-use service::OrderServiceImpl;
+use server::{Authenticator, RateLimiter, Server, ServerConfig};
+use service::{
+    BusinessMetrics, KpiAllowList, OrderChanges, OrderService, OrderServiceImpl, SaveOptions,
+};
 use tokio_postgres::NoTls;
 
-/// Initialize the tracing subscriber for logging
-fn init_logger() -> Result<()> {
-    tracing_subscriber::fmt::init();
+/// Builds the consumer subscriptions from the config, defaulting to JSON orders on `kafka_topic`.
+fn subscriptions(config: &AppConfig) -> Result<Vec<Subscription>> {
+    let configured = if config.kafka_subscriptions.is_empty() {
+        vec![SubscriptionConfig {
+            topic: config.kafka_topic.clone(),
+            decoder: "json".to_string(),
+            validation: "default".to_string(),
+        }]
+    } else {
+        config.kafka_subscriptions.clone()
+    };
+    let schema_registry = if config.kafka_schema_registry_url.is_empty() {
+        None
+    } else {
+        Some(Arc::new(SchemaRegistry::new(
+            &config.kafka_schema_registry_url,
+        )?))
+    };
+    configured
+        .into_iter()
+        .map(|sub| {
+            Subscription::new(
+                sub.topic,
+                build_decoder(&sub.decoder, schema_registry.as_ref())?,
+                sub.validation
+                    .parse()
+                    .map_err(|e: String| anyhow::anyhow!(e))?,
+            )
+        })
+        .collect()
+}
+
+/// Replays a range of the orders topic and logs the resulting counts.
+async fn run_replay(
+    config: &AppConfig,
+    args: cli::ReplayArgs,
+    order_service: &dyn OrderService,
+    shutdown: &Notify,
+) -> Result<()> {
+    let (start, end) = (args.start(), args.end());
+    let group_id = args
+        .group_id
+        .unwrap_or_else(|| format!("{}-replay", config.kafka_group_id));
+    if group_id == config.kafka_group_id {
+        return Err(anyhow::anyhow!(
+            "Replay must not use the production consumer group {group_id}"
+        ));
+    }
+    let topic = args.topic.unwrap_or_else(|| config.kafka_topic.clone());
+    // Decode and validate the topic's orders the same way the consumer does.
+    let subscription = subscriptions(config)?
+        .into_iter()
+        .find(|sub| sub.matches(&topic))
+        .with_context(|| format!("No subscription configured for topic {topic}"))?;
+    let replay_config = ReplayConfig {
+        brokers: config.kafka_brokers.clone(),
+        topic,
+        group_id,
+        decoder: subscription.decoder,
+        validation: subscription.validation,
+        start,
+        end,
+        options: SaveOptions {
+            duplicate_policy: args.duplicate_policy,
+        },
+        idle_timeout: args.idle_timeout,
+    };
+    info!(
+        "Replaying topic {} with consumer group {} (duplicates: {})",
+        replay_config.topic, replay_config.group_id, args.duplicate_policy
+    );
+    let report = kafka_consumer::replay(&replay_config, order_service, shutdown).await?;
+    info!("Replay finished: {report}");
+    Ok(())
+}
+
+/// Writes the orders matching the export arguments to the output file.
+async fn run_export(config: &AppConfig, args: cli::ExportArgs, db_pool: Pool) -> Result<()> {
+    let view = if args.masked {
+        PiiView::Masked
+    } else {
+        PiiView::Full
+    };
+    let mut encoder = Encoder::new(args.format, args.layout, view)?;
+    let mut pages = OrderPages::new(db_pool, args.filter(), config.export_page_size);
+    let file = File::create(&args.output)
+        .with_context(|| format!("Failed to create {}", args.output.display()))?;
+    let mut output = BufWriter::new(file);
+    info!(
+        "Exporting orders as {} ({} rows) to {}",
+        args.format,
+        args.layout,
+        args.output.display()
+    );
+
+    let mut exported = 0;
+    while let Some(orders) = pages.next_page().await? {
+        output.write_all(&encoder.encode(&orders)?)?;
+        exported += orders.len();
+    }
+    output.write_all(&encoder.finish()?)?;
+    output
+        .flush()
+        .with_context(|| format!("Failed to write {}", args.output.display()))?;
+
+    if view == PiiView::Full {
+        server::audit::unmasked_access("cli", None, "export", exported);
+    }
+    info!("Exported {exported} orders to {}", args.output.display());
     Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize logger
-    if let Err(err) = init_logger() {
-        eprintln!("Failed to initialize logger: {err}");
-        return Err(anyhow::anyhow!("Failed to initialize logger"));
-    }
+    let command = cli::parse();
+
+    // Load configuration
+    let config = AppConfig::load().context("Failed to load configuration")?;
+
+    // Initialize logger; the guard flushes pending log lines and spans on exit
+    let _logging = match logging::init(&config) {
+        Ok(guard) => guard,
+        Err(err) => {
+            eprintln!("Failed to initialize logger: {err:#}");
+            return Err(anyhow::anyhow!("Failed to initialize logger"));
+        }
+    };
 
     info!("Shopping Cart Backend starting...");
 
@@ -70,9 +199,6 @@ async fn main() -> Result<()> {
         }
     });
 
-    // Load configuration
-    let config = AppConfig::load().context("Failed to load configuration")?;
-
     // Initialize database
     let db_pool = match db::init_db_pool(&config).await {
         Ok(pool) => {
@@ -86,8 +212,16 @@ async fn main() -> Result<()> {
         }
     };
 
+    if let cli::Command::Export(args) = command {
+        return run_export(&config, args, db_pool).await;
+    }
+
     // Initialize cache
-    let order_cache = Arc::new(OrderCache::new());
+    let order_cache = Arc::new(OrderCache::with_negative_ttl(config.cache_negative_ttl));
+    // Orders saved by the consumer, streamed to live clients of the HTTP server
+    let order_feed = Arc::new(OrderFeed::new(config.order_stream_buffer));
+    // Orders created or updated through the service, for WebSocket subscribers
+    let order_changes = OrderChanges::new(config.order_stream_buffer);
 
     // Get a connection to initialize repositories
     // We need to create separate connections for each repository
@@ -187,14 +321,35 @@ async fn main() -> Result<()> {
     let payments_repo = PgPaymentsRepository::new(payments_client);
     let items_repo = PgItemsRepository::new(items_client);
 
+    // Shared Prometheus registry served by the HTTP server at /metrics
+    let registry = Registry::new();
+
     // Initialize order service
-    let order_service = Arc::new(OrderServiceImpl::new(
-        db_pool.clone(),
-        orders_repo,
-        deliveries_repo,
-        payments_repo,
-        items_repo,
-    ));
+    let business_metrics = BusinessMetrics::new(
+        &registry,
+        KpiAllowList {
+            delivery_services: config.kpi_delivery_services.iter().cloned().collect(),
+            entries: config.kpi_entries.iter().cloned().collect(),
+            locales: config.kpi_locales.iter().cloned().collect(),
+            currencies: config.kpi_currencies.iter().cloned().collect(),
+        },
+    )
+    .context("Failed to register business metrics")?;
+    let order_service = Arc::new(
+        OrderServiceImpl::new(
+            db_pool.clone(),
+            orders_repo,
+            deliveries_repo,
+            payments_repo,
+            items_repo,
+        )
+        .with_metrics(business_metrics)
+        .with_changes(order_changes.clone()),
+    );
+
+    if let cli::Command::Replay(args) = command {
+        return run_replay(&config, args, order_service.as_ref(), &shutdown).await;
+    }
 
     // Load cache from DB
     info!("Creating additional repository instances for cache loading");
@@ -339,15 +494,28 @@ async fn main() -> Result<()> {
     let kafka_shutdown = shutdown.clone();
 
     // Initialize KafkaConsumer
+    let consumer_config = ConsumerConfig {
+        brokers: config.kafka_brokers.clone(),
+        subscriptions: subscriptions(&config)?,
+        group_id: config.kafka_group_id.clone(),
+        batch: (config.kafka_batch_size > 0).then_some(BatchConfig {
+            max_messages: config.kafka_batch_size,
+            max_wait: config.kafka_batch_timeout,
+        }),
+        partition_workers: (config.kafka_partition_workers > 0)
+            .then_some(config.kafka_partition_workers),
+        dlq_topic: (!config.kafka_dlq_topic.is_empty()).then(|| config.kafka_dlq_topic.clone()),
+        statistics_interval: config.kafka_statistics_interval,
+    };
     match KafkaConsumer::new(
-        &config.kafka_brokers,
-        &config.kafka_topic,
-        &config.kafka_group_id,
+        &consumer_config,
         order_service.clone(),
         order_cache.clone(),
+        &registry,
     ) {
         Ok(consumer) => {
             // Start KafkaConsumer in a separate task
+            let consumer = Arc::new(consumer.with_feed(order_feed.clone()));
             tasks.spawn(async move {
                 info!("Starting Kafka consumer");
                 if let Err(err) = consumer.run(kafka_shutdown).await {
@@ -360,7 +528,81 @@ async fn main() -> Result<()> {
         }
     }
 
-    let http_server = Server::new(http_port, order_cache.clone(), static_dir, db_pool);
+    // Cache consistency checker, triggered periodically and via the admin endpoint
+    let consistency_checker = Arc::new(
+        ConsistencyChecker::new(
+            db_pool.clone(),
+            order_cache.clone(),
+            order_service.clone(),
+            &registry,
+        )
+        .context("Failed to create cache consistency checker")?,
+    );
+    if !config.consistency_check_interval.is_zero() {
+        let mode = match config.consistency_check_sample_size {
+            0 => ScanMode::Full,
+            size => ScanMode::Sample(size),
+        };
+        info!(
+            "Starting periodic cache consistency check every {:?}",
+            config.consistency_check_interval
+        );
+        consistency_checker.clone().spawn_periodic(
+            config.consistency_check_interval,
+            mode,
+            config.consistency_check_repair,
+        );
+    }
+
+    let http_server = Server::new(
+        ServerConfig {
+            port: http_port,
+            static_dir,
+            duration_buckets: config.http_duration_buckets.clone(),
+        },
+        order_cache.clone(),
+        db_pool,
+        order_service.clone(),
+        registry,
+        consistency_checker,
+    );
+    let http_server = http_server
+        .with_rate_limiter(RateLimiter::new(
+            config.rate_limit_default,
+            config.rate_limit_routes.clone(),
+        ))
+        .with_max_concurrent_requests(config.http_max_concurrent_requests)
+        .with_compression(config.http_compression_min_size)
+        .with_export_page_size(config.export_page_size)
+        .with_order_producer(Arc::new(
+            OrderProducer::from_config(&config)
+                .context("Failed to configure the order producer")?,
+        ))
+        .with_order_stream(order_feed, config.order_stream_heartbeat)
+        .with_order_subscriptions(
+            order_changes,
+            config.order_ws_max_subscriptions,
+            config.order_ws_ping_interval,
+        );
+    let http_server = if config.auth_enabled {
+        if config.auth_api_keys.is_empty()
+            && config.auth_jwt_hs256_secret.is_empty()
+            && config.auth_jwt_rs256_public_key_path.is_empty()
+        {
+            warn!("No API keys or JWT keys are configured; API routes will reject every request");
+        }
+        let auth =
+            Authenticator::from_config(&config).context("Failed to configure authentication")?;
+        http_server.with_auth(auth)
+    } else {
+        warn!("HTTP API authentication is disabled; all routes are public");
+        http_server
+    };
+    #[cfg(feature = "embed-static")]
+    let http_server = {
+        info!("Serving embedded static files");
+        http_server.with_embedded_static()
+    };
     tasks.spawn(async move {
         if let Err(err) = http_server.start().await {
             error!("HTTP server error: {}", err);
@@ -0,0 +1,416 @@
+//! Cache–database consistency checking and repair.
+//!
+//! The in-memory [`OrderCache`] can drift from the `orders` table: a cache update
+//! after a save may be skipped, or rows may be edited manually. The
+//! [`ConsistencyChecker`] compares content hashes of cached orders with the orders
+//! loaded from the database and classifies differences as:
+//!
+//! - **missing** – the order exists in the DB but not in the cache;
+//! - **stale** – both exist, but their contents differ;
+//! - **orphaned** – the order is cached but no longer exists in the DB.
+//!
+//! Each run produces a [`ConsistencyReport`], updates Prometheus metrics and can
+//! optionally repair the cache from the database.
+
+use crate::OrderCache;
+use anyhow::Result;
+use chrono::{DateTime, SubsecRound, Utc};
+use deadpool_postgres::{Object as DbConn, Pool};
+use model::Order;
+use prometheus::{IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry};
+use rand::seq::SliceRandom;
+use repository::RepositoryError;
+use serde::Serialize;
+use service::{OrderService, ServiceError};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+/// Computes a stable content hash of an order.
+///
+/// The hash is a hex-encoded SHA-256 of the order's JSON form. `date_created` is
+/// truncated to microseconds first, because that is the precision Postgres stores,
+/// so an order received from Kafka hashes the same as its persisted copy.
+pub fn content_hash(order: &Order) -> String {
+    let normalized = Order {
+        date_created: order.date_created.trunc_subsecs(6),
+        ..order.clone()
+    };
+    let json = serde_json::to_vec(&normalized).expect("Order is always serializable");
+    hex::encode(Sha256::digest(&json))
+}
+
+/// Which part of the data set a consistency run inspects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanMode {
+    /// Compare every order in the database and every cache entry.
+    Full,
+    /// Compare a random sample of this many DB orders and this many cache entries.
+    Sample(usize),
+}
+
+/// Outcome of a single consistency run.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConsistencyReport {
+    /// `"full"` or `"sample"`.
+    pub mode: &'static str,
+    /// Whether differences were repaired during this run.
+    pub repair: bool,
+    /// When the run started.
+    pub started_at: DateTime<Utc>,
+    /// Run duration in milliseconds.
+    pub duration_ms: u64,
+    /// Number of DB orders compared against the cache.
+    pub db_orders_checked: usize,
+    /// Number of cache entries checked for existence in the DB.
+    pub cache_entries_checked: usize,
+    /// Order ids present in the DB but not in the cache.
+    pub missing: Vec<String>,
+    /// Order ids whose cached content differs from the DB.
+    pub stale: Vec<String>,
+    /// Order ids present in the cache but not in the DB.
+    pub orphaned: Vec<String>,
+    /// Order ids that could not be loaded from the DB.
+    pub failed: Vec<String>,
+    /// Order ids not repaired because the entry or the order changed during this run.
+    pub skipped: Vec<String>,
+    /// Number of cache entries fixed during this run.
+    pub repaired: usize,
+}
+
+/// Prometheus metrics describing consistency runs.
+struct ConsistencyMetrics {
+    runs_total: IntCounter,
+    entries: IntGaugeVec,
+    repairs_total: IntCounterVec,
+    last_run_timestamp: IntGauge,
+}
+
+impl ConsistencyMetrics {
+    fn new(registry: &Registry) -> prometheus::Result<Self> {
+        let runs_total = IntCounter::new(
+            "cache_consistency_runs_total",
+            "Total number of cache consistency runs",
+        )?;
+        let entries = IntGaugeVec::new(
+            Opts::new(
+                "cache_consistency_entries",
+                "Inconsistent cache entries found by the last consistency run",
+            ),
+            &["kind"],
+        )?;
+        let repairs_total = IntCounterVec::new(
+            Opts::new(
+                "cache_consistency_repairs_total",
+                "Total number of cache entries repaired by consistency runs",
+            ),
+            &["kind"],
+        )?;
+        let last_run_timestamp = IntGauge::new(
+            "cache_consistency_last_run_timestamp_seconds",
+            "Unix timestamp of the last completed consistency run",
+        )?;
+
+        registry.register(Box::new(runs_total.clone()))?;
+        registry.register(Box::new(entries.clone()))?;
+        registry.register(Box::new(repairs_total.clone()))?;
+        registry.register(Box::new(last_run_timestamp.clone()))?;
+
+        Ok(Self {
+            runs_total,
+            entries,
+            repairs_total,
+            last_run_timestamp,
+        })
+    }
+
+    fn record(&self, report: &ConsistencyReport) {
+        self.runs_total.inc();
+        self.entries
+            .with_label_values(&["missing"])
+            .set(report.missing.len() as i64);
+        self.entries
+            .with_label_values(&["stale"])
+            .set(report.stale.len() as i64);
+        self.entries
+            .with_label_values(&["orphaned"])
+            .set(report.orphaned.len() as i64);
+        self.entries
+            .with_label_values(&["failed"])
+            .set(report.failed.len() as i64);
+        self.last_run_timestamp.set(Utc::now().timestamp());
+    }
+}
+
+/// Compares [`OrderCache`] entries with the database and optionally repairs them.
+pub struct ConsistencyChecker {
+    pool: Pool,
+    cache: Arc<OrderCache>,
+    order_service: Arc<dyn OrderService>,
+    metrics: ConsistencyMetrics,
+}
+
+impl ConsistencyChecker {
+    /// Creates a new checker and registers its metrics in `registry`.
+    ///
+    /// # Errors
+    /// Returns an error if the metrics cannot be registered (e.g. registered twice).
+    pub fn new(
+        pool: Pool,
+        cache: Arc<OrderCache>,
+        order_service: Arc<dyn OrderService>,
+        registry: &Registry,
+    ) -> prometheus::Result<Self> {
+        Ok(Self {
+            pool,
+            cache,
+            order_service,
+            metrics: ConsistencyMetrics::new(registry)?,
+        })
+    }
+
+    /// Runs a single consistency check.
+    ///
+    /// With `repair` set, missing and stale entries are replaced with the DB copy
+    /// and orphaned entries are evicted from the cache. Orders keep being saved
+    /// while the check runs, so every repair is based on a fresh read: the DB copy
+    /// is re-read right before it is cached, orphans are re-checked against the DB
+    /// before they are reported, and an entry that changed since it was compared is
+    /// left alone and reported as skipped.
+    ///
+    /// # Errors
+    /// Returns an error if the order ids cannot be listed from the database.
+    pub async fn run(&self, mode: ScanMode, repair: bool) -> Result<ConsistencyReport> {
+        let started_at = Utc::now();
+        let start = Instant::now();
+
+        // The cache is listed before the DB: an order saved in between is then in
+        // the DB listing, instead of looking like a cached id missing from the DB.
+        let conn = self.pool.get().await?;
+        let (db_uids, cache_uids) = match mode {
+            ScanMode::Full => {
+                let cache_uids = self.cache.keys().await;
+                let rows = conn.query("SELECT order_uid FROM orders", &[]).await?;
+                let db_uids: Vec<String> = rows.iter().map(|r| r.get("order_uid")).collect();
+                (db_uids, cache_uids)
+            }
+            ScanMode::Sample(size) => {
+                let cache_uids: Vec<String> = self
+                    .cache
+                    .keys()
+                    .await
+                    .choose_multiple(&mut rand::thread_rng(), size)
+                    .cloned()
+                    .collect();
+                let limit = size as i64;
+                let rows = conn
+                    .query(
+                        "SELECT order_uid FROM orders ORDER BY random() LIMIT $1",
+                        &[&limit],
+                    )
+                    .await?;
+                let db_uids: Vec<String> = rows.iter().map(|r| r.get("order_uid")).collect();
+                (db_uids, cache_uids)
+            }
+        };
+
+        // Orphans: cached ids that do not exist in the orders table.
+        let existing: HashSet<String> = match mode {
+            ScanMode::Full => db_uids.iter().cloned().collect(),
+            ScanMode::Sample(_) => existing_uids(&conn, &cache_uids).await?,
+        };
+
+        let mut report = ConsistencyReport {
+            mode: match mode {
+                ScanMode::Full => "full",
+                ScanMode::Sample(_) => "sample",
+            },
+            repair,
+            started_at,
+            duration_ms: 0,
+            db_orders_checked: db_uids.len(),
+            cache_entries_checked: cache_uids.len(),
+            missing: Vec::new(),
+            stale: Vec::new(),
+            orphaned: Vec::new(),
+            failed: Vec::new(),
+            skipped: Vec::new(),
+            repaired: 0,
+        };
+
+        for uid in &db_uids {
+            let db_order = match self.order_service.get_order_by_id(uid).await {
+                Ok(order) => order,
+                // Deleted between listing and loading; the orphan pass will catch it next time.
+                Err(ServiceError::Db(RepositoryError::NotFound)) => continue,
+                Err(e) => {
+                    warn!("Consistency check failed to load order {}: {}", uid, e);
+                    report.failed.push(uid.clone());
+                    continue;
+                }
+            };
+
+            let cached_hash = self
+                .cache
+                .get(uid)
+                .await
+                .map(|cached| content_hash(&cached));
+            let kind = match &cached_hash {
+                None => {
+                    report.missing.push(uid.clone());
+                    "missing"
+                }
+                Some(hash) if *hash != content_hash(&db_order) => {
+                    report.stale.push(uid.clone());
+                    "stale"
+                }
+                Some(_) => continue,
+            };
+
+            if repair {
+                self.repair_entry(uid, cached_hash.as_deref(), kind, &mut report)
+                    .await;
+            }
+        }
+
+        // Seen now, so that an entry replaced from here on is not evicted
+        let mut candidates = Vec::new();
+        for uid in cache_uids {
+            if !existing.contains(&uid)
+                && let Some(cached) = self.cache.get(&uid).await
+            {
+                candidates.push((uid, content_hash(&cached)));
+            }
+        }
+        if !candidates.is_empty() {
+            // Re-checked, since an order saved after the listing is not an orphan
+            let uids: Vec<String> = candidates.iter().map(|(uid, _)| uid.clone()).collect();
+            let saved_since = existing_uids(&conn, &uids).await?;
+            for (uid, hash) in candidates {
+                if saved_since.contains(&uid) {
+                    continue;
+                }
+                if repair {
+                    if self.cache.remove_if_unchanged(&uid, &hash).await {
+                        self.metrics
+                            .repairs_total
+                            .with_label_values(&["orphaned"])
+                            .inc();
+                        report.repaired += 1;
+                    } else {
+                        report.skipped.push(uid.clone());
+                    }
+                }
+                report.orphaned.push(uid);
+            }
+        }
+        drop(conn);
+
+        report.duration_ms = start.elapsed().as_millis() as u64;
+        self.metrics.record(&report);
+
+        info!(
+            "Cache consistency run ({}) finished: {} missing, {} stale, {} orphaned, {} failed, {} skipped, {} repaired",
+            report.mode,
+            report.missing.len(),
+            report.stale.len(),
+            report.orphaned.len(),
+            report.failed.len(),
+            report.skipped.len(),
+            report.repaired
+        );
+        Ok(report)
+    }
+
+    /// Replaces a missing or stale entry with a fresh copy from the DB.
+    ///
+    /// `seen` is the hash of the entry when it was compared, `None` if it was
+    /// missing. The DB copy compared against may be older than a save completed
+    /// since, so the order is re-read, and only cached if the entry is unchanged.
+    async fn repair_entry(
+        &self,
+        uid: &str,
+        seen: Option<&str>,
+        kind: &str,
+        report: &mut ConsistencyReport,
+    ) {
+        let db_order = match self.order_service.get_order_by_id(uid).await {
+            Ok(order) => order,
+            Err(ServiceError::Db(RepositoryError::NotFound)) => {
+                report.skipped.push(uid.to_string());
+                return;
+            }
+            Err(e) => {
+                warn!("Consistency repair failed to reload order {}: {}", uid, e);
+                report.failed.push(uid.to_string());
+                return;
+            }
+        };
+        if self.cache.set_if_unchanged(db_order, seen).await {
+            self.metrics.repairs_total.with_label_values(&[kind]).inc();
+            report.repaired += 1;
+        } else {
+            report.skipped.push(uid.to_string());
+        }
+    }
+
+    /// Spawns a background task that runs the check every `interval`.
+    ///
+    /// The first run happens one `interval` after the call. The task runs until it
+    /// is aborted or the runtime shuts down.
+    pub fn spawn_periodic(
+        self: Arc<Self>,
+        interval: Duration,
+        mode: ScanMode,
+        repair: bool,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker =
+                tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = self.run(mode, repair).await {
+                    error!("Cache consistency run failed: {}", e);
+                }
+            }
+        })
+    }
+}
+
+/// Returns which of `uids` exist in the orders table.
+async fn existing_uids(conn: &DbConn, uids: &[String]) -> Result<HashSet<String>> {
+    Ok(conn
+        .query(
+            "SELECT order_uid FROM orders WHERE order_uid = ANY($1)",
+            &[&uids],
+        )
+        .await?
+        .iter()
+        .map(|r| r.get("order_uid"))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_content_hash_ignores_sub_microsecond_precision() {
+        let mut order = Order {
+            order_uid: "order123".to_string(),
+            date_created: Utc.timestamp_opt(1_700_000_000, 123_456_789).unwrap(),
+            ..Default::default()
+        };
+        let from_kafka = content_hash(&order);
+
+        order.date_created = Utc.timestamp_opt(1_700_000_000, 123_456_000).unwrap();
+        assert_eq!(content_hash(&order), from_kafka);
+
+        order.locale = "ru".to_string();
+        assert_ne!(content_hash(&order), from_kafka);
+    }
+}
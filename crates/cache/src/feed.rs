@@ -0,0 +1,148 @@
+//! Live feed of newly ingested orders.
+//!
+//! The consumer publishes every order it saves and caches; subscribers receive
+//! them over a bounded broadcast channel. The most recent events are also kept in
+//! a ring buffer, so a reconnecting subscriber can resume after the last event it
+//! saw. Event ids start from the feed's creation time in microseconds, so they
+//! keep increasing across restarts and a stale id is detected as a gap rather
+//! than mistaken for a recent one.
+
+use model::Order;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+
+/// An order published to the feed.
+#[derive(Debug, Clone)]
+pub struct FeedEvent {
+    /// Position in the feed; strictly increasing.
+    pub id: u64,
+    pub order: Arc<Order>,
+}
+
+/// Where a subscription starts.
+#[derive(Debug)]
+pub struct Subscription {
+    /// Buffered events after the requested id, oldest first.
+    pub backlog: Vec<FeedEvent>,
+    /// Whether events after the requested id were already evicted from the buffer,
+    /// so the backlog is incomplete.
+    pub gap: bool,
+    /// Events published from now on.
+    pub receiver: broadcast::Receiver<FeedEvent>,
+}
+
+struct Recent {
+    next_id: u64,
+    events: VecDeque<FeedEvent>,
+}
+
+/// Broadcast feed of ingested orders with a bounded replay buffer.
+pub struct OrderFeed {
+    sender: broadcast::Sender<FeedEvent>,
+    recent: Mutex<Recent>,
+    capacity: usize,
+}
+
+impl OrderFeed {
+    /// Creates a feed keeping the last `capacity` events for replay.
+    ///
+    /// Subscribers falling more than `capacity` events behind lose the oldest ones.
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        let start = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(1, |d| d.as_micros() as u64);
+        Self {
+            sender: broadcast::channel(capacity).0,
+            recent: Mutex::new(Recent {
+                next_id: start,
+                events: VecDeque::with_capacity(capacity),
+            }),
+            capacity,
+        }
+    }
+
+    /// Publishes a saved order to all subscribers.
+    pub fn publish(&self, order: Order) {
+        let mut recent = self.recent.lock().unwrap_or_else(PoisonError::into_inner);
+        let event = FeedEvent {
+            id: recent.next_id,
+            order: Arc::new(order),
+        };
+        recent.next_id += 1;
+        if recent.events.len() == self.capacity {
+            recent.events.pop_front();
+        }
+        recent.events.push_back(event.clone());
+        // Sending under the lock keeps subscriptions free of gaps and duplicates;
+        // without subscribers the event is only buffered
+        let _ = self.sender.send(event);
+    }
+
+    /// Subscribes to events after `last_id`, or to new events only without one.
+    pub fn subscribe(&self, last_id: Option<u64>) -> Subscription {
+        let recent = self.recent.lock().unwrap_or_else(PoisonError::into_inner);
+        let receiver = self.sender.subscribe();
+        let Some(last_id) = last_id else {
+            return Subscription {
+                backlog: Vec::new(),
+                gap: false,
+                receiver,
+            };
+        };
+        let oldest = recent.events.front().map_or(recent.next_id, |e| e.id);
+        Subscription {
+            backlog: recent
+                .events
+                .iter()
+                .filter(|e| e.id > last_id)
+                .cloned()
+                .collect(),
+            gap: last_id.saturating_add(1) < oldest,
+            receiver,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(uid: &str) -> Order {
+        Order {
+            order_uid: uid.into(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resume_from_buffer() {
+        let feed = OrderFeed::new(2);
+        let mut live = feed.subscribe(None);
+        feed.publish(order("a"));
+        let first = live.receiver.recv().await.unwrap();
+        assert_eq!(first.order.order_uid, "a");
+        feed.publish(order("b"));
+        feed.publish(order("c"));
+
+        // "a" was evicted, so resuming after it is complete but after an older id is not
+        let resumed = feed.subscribe(Some(first.id));
+        assert!(!resumed.gap);
+        let uids: Vec<_> = resumed
+            .backlog
+            .iter()
+            .map(|e| e.order.order_uid.as_str())
+            .collect();
+        assert_eq!(uids, ["b", "c"]);
+        assert!(feed.subscribe(Some(first.id - 1)).gap);
+
+        // A subscriber more than `capacity` events behind has lagged
+        feed.publish(order("d"));
+        assert!(matches!(
+            live.receiver.recv().await,
+            Err(broadcast::error::RecvError::Lagged(_))
+        ));
+    }
+}
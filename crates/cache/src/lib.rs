@@ -7,16 +7,40 @@
 //! ## Features
 //! - Thread-safe, async-first API
 //! - Integration with repositories for population from DB
+//! - Read-through lookups with single-flight deduplication of concurrent misses
+//! - Short-lived negative caching of order ids confirmed to be missing
+//! - Consistency checking and repair against the database
+//! - A live feed of newly ingested orders with a replay buffer
 //! - Unit tests for correctness and concurrency
 
+mod consistency;
+mod feed;
+mod singleflight;
+
+pub use consistency::{ConsistencyChecker, ConsistencyReport, ScanMode, content_hash};
+pub use feed::{FeedEvent, OrderFeed, Subscription};
+pub use singleflight::SingleFlight;
+
 use anyhow::Result;
 use deadpool_postgres::{Object as DbConn, Pool};
 use model::Order;
-use repository::{DeliveriesRepository, ItemsRepository, OrdersRepository, PaymentsRepository};
+use repository::{
+    DeliveriesRepository, ItemsRepository, OrdersRepository, PaymentsRepository, RepositoryError,
+};
+use service::{OrderService, ServiceError};
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 
+/// Upper bound on the number of remembered not-found ids.
+///
+/// Keeps an enumeration scan from growing the negative cache without limit.
+const NEGATIVE_CACHE_CAPACITY: usize = 10_000;
+
+/// Result of a read-through load, shared between all callers waiting on the same key.
+pub type LoadResult = std::result::Result<Option<Order>, Arc<ServiceError>>;
+
 /// Thread-safe, in-memory cache for orders, keyed by order UID.
 ///
 /// The cache uses [`tokio::sync::RwLock`] to allow concurrent reads and exclusive writes.
@@ -24,6 +48,11 @@ use tokio::sync::RwLock;
 #[derive(Debug, Default)]
 pub struct OrderCache {
     inner: Arc<RwLock<HashMap<String, Order>>>,
+    /// Order ids confirmed missing in the DB, with the instant they expire.
+    negative: RwLock<HashMap<String, Instant>>,
+    /// How long a not-found id is remembered; zero disables negative caching.
+    negative_ttl: Duration,
+    loads: SingleFlight<LoadResult>,
 }
 
 impl OrderCache {
@@ -31,6 +60,17 @@ impl OrderCache {
     pub fn new() -> Self {
         Self {
             inner: Arc::new(RwLock::new(HashMap::new())),
+            ..Default::default()
+        }
+    }
+
+    /// Creates a new, empty order cache that remembers not-found ids for `ttl`.
+    ///
+    /// A zero `ttl` disables negative caching.
+    pub fn with_negative_ttl(ttl: Duration) -> Self {
+        Self {
+            negative_ttl: ttl,
+            ..Self::new()
         }
     }
 
@@ -90,10 +130,123 @@ impl OrderCache {
     ///
     /// If an order with this UID already exists, it is overwritten.
     pub async fn set(&self, order: Order) {
+        if self.negative_ttl > Duration::ZERO {
+            self.negative.write().await.remove(&order.order_uid);
+        }
         let mut map = self.inner.write().await;
         map.insert(order.order_uid.clone(), order);
     }
 
+    /// Insert or update an order, but only if its entry is still the one last seen.
+    ///
+    /// `expected` is the [`content_hash`] of the entry as last seen, or `None` if
+    /// there was no entry. Returns `false`, leaving the cache unchanged, if the
+    /// entry has changed since, so a write based on an older read cannot
+    /// overwrite a newer one.
+    pub async fn set_if_unchanged(&self, order: Order, expected: Option<&str>) -> bool {
+        let mut map = self.inner.write().await;
+        let current = map.get(&order.order_uid).map(content_hash);
+        if current.as_deref() != expected {
+            return false;
+        }
+        if self.negative_ttl > Duration::ZERO {
+            self.negative.write().await.remove(&order.order_uid);
+        }
+        map.insert(order.order_uid.clone(), order);
+        true
+    }
+
+    /// Get an order from the cache, loading it through `service` on a miss.
+    ///
+    /// Concurrent misses for the same `order_uid` share a single
+    /// [`OrderService::get_order_by_id`] call. A loaded order is stored in the cache.
+    /// Ids the service reports as not found are remembered for the negative TTL,
+    /// during which lookups return `Ok(None)` without touching the database.
+    ///
+    /// # Errors
+    /// Returns the service error (shared between all waiting callers) if the load fails
+    /// for any reason other than the order not existing.
+    pub async fn get_or_load<S>(&self, order_uid: &str, service: &S) -> LoadResult
+    where
+        S: OrderService + ?Sized,
+    {
+        if let Some(order) = self.get(order_uid).await {
+            return Ok(Some(order));
+        }
+        if self.is_known_missing(order_uid).await {
+            return Ok(None);
+        }
+
+        self.loads
+            .run(order_uid, || async {
+                match service.get_order_by_id(order_uid).await {
+                    Ok(order) => {
+                        self.set(order.clone()).await;
+                        Ok(Some(order))
+                    }
+                    Err(ServiceError::Db(RepositoryError::NotFound)) => {
+                        self.remember_missing(order_uid).await;
+                        Ok(None)
+                    }
+                    Err(e) => Err(Arc::new(e)),
+                }
+            })
+            .await
+    }
+
+    /// Returns `true` if `order_uid` was recently confirmed missing and the entry has not expired.
+    async fn is_known_missing(&self, order_uid: &str) -> bool {
+        if self.negative_ttl.is_zero() {
+            return false;
+        }
+        let negative = self.negative.read().await;
+        negative
+            .get(order_uid)
+            .is_some_and(|expires_at| *expires_at > Instant::now())
+    }
+
+    /// Records `order_uid` as missing for the negative TTL.
+    async fn remember_missing(&self, order_uid: &str) {
+        if self.negative_ttl.is_zero() {
+            return;
+        }
+        let now = Instant::now();
+        let mut negative = self.negative.write().await;
+        if negative.len() >= NEGATIVE_CACHE_CAPACITY {
+            negative.retain(|_, expires_at| *expires_at > now);
+            if negative.len() >= NEGATIVE_CACHE_CAPACITY {
+                return;
+            }
+        }
+        negative.insert(order_uid.to_string(), now + self.negative_ttl);
+    }
+
+    /// Remove an order from the cache.
+    ///
+    /// Returns the removed order, if it was cached.
+    pub async fn remove(&self, order_uid: &str) -> Option<Order> {
+        let mut map = self.inner.write().await;
+        map.remove(order_uid)
+    }
+
+    /// Remove an order from the cache, but only if its [`content_hash`] is still `expected`.
+    ///
+    /// Returns `false`, leaving the cache unchanged, if the entry is gone or has changed.
+    pub async fn remove_if_unchanged(&self, order_uid: &str, expected: &str) -> bool {
+        let mut map = self.inner.write().await;
+        if map.get(order_uid).map(content_hash).as_deref() != Some(expected) {
+            return false;
+        }
+        map.remove(order_uid);
+        true
+    }
+
+    /// Get the UIDs of all cached orders.
+    pub async fn keys(&self) -> Vec<String> {
+        let map = self.inner.read().await;
+        map.keys().cloned().collect()
+    }
+
     /// Get all orders from the cache.
     ///
     /// Returns a vector of all orders in the cache.
@@ -141,7 +294,80 @@ async fn get_all_order_uids(conn: &DbConn) -> Result<Vec<String>> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use async_trait::async_trait;
     use model::{Delivery, Item, Order, Payment};
+    use service::{OrderPredicate, SaveOptions, SaveOutcome};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Order service stub that counts lookups and knows a fixed set of orders.
+    struct CountingService {
+        orders: HashMap<String, Order>,
+        calls: AtomicUsize,
+    }
+
+    impl CountingService {
+        fn new(orders: &[Order]) -> Self {
+            Self {
+                orders: orders
+                    .iter()
+                    .map(|o| (o.order_uid.clone(), o.clone()))
+                    .collect(),
+                calls: AtomicUsize::new(0),
+            }
+        }
+
+        fn calls(&self) -> usize {
+            self.calls.load(Ordering::SeqCst)
+        }
+    }
+
+    /// Error returned by the stub's writes, which the cache never calls.
+    fn unused() -> ServiceError {
+        ServiceError::Unexpected("not used by cache tests".into())
+    }
+
+    #[async_trait]
+    impl OrderService for CountingService {
+        async fn save_order(&self, _order: &Order) -> std::result::Result<(), ServiceError> {
+            Err(unused())
+        }
+
+        async fn save_order_with(
+            &self,
+            _order: &Order,
+            _options: &SaveOptions,
+        ) -> std::result::Result<SaveOutcome, ServiceError> {
+            Err(unused())
+        }
+
+        async fn update_order_if(
+            &self,
+            _order: &Order,
+            _is_current: &OrderPredicate<'_>,
+        ) -> std::result::Result<bool, ServiceError> {
+            Err(unused())
+        }
+
+        async fn save_order_batch(
+            &self,
+            _orders: &[Order],
+        ) -> std::result::Result<Vec<std::result::Result<(), ServiceError>>, ServiceError> {
+            Err(unused())
+        }
+
+        async fn get_order_by_id(
+            &self,
+            order_uid: &str,
+        ) -> std::result::Result<Order, ServiceError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            // Give concurrent callers a chance to pile up on the same key.
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            self.orders
+                .get(order_uid)
+                .cloned()
+                .ok_or(ServiceError::Db(RepositoryError::NotFound))
+        }
+    }
 
     fn sample_order(uid: &str) -> Order {
         Order {
@@ -190,6 +416,7 @@ mod tests {
             sm_id: 1,
             date_created: chrono::Utc::now(),
             oof_shard: "oof".to_string(),
+            source_topic: None,
         }
     }
 
@@ -221,4 +448,136 @@ mod tests {
         let got = cache.get("order123").await.unwrap();
         assert_eq!(got.locale, "ru");
     }
+
+    #[tokio::test]
+    async fn test_remove_order() {
+        let cache = OrderCache::new();
+        cache.set(sample_order("order123")).await;
+        assert_eq!(cache.keys().await, vec!["order123".to_string()]);
+
+        assert!(cache.remove("order123").await.is_some());
+        assert!(cache.get("order123").await.is_none());
+        assert!(cache.remove("order123").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_set_if_unchanged_keeps_newer_entry() {
+        let cache = OrderCache::new();
+        let seen = sample_order("order123");
+        assert!(cache.set_if_unchanged(seen.clone(), None).await);
+        let seen_hash = content_hash(&seen);
+
+        // Updated by someone else after the entry was seen
+        let mut newer = seen.clone();
+        newer.locale = "ru".to_string();
+        cache.set(newer).await;
+
+        let mut repair = seen;
+        repair.locale = "de".to_string();
+        assert!(
+            !cache
+                .set_if_unchanged(repair.clone(), Some(&seen_hash))
+                .await
+        );
+        assert!(!cache.set_if_unchanged(repair, None).await);
+        assert_eq!(cache.get("order123").await.unwrap().locale, "ru");
+    }
+
+    #[tokio::test]
+    async fn test_remove_if_unchanged_keeps_newer_entry() {
+        let cache = OrderCache::new();
+        let mut order = sample_order("order123");
+        cache.set(order.clone()).await;
+        let seen_hash = content_hash(&order);
+
+        order.locale = "ru".to_string();
+        cache.set(order.clone()).await;
+        assert!(!cache.remove_if_unchanged("order123", &seen_hash).await);
+        assert!(cache.get("order123").await.is_some());
+
+        assert!(
+            cache
+                .remove_if_unchanged("order123", &content_hash(&order))
+                .await
+        );
+        assert!(cache.get("order123").await.is_none());
+        assert!(!cache.remove_if_unchanged("order123", &seen_hash).await);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_misses_share_one_load() {
+        let cache = Arc::new(OrderCache::new());
+        let service = Arc::new(CountingService::new(&[sample_order("order123")]));
+
+        let mut handles = Vec::new();
+        for _ in 0..10 {
+            let cache = cache.clone();
+            let service = service.clone();
+            handles.push(tokio::spawn(async move {
+                cache.get_or_load("order123", service.as_ref()).await
+            }));
+        }
+        for handle in handles {
+            let got = handle.await.unwrap().unwrap();
+            assert_eq!(got.unwrap().order_uid, "order123");
+        }
+
+        assert_eq!(service.calls(), 1);
+        assert_eq!(cache.loads.in_flight(), 0);
+        assert!(cache.get("order123").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_negative_cache_absorbs_repeated_misses() {
+        let cache = OrderCache::with_negative_ttl(Duration::from_secs(60));
+        let service = CountingService::new(&[]);
+
+        assert!(
+            cache
+                .get_or_load("missing", &service)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            cache
+                .get_or_load("missing", &service)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(service.calls(), 1);
+
+        // An order arriving later (e.g. from Kafka) must become visible immediately.
+        cache.set(sample_order("missing")).await;
+        assert!(
+            cache
+                .get_or_load("missing", &service)
+                .await
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_negative_cache_disabled_by_default() {
+        let cache = OrderCache::new();
+        let service = CountingService::new(&[]);
+
+        assert!(
+            cache
+                .get_or_load("missing", &service)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            cache
+                .get_or_load("missing", &service)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(service.calls(), 2);
+    }
 }
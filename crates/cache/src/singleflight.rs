@@ -0,0 +1,72 @@
+//! Single-flight deduplication of concurrent loads.
+//!
+//! When several tasks miss the cache for the same key at the same time, only the
+//! first of them runs the actual load; the others wait for that load and receive a
+//! clone of its result instead of issuing duplicate database queries.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex, PoisonError};
+use tokio::sync::OnceCell;
+
+/// Deduplicates concurrent loads that share the same string key.
+///
+/// A slot is created for a key when the first caller arrives and removed as soon
+/// as its load completes, so results are never reused by later, non-overlapping
+/// calls. If the task running the load is cancelled, one of the waiting tasks
+/// takes over and runs its own loader.
+#[derive(Debug)]
+pub struct SingleFlight<T> {
+    calls: Mutex<HashMap<String, Arc<OnceCell<T>>>>,
+}
+
+impl<T> Default for SingleFlight<T> {
+    fn default() -> Self {
+        Self {
+            calls: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<T: Clone> SingleFlight<T> {
+    /// Creates an empty single-flight group.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `load` for `key`, unless a load for the same key is already in flight,
+    /// in which case the caller waits for that load and returns its result.
+    pub async fn run<F, Fut>(&self, key: &str, load: F) -> T
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let cell = self
+            .calls
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(key.to_string())
+            .or_default()
+            .clone();
+
+        let value = cell.get_or_init(load).await.clone();
+
+        // Whoever finishes first clears the slot, so the next miss starts a fresh load.
+        let mut calls = self.calls.lock().unwrap_or_else(PoisonError::into_inner);
+        if calls
+            .get(key)
+            .is_some_and(|current| Arc::ptr_eq(current, &cell))
+        {
+            calls.remove(key);
+        }
+        value
+    }
+
+    /// Returns the number of keys with a load currently in flight.
+    pub fn in_flight(&self) -> usize {
+        self.calls
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+}
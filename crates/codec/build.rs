@@ -0,0 +1,19 @@
+//! Generates the protobuf message types from `schemas/order.proto`.
+//!
+//! Uses the `protoc` shipped with `protoc-bin-vendored`, so building the
+//! workspace does not need one installed.
+
+use std::error::Error;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let proto = "../../schemas/order.proto";
+    println!("cargo:rerun-if-changed={proto}");
+
+    prost_build::Config::new()
+        .protoc_executable(protoc_bin_vendored::protoc_bin_path()?)
+        .compile_protos(
+            &[proto],
+            &["../../schemas".into(), protoc_bin_vendored::include_path()?],
+        )?;
+    Ok(())
+}
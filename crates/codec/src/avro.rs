@@ -0,0 +1,209 @@
+//! Avro binary encoding of orders.
+//!
+//! Encoding, decoding and schema resolution are done by the `apache-avro` crate,
+//! which lets orders written with an older or newer compatible schema be read as
+//! the order schema checked into the repository (`schemas/order.avsc`). This
+//! module maps orders to and from generic Avro values of that schema.
+
+mod order;
+
+pub use apache_avro::Schema;
+pub use apache_avro::types::Value;
+pub use order::{order_from_value, order_to_value};
+
+use apache_avro::schema_compatibility::SchemaCompatibility;
+use model::Order;
+use std::sync::LazyLock;
+
+/// The order schema checked into the repository.
+pub const ORDER_SCHEMA_JSON: &str = include_str!("../../../schemas/order.avsc");
+
+/// Largest string, byte array or array block the decoder allocates for.
+///
+/// Lengths and block counts come from the message, so the decoder would
+/// otherwise reserve memory for whatever a corrupt message claims. Orders are far
+/// smaller than a megabyte, Kafka's default message size limit.
+pub const MAX_ALLOCATION: usize = 1024 * 1024;
+
+static ORDER_SCHEMA: LazyLock<Schema> = LazyLock::new(|| {
+    // The limit can only be set once, before the first decode
+    apache_avro::util::max_allocation_bytes(MAX_ALLOCATION);
+    Schema::parse_str(ORDER_SCHEMA_JSON).expect("schemas/order.avsc is valid")
+});
+
+/// Errors from encoding or decoding orders.
+#[derive(Debug, thiserror::Error)]
+pub enum AvroError {
+    #[error("Failed to decode Avro data: {0}")]
+    Decode(String),
+    #[error("Failed to encode Avro data: {0}")]
+    Encode(String),
+    #[error("Incompatible Avro schema: {0}")]
+    Incompatible(String),
+}
+
+/// Returns the parsed order schema.
+pub fn order_schema() -> &'static Schema {
+    &ORDER_SCHEMA
+}
+
+/// Checks that data written with the `writer` schema can be read as `reader`.
+///
+/// # Errors
+/// Returns [`AvroError::Incompatible`] naming the first mismatch found.
+pub fn check_compatible(reader: &Schema, writer: &Schema) -> Result<(), AvroError> {
+    SchemaCompatibility::can_read(writer, reader)
+        .map_err(|e| AvroError::Incompatible(e.to_string()))
+}
+
+/// Encodes an order with the order schema.
+///
+/// # Errors
+/// Returns [`AvroError::Encode`] if the order cannot be encoded.
+pub fn encode_order(order: &Order) -> Result<Vec<u8>, AvroError> {
+    apache_avro::to_avro_datum(order_schema(), order_to_value(order))
+        .map_err(|e| AvroError::Encode(e.to_string()))
+}
+
+/// Decodes an order written with the `writer` schema.
+///
+/// The data is decoded with the writer's schema and then resolved to the order
+/// schema, so fields added by the writer are ignored and fields it lacks take
+/// their defaults.
+///
+/// # Errors
+/// Returns [`AvroError::Decode`] for malformed data or data that cannot be read
+/// as the order schema.
+pub fn decode_order(mut bytes: &[u8], writer: &Schema) -> Result<Order, AvroError> {
+    let reader = order_schema();
+    let value = apache_avro::from_avro_datum(writer, &mut bytes, Some(reader))
+        .map_err(|e| AvroError::Decode(e.to_string()))?;
+    if !bytes.is_empty() {
+        return Err(AvroError::Decode(format!(
+            "{} trailing bytes after the order",
+            bytes.len()
+        )));
+    }
+    order_from_value(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use model::{Delivery, Item, Payment};
+
+    fn sample_order() -> Order {
+        Order {
+            order_uid: "b563feb7b2b84b6test".into(),
+            track_number: "WBILMTESTTRACK".into(),
+            entry: "WBIL".into(),
+            delivery: Delivery {
+                name: "Test Testov".into(),
+                city: "Kiryat Mozkin".into(),
+                ..Default::default()
+            },
+            payment: Payment {
+                transaction: "b563feb7b2b84b6test".into(),
+                currency: "USD".into(),
+                amount: 1817,
+                payment_dt: 1637907727,
+                delivery_cost: 1500,
+                goods_total: 317,
+                ..Default::default()
+            },
+            items: vec![Item {
+                chrt_id: 9934930,
+                track_number: "WBILMTESTTRACK".into(),
+                price: 453,
+                name: "Mascaras".into(),
+                total_price: 317,
+                ..Default::default()
+            }],
+            locale: "en".into(),
+            customer_id: "test".into(),
+            sm_id: 99,
+            date_created: Utc.with_ymd_and_hms(2021, 11, 26, 6, 22, 19).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_order_round_trip() {
+        let order = sample_order();
+        let bytes = encode_order(&order).unwrap();
+        assert_eq!(decode_order(&bytes, order_schema()).unwrap(), order);
+    }
+
+    #[test]
+    fn test_decode_with_evolved_writer_schema() {
+        // The writer added a field and dropped `internal_signature`, which has a default.
+        let mut json: serde_json::Value = serde_json::from_str(ORDER_SCHEMA_JSON).unwrap();
+        let fields = json["fields"].as_array_mut().unwrap();
+        fields.retain(|f| f["name"] != "internal_signature");
+        fields.push(serde_json::json!({"name": "channel", "type": ["null", "string"]}));
+        let writer = Schema::parse_str(&json.to_string()).unwrap();
+        check_compatible(order_schema(), &writer).unwrap();
+
+        let mut order = sample_order();
+        order.internal_signature = "signed".into();
+        let Value::Record(mut fields) = order_to_value(&order) else {
+            unreachable!()
+        };
+        fields.retain(|(name, _)| name != "internal_signature");
+        let channel = Value::Union(1, Box::new(Value::String("web".into())));
+        fields.push(("channel".into(), channel));
+        let bytes = apache_avro::to_avro_datum(&writer, Value::Record(fields)).unwrap();
+
+        order.internal_signature = String::new();
+        assert_eq!(decode_order(&bytes, &writer).unwrap(), order);
+    }
+
+    #[test]
+    fn test_incompatible_writer_schema() {
+        let mut json: serde_json::Value = serde_json::from_str(ORDER_SCHEMA_JSON).unwrap();
+        let fields = json["fields"].as_array_mut().unwrap();
+        fields.retain(|f| f["name"] != "customer_id");
+        let writer = Schema::parse_str(&json.to_string()).unwrap();
+
+        let err = check_compatible(order_schema(), &writer).unwrap_err();
+        assert!(err.to_string().contains("customer_id"), "{err}");
+    }
+
+    #[test]
+    fn test_oversized_block_count_is_rejected() {
+        // An order whose items array claims a block of 2^30 items
+        let writer = Schema::parse_str(
+            r#"{"type": "record", "name": "Order", "namespace": "shoppingcart.orders",
+                "fields": [{"name": "items", "type": {"type": "array", "items": "int"}}]}"#,
+        )
+        .unwrap();
+        let count = 1i64 << 30;
+        let mut zigzag = ((count << 1) ^ (count >> 63)) as u64;
+        let mut bytes = Vec::new();
+        while zigzag >= 0x80 {
+            bytes.push((zigzag as u8) | 0x80);
+            zigzag >>= 7;
+        }
+        bytes.push(zigzag as u8);
+
+        let err = decode_order(&bytes, &writer).unwrap_err();
+        assert!(matches!(err, AvroError::Decode(_)), "{err}");
+    }
+
+    #[test]
+    fn test_corrupt_data_is_rejected() {
+        let bytes = encode_order(&sample_order()).unwrap();
+        for len in 0..bytes.len() {
+            assert!(decode_order(&bytes[..len], order_schema()).is_err());
+        }
+        // Flipping any byte must fail or decode something, never panic
+        for i in 0..bytes.len() {
+            for flip in [0x01, 0x80, 0xff] {
+                let mut corrupt = bytes.clone();
+                corrupt[i] ^= flip;
+                let _ = decode_order(&corrupt, order_schema());
+            }
+        }
+    }
+}
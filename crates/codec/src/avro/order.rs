@@ -0,0 +1,217 @@
+//! Mapping between [`Order`] and generic Avro values of the order schema.
+
+use super::AvroError;
+use apache_avro::types::Value;
+use chrono::DateTime;
+use model::{Delivery, Item, Order, Payment};
+
+/// Converts an order into a value of the order schema.
+pub fn order_to_value(order: &Order) -> Value {
+    let d = &order.delivery;
+    let p = &order.payment;
+    Value::Record(vec![
+        string("order_uid", &order.order_uid),
+        string("track_number", &order.track_number),
+        string("entry", &order.entry),
+        (
+            "delivery".into(),
+            Value::Record(vec![
+                string("name", &d.name),
+                string("phone", &d.phone),
+                string("zip", &d.zip),
+                string("city", &d.city),
+                string("address", &d.address),
+                string("region", &d.region),
+                string("email", &d.email),
+            ]),
+        ),
+        (
+            "payment".into(),
+            Value::Record(vec![
+                string("transaction", &p.transaction),
+                string("request_id", &p.request_id),
+                string("currency", &p.currency),
+                string("provider", &p.provider),
+                ("amount".into(), Value::Int(p.amount)),
+                ("payment_dt".into(), Value::Long(p.payment_dt)),
+                string("bank", &p.bank),
+                ("delivery_cost".into(), Value::Int(p.delivery_cost)),
+                ("goods_total".into(), Value::Int(p.goods_total)),
+                ("custom_fee".into(), Value::Int(p.custom_fee)),
+            ]),
+        ),
+        (
+            "items".into(),
+            Value::Array(
+                order
+                    .items
+                    .iter()
+                    .map(|i| {
+                        Value::Record(vec![
+                            ("chrt_id".into(), Value::Int(i.chrt_id)),
+                            string("track_number", &i.track_number),
+                            ("price".into(), Value::Int(i.price)),
+                            string("rid", &i.rid),
+                            string("name", &i.name),
+                            ("sale".into(), Value::Int(i.sale)),
+                            string("size", &i.size),
+                            ("total_price".into(), Value::Int(i.total_price)),
+                            ("nm_id".into(), Value::Int(i.nm_id)),
+                            string("brand", &i.brand),
+                            ("status".into(), Value::Int(i.status)),
+                        ])
+                    })
+                    .collect(),
+            ),
+        ),
+        string("locale", &order.locale),
+        string("internal_signature", &order.internal_signature),
+        string("customer_id", &order.customer_id),
+        string("delivery_service", &order.delivery_service),
+        string("shardkey", &order.shardkey),
+        ("sm_id".into(), Value::Int(order.sm_id)),
+        (
+            "date_created".into(),
+            Value::TimestampMicros(order.date_created.timestamp_micros()),
+        ),
+        string("oof_shard", &order.oof_shard),
+    ])
+}
+
+/// Converts a value of the order schema into an order.
+///
+/// # Errors
+/// Returns [`AvroError::Decode`] if the value does not have the order schema's shape.
+pub fn order_from_value(value: Value) -> Result<Order, AvroError> {
+    let mut order = Fields::new(value, "Order")?;
+    let mut delivery = Fields::new(order.take("delivery")?, "Delivery")?;
+    let mut payment = Fields::new(order.take("payment")?, "Payment")?;
+    let Value::Array(items) = order.take("items")? else {
+        return Err(AvroError::Decode("Order.items is not an array".into()));
+    };
+
+    let items = items
+        .into_iter()
+        .map(|item| {
+            let mut item = Fields::new(item, "Item")?;
+            Ok(Item {
+                chrt_id: item.int("chrt_id")?,
+                track_number: item.string("track_number")?,
+                price: item.int("price")?,
+                rid: item.string("rid")?,
+                name: item.string("name")?,
+                sale: item.int("sale")?,
+                size: item.string("size")?,
+                total_price: item.int("total_price")?,
+                nm_id: item.int("nm_id")?,
+                brand: item.string("brand")?,
+                status: item.int("status")?,
+            })
+        })
+        .collect::<Result<Vec<_>, AvroError>>()?;
+
+    let micros = order.timestamp_micros("date_created")?;
+    Ok(Order {
+        order_uid: order.string("order_uid")?,
+        track_number: order.string("track_number")?,
+        entry: order.string("entry")?,
+        delivery: Delivery {
+            name: delivery.string("name")?,
+            phone: delivery.string("phone")?,
+            zip: delivery.string("zip")?,
+            city: delivery.string("city")?,
+            address: delivery.string("address")?,
+            region: delivery.string("region")?,
+            email: delivery.string("email")?,
+        },
+        payment: Payment {
+            transaction: payment.string("transaction")?,
+            request_id: payment.string("request_id")?,
+            currency: payment.string("currency")?,
+            provider: payment.string("provider")?,
+            amount: payment.int("amount")?,
+            payment_dt: payment.long("payment_dt")?,
+            bank: payment.string("bank")?,
+            delivery_cost: payment.int("delivery_cost")?,
+            goods_total: payment.int("goods_total")?,
+            custom_fee: payment.int("custom_fee")?,
+        },
+        items,
+        locale: order.string("locale")?,
+        internal_signature: order.string("internal_signature")?,
+        customer_id: order.string("customer_id")?,
+        delivery_service: order.string("delivery_service")?,
+        shardkey: order.string("shardkey")?,
+        sm_id: order.int("sm_id")?,
+        date_created: DateTime::from_timestamp_micros(micros).ok_or_else(|| {
+            AvroError::Decode(format!("Order.date_created {micros} is out of range"))
+        })?,
+        oof_shard: order.string("oof_shard")?,
+        source_topic: None,
+    })
+}
+
+fn string(name: &str, value: &str) -> (String, Value) {
+    (name.to_string(), Value::String(value.to_string()))
+}
+
+/// Field accessor for a decoded record.
+struct Fields {
+    record: &'static str,
+    fields: Vec<(String, Value)>,
+}
+
+impl Fields {
+    fn new(value: Value, record: &'static str) -> Result<Self, AvroError> {
+        match value {
+            Value::Record(fields) => Ok(Self { record, fields }),
+            other => Err(AvroError::Decode(format!(
+                "expected a {record} record, got {other:?}"
+            ))),
+        }
+    }
+
+    fn take(&mut self, name: &str) -> Result<Value, AvroError> {
+        let pos = self
+            .fields
+            .iter()
+            .position(|(n, _)| n == name)
+            .ok_or_else(|| AvroError::Decode(format!("{}.{name} is missing", self.record)))?;
+        Ok(self.fields.swap_remove(pos).1)
+    }
+
+    fn string(&mut self, name: &str) -> Result<String, AvroError> {
+        match self.take(name)? {
+            Value::String(s) => Ok(s),
+            other => Err(self.mismatch(name, "string", &other)),
+        }
+    }
+
+    fn int(&mut self, name: &str) -> Result<i32, AvroError> {
+        match self.take(name)? {
+            Value::Int(n) => Ok(n),
+            other => Err(self.mismatch(name, "int", &other)),
+        }
+    }
+
+    fn long(&mut self, name: &str) -> Result<i64, AvroError> {
+        match self.take(name)? {
+            Value::Long(n) => Ok(n),
+            other => Err(self.mismatch(name, "long", &other)),
+        }
+    }
+
+    fn timestamp_micros(&mut self, name: &str) -> Result<i64, AvroError> {
+        match self.take(name)? {
+            Value::TimestampMicros(n) => Ok(n),
+            other => Err(self.mismatch(name, "timestamp-micros", &other)),
+        }
+    }
+
+    fn mismatch(&self, name: &str, expected: &str, got: &Value) -> AvroError {
+        AvroError::Decode(format!(
+            "{}.{name} should be {expected}, got {got:?}",
+            self.record
+        ))
+    }
+}
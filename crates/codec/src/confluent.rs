@@ -0,0 +1,59 @@
+//! Confluent Schema Registry wire format.
+//!
+//! Each message starts with a zero magic byte and the 4-byte big-endian id of
+//! the writer's schema in the registry, followed by the Avro-encoded datum.
+
+use thiserror::Error;
+
+const MAGIC_BYTE: u8 = 0;
+const HEADER_LEN: usize = 5;
+
+/// A payload that is not in the Confluent wire format.
+#[derive(Debug, Error)]
+pub enum FramingError {
+    #[error("Payload is too short for the schema registry header ({0} bytes)")]
+    TooShort(usize),
+    #[error("Unknown magic byte {0:#04x}")]
+    MagicByte(u8),
+}
+
+/// Prefixes an encoded datum with the header for `schema_id`.
+pub fn frame(schema_id: u32, datum: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(HEADER_LEN + datum.len());
+    out.push(MAGIC_BYTE);
+    out.extend_from_slice(&schema_id.to_be_bytes());
+    out.extend_from_slice(datum);
+    out
+}
+
+/// Splits a framed payload into its schema id and encoded datum.
+///
+/// # Errors
+/// Returns a [`FramingError`] if the payload does not start with a valid header.
+pub fn unframe(payload: &[u8]) -> Result<(u32, &[u8]), FramingError> {
+    if payload.len() < HEADER_LEN {
+        return Err(FramingError::TooShort(payload.len()));
+    }
+    if payload[0] != MAGIC_BYTE {
+        return Err(FramingError::MagicByte(payload[0]));
+    }
+    let id = u32::from_be_bytes(payload[1..HEADER_LEN].try_into().expect("4 bytes"));
+    Ok((id, &payload[HEADER_LEN..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_unframe() {
+        let framed = frame(258, b"datum");
+        assert_eq!(&framed[..5], [0, 0, 0, 1, 2]);
+        assert_eq!(unframe(&framed).unwrap(), (258, &b"datum"[..]));
+        assert!(matches!(
+            unframe(b"{\"a\":1}"),
+            Err(FramingError::MagicByte(b'{'))
+        ));
+        assert!(matches!(unframe(&[0, 1]), Err(FramingError::TooShort(2))));
+    }
+}
@@ -0,0 +1,20 @@
+//! Binary payload formats for order messages.
+//!
+//! - [`avro`]: Avro encoding of orders against the schema in `schemas/order.avsc`.
+//! - [`confluent`]: the Confluent magic-byte plus schema-id framing.
+//! - [`protobuf`]: protobuf encoding of orders as described by `schemas/order.proto`.
+//! - [`registry`]: a caching client for a Confluent-compatible schema registry.
+
+pub mod avro;
+pub mod confluent;
+pub mod protobuf;
+pub mod registry;
+
+/// Kafka message header carrying the MIME type of the payload.
+pub const CONTENT_TYPE_HEADER: &str = "content-type";
+
+/// Content type of JSON-encoded orders.
+pub const CONTENT_TYPE_JSON: &str = "application/json";
+
+/// Content type of protobuf-encoded orders.
+pub const CONTENT_TYPE_PROTOBUF: &str = "application/x-protobuf";
@@ -0,0 +1,229 @@
+//! Protobuf encoding of orders.
+//!
+//! The message types are generated from `schemas/order.proto` by `prost-build`
+//! when the crate is built; this module converts between them and
+//! [`model::Order`].
+
+use chrono::DateTime;
+use prost::Message;
+use thiserror::Error;
+
+/// Errors from decoding protobuf orders.
+#[derive(Debug, Error)]
+pub enum ProtobufError {
+    #[error("Failed to decode protobuf order: {0}")]
+    Decode(#[from] prost::DecodeError),
+    #[error("Order.date_created is out of range ({seconds}s, {nanos}ns)")]
+    InvalidTimestamp { seconds: i64, nanos: i32 },
+}
+
+/// Message types generated from `schemas/order.proto` by the build script.
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/shoppingcart.orders.rs"));
+}
+
+pub use generated::{Delivery, Item, Order, Payment};
+pub use prost_types::Timestamp;
+
+/// Encodes an order as a protobuf `Order` message.
+pub fn encode_order(order: &model::Order) -> Vec<u8> {
+    Order::from(order).encode_to_vec()
+}
+
+/// Decodes an order from a protobuf `Order` message.
+///
+/// # Errors
+/// Returns a [`ProtobufError`] if the bytes are not a valid message or the
+/// creation timestamp is out of range.
+pub fn decode_order(bytes: &[u8]) -> Result<model::Order, ProtobufError> {
+    Order::decode(bytes)?.try_into()
+}
+
+impl From<&model::Order> for Order {
+    fn from(order: &model::Order) -> Self {
+        let d = &order.delivery;
+        let p = &order.payment;
+        Self {
+            order_uid: order.order_uid.clone(),
+            track_number: order.track_number.clone(),
+            entry: order.entry.clone(),
+            delivery: Some(Delivery {
+                name: d.name.clone(),
+                phone: d.phone.clone(),
+                zip: d.zip.clone(),
+                city: d.city.clone(),
+                address: d.address.clone(),
+                region: d.region.clone(),
+                email: d.email.clone(),
+            }),
+            payment: Some(Payment {
+                transaction: p.transaction.clone(),
+                request_id: p.request_id.clone(),
+                currency: p.currency.clone(),
+                provider: p.provider.clone(),
+                amount: p.amount,
+                payment_dt: p.payment_dt,
+                bank: p.bank.clone(),
+                delivery_cost: p.delivery_cost,
+                goods_total: p.goods_total,
+                custom_fee: p.custom_fee,
+            }),
+            items: order
+                .items
+                .iter()
+                .map(|i| Item {
+                    chrt_id: i.chrt_id,
+                    track_number: i.track_number.clone(),
+                    price: i.price,
+                    rid: i.rid.clone(),
+                    name: i.name.clone(),
+                    sale: i.sale,
+                    size: i.size.clone(),
+                    total_price: i.total_price,
+                    nm_id: i.nm_id,
+                    brand: i.brand.clone(),
+                    status: i.status,
+                })
+                .collect(),
+            locale: order.locale.clone(),
+            internal_signature: order.internal_signature.clone(),
+            customer_id: order.customer_id.clone(),
+            delivery_service: order.delivery_service.clone(),
+            shardkey: order.shardkey.clone(),
+            sm_id: order.sm_id,
+            date_created: Some(Timestamp {
+                seconds: order.date_created.timestamp(),
+                nanos: order.date_created.timestamp_subsec_nanos() as i32,
+            }),
+            oof_shard: order.oof_shard.clone(),
+        }
+    }
+}
+
+impl TryFrom<Order> for model::Order {
+    type Error = ProtobufError;
+
+    /// Missing nested messages take their default values, as in proto3.
+    fn try_from(order: Order) -> Result<Self, Self::Error> {
+        let d = order.delivery.unwrap_or_default();
+        let p = order.payment.unwrap_or_default();
+        let Timestamp { seconds, nanos } = order.date_created.unwrap_or_default();
+        let date_created = u32::try_from(nanos)
+            .ok()
+            .and_then(|nanos| DateTime::from_timestamp(seconds, nanos))
+            .ok_or(ProtobufError::InvalidTimestamp { seconds, nanos })?;
+
+        Ok(Self {
+            order_uid: order.order_uid,
+            track_number: order.track_number,
+            entry: order.entry,
+            delivery: model::Delivery {
+                name: d.name,
+                phone: d.phone,
+                zip: d.zip,
+                city: d.city,
+                address: d.address,
+                region: d.region,
+                email: d.email,
+            },
+            payment: model::Payment {
+                transaction: p.transaction,
+                request_id: p.request_id,
+                currency: p.currency,
+                provider: p.provider,
+                amount: p.amount,
+                payment_dt: p.payment_dt,
+                bank: p.bank,
+                delivery_cost: p.delivery_cost,
+                goods_total: p.goods_total,
+                custom_fee: p.custom_fee,
+            },
+            items: order
+                .items
+                .into_iter()
+                .map(|i| model::Item {
+                    chrt_id: i.chrt_id,
+                    track_number: i.track_number,
+                    price: i.price,
+                    rid: i.rid,
+                    name: i.name,
+                    sale: i.sale,
+                    size: i.size,
+                    total_price: i.total_price,
+                    nm_id: i.nm_id,
+                    brand: i.brand,
+                    status: i.status,
+                })
+                .collect(),
+            locale: order.locale,
+            internal_signature: order.internal_signature,
+            customer_id: order.customer_id,
+            delivery_service: order.delivery_service,
+            shardkey: order.shardkey,
+            sm_id: order.sm_id,
+            date_created,
+            oof_shard: order.oof_shard,
+            source_topic: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORDER_JSON: &str = r#"{
+        "order_uid": "b563feb7b2b84b6test",
+        "track_number": "WBILMTESTTRACK",
+        "entry": "WBIL",
+        "delivery": {
+            "name": "Test Testov", "phone": "+9720000000", "zip": "2639809",
+            "city": "Kiryat Mozkin", "address": "Ploshad Mira 15", "region": "Kraiot",
+            "email": "test@gmail.com"
+        },
+        "payment": {
+            "transaction": "b563feb7b2b84b6test", "request_id": "", "currency": "USD",
+            "provider": "wbpay", "amount": 1817, "payment_dt": 1637907727, "bank": "alpha",
+            "delivery_cost": 1500, "goods_total": 317, "custom_fee": 0
+        },
+        "items": [{
+            "chrt_id": 9934930, "track_number": "WBILMTESTTRACK", "price": 453,
+            "rid": "ab4219087a764ae0btest", "name": "Mascaras", "sale": 30, "size": "0",
+            "total_price": 317, "nm_id": 2389212, "brand": "Vivienne Sabo", "status": 202
+        }],
+        "locale": "en",
+        "internal_signature": "",
+        "customer_id": "test",
+        "delivery_service": "meest",
+        "shardkey": "9",
+        "sm_id": 99,
+        "date_created": "2021-11-26T06:22:19.123456789Z",
+        "oof_shard": "1"
+    }"#;
+
+    #[test]
+    fn test_round_trip_matches_json() {
+        let order: model::Order = serde_json::from_str(ORDER_JSON).unwrap();
+        let decoded = decode_order(&encode_order(&order)).unwrap();
+        assert_eq!(decoded, order);
+        // Both forms serialize the decoded order identically, down to the nanoseconds.
+        assert_eq!(
+            serde_json::to_value(&decoded).unwrap(),
+            serde_json::from_str::<serde_json::Value>(ORDER_JSON).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_invalid_timestamp_is_rejected() {
+        let mut message = Order::from(&model::Order::default());
+        message.date_created = Some(Timestamp {
+            seconds: 0,
+            nanos: -1,
+        });
+        let err = decode_order(&message.encode_to_vec()).unwrap_err();
+        assert!(
+            matches!(err, ProtobufError::InvalidTimestamp { .. }),
+            "{err}"
+        );
+    }
+}
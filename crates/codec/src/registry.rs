@@ -0,0 +1,198 @@
+//! Client for a Confluent-compatible schema registry.
+//!
+//! Schemas are immutable once registered, so every schema fetched by id and
+//! every id obtained by registering a schema is cached for the lifetime of the
+//! client.
+
+use crate::avro::Schema;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use thiserror::Error;
+use tracing::debug;
+
+const CONTENT_TYPE: &str = "application/vnd.schemaregistry.v1+json";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Errors from talking to the schema registry.
+#[derive(Debug, Error)]
+pub enum RegistryError {
+    #[error("Schema registry request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("Schema registry returned {status}: {message}")]
+    Status { status: u16, message: String },
+    #[error("Schema {id} from the registry is not a valid Avro schema: {source}")]
+    InvalidSchema { id: u32, source: apache_avro::Error },
+}
+
+#[derive(Deserialize)]
+struct SchemaResponse {
+    schema: String,
+}
+
+#[derive(Deserialize)]
+struct RegisterResponse {
+    id: u32,
+}
+
+/// Caching schema registry client.
+pub struct SchemaRegistry {
+    base_url: String,
+    http: reqwest::Client,
+    schemas: RwLock<HashMap<u32, Arc<Schema>>>,
+    ids: RwLock<HashMap<(String, String), u32>>,
+}
+
+impl SchemaRegistry {
+    /// Creates a client for the registry at `base_url`.
+    ///
+    /// # Errors
+    /// Returns an error if the HTTP client cannot be built.
+    pub fn new(base_url: impl Into<String>) -> Result<Self, RegistryError> {
+        Ok(Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            http: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()?,
+            schemas: RwLock::default(),
+            ids: RwLock::default(),
+        })
+    }
+
+    /// Returns the schema registered under `id`, fetching it on first use.
+    ///
+    /// # Errors
+    /// Returns an error if the registry cannot be reached, does not know the id,
+    /// or serves something that is not an Avro schema.
+    pub async fn schema_by_id(&self, id: u32) -> Result<Arc<Schema>, RegistryError> {
+        if let Some(schema) = self.schemas.read().unwrap().get(&id) {
+            return Ok(schema.clone());
+        }
+
+        debug!("Fetching schema {id} from the schema registry");
+        let url = format!("{}/schemas/ids/{id}", self.base_url);
+        let response = self.http.get(url).send().await?;
+        let body: SchemaResponse = check(response).await?.json().await?;
+        let schema = Schema::parse_str(&body.schema)
+            .map_err(|source| RegistryError::InvalidSchema { id, source })?;
+
+        let schema = Arc::new(schema);
+        self.schemas.write().unwrap().insert(id, schema.clone());
+        Ok(schema)
+    }
+
+    /// Registers `schema` under `subject` and returns its id.
+    ///
+    /// Registering a schema that is already registered returns the existing id;
+    /// the registry rejects schemas that break the subject's compatibility rules.
+    ///
+    /// # Errors
+    /// Returns an error if the registry cannot be reached or rejects the schema.
+    pub async fn register(&self, subject: &str, schema: &str) -> Result<u32, RegistryError> {
+        let key = (subject.to_string(), schema.to_string());
+        if let Some(id) = self.ids.read().unwrap().get(&key) {
+            return Ok(*id);
+        }
+
+        debug!("Registering schema under subject {subject}");
+        let url = format!("{}/subjects/{subject}/versions", self.base_url);
+        let response = self
+            .http
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, CONTENT_TYPE)
+            .body(json!({ "schema": schema }).to_string())
+            .send()
+            .await?;
+        let body: RegisterResponse = check(response).await?.json().await?;
+
+        self.ids.write().unwrap().insert(key, body.id);
+        Ok(body.id)
+    }
+}
+
+/// Turns non-success responses into [`RegistryError::Status`].
+async fn check(response: reqwest::Response) -> Result<reqwest::Response, RegistryError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let message = response.text().await.unwrap_or_default();
+    Err(RegistryError::Status {
+        status: status.as_u16(),
+        message,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::avro::ORDER_SCHEMA_JSON;
+    use axum::extract::{Path, State};
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Serves the order schema as id 7 and counts the requests it receives.
+    async fn mock_registry() -> (String, Arc<AtomicUsize>) {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let app = Router::new()
+            .route(
+                "/schemas/ids/{id}",
+                get(
+                    |State(hits): State<Arc<AtomicUsize>>, Path(id): Path<u32>| async move {
+                        hits.fetch_add(1, Ordering::SeqCst);
+                        match id {
+                            7 => Ok(Json(json!({ "schema": ORDER_SCHEMA_JSON }))),
+                            _ => Err(axum::http::StatusCode::NOT_FOUND),
+                        }
+                    },
+                ),
+            )
+            .route(
+                "/subjects/{subject}/versions",
+                post(|State(hits): State<Arc<AtomicUsize>>| async move {
+                    hits.fetch_add(1, Ordering::SeqCst);
+                    Json(json!({ "id": 7 }))
+                }),
+            )
+            .with_state(hits.clone());
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{addr}"), hits)
+    }
+
+    #[tokio::test]
+    async fn test_schema_lookup_is_cached() {
+        let (url, hits) = mock_registry().await;
+        let registry = SchemaRegistry::new(url).unwrap();
+
+        let id = registry
+            .register("orders-value", ORDER_SCHEMA_JSON)
+            .await
+            .unwrap();
+        assert_eq!(
+            id,
+            registry
+                .register("orders-value", ORDER_SCHEMA_JSON)
+                .await
+                .unwrap()
+        );
+        let schema = registry.schema_by_id(id).await.unwrap();
+        registry.schema_by_id(id).await.unwrap();
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            schema.name().unwrap().fullname(None),
+            "shoppingcart.orders.Order"
+        );
+
+        let err = registry.schema_by_id(8).await.unwrap_err();
+        assert!(
+            matches!(err, RegistryError::Status { status: 404, .. }),
+            "{err}"
+        );
+    }
+}
@@ -26,12 +26,130 @@ pub struct AppConfig {
     pub kafka_brokers: Vec<String>,
     /// Kafka topic for processing orders.
     pub kafka_topic: String,
+    /// Topic subscriptions with their decoder and validation profile, as a comma-separated
+    /// list of `TOPIC[=DECODER[:PROFILE]]` entries; a topic starting with `^` is a regex
+    /// pattern. Empty subscribes to `kafka_topic` with the JSON decoder.
+    #[serde(deserialize_with = "deserialize_subscriptions")]
+    pub kafka_subscriptions: Vec<SubscriptionConfig>,
     /// Kafka consumer group ID.
     pub kafka_group_id: String,
+    /// Maximum number of messages persisted per batch (0 disables batch mode).
+    pub kafka_batch_size: usize,
+    /// Maximum time to wait for a batch to fill up (human-friendly format, e.g. "100ms").
+    #[serde(deserialize_with = "deserialize_duration_secs")]
+    pub kafka_batch_timeout: Duration,
+    /// Maximum number of partitions processed concurrently by per-partition workers
+    /// (0 consumes all partitions sequentially in a single loop).
+    pub kafka_partition_workers: usize,
+    /// Dead-letter topic for messages that cannot be processed (empty disables it)
+    pub kafka_dlq_topic: String,
+    /// How often librdkafka statistics (partition lag) are collected
+    #[serde(deserialize_with = "deserialize_duration_secs")]
+    pub kafka_statistics_interval: Duration,
+    /// Base URL of the Confluent-compatible schema registry used by the avro decoder
+    /// and encoder (empty disables Avro support).
+    pub kafka_schema_registry_url: String,
+    /// Payload format of messages published by the test producer ("json", "protobuf" or "avro").
+    pub kafka_producer_format: String,
+
+    // --- Logging ---
+    /// Log output format: "text", "pretty" (multi-line) or "json".
+    pub log_format: String,
+    /// Level filter in `RUST_LOG` syntax, e.g. "info,kafka_consumer=debug".
+    pub log_level: String,
+    /// Directory for rotated log files (empty logs to stdout).
+    pub log_dir: String,
+    /// How often log files are rotated: "minutely", "hourly", "daily" or "never".
+    pub log_rotation: String,
+
+    // --- Tracing ---
+    /// OTLP/HTTP traces endpoint of the OpenTelemetry collector
+    /// (e.g. "http://otel-collector:4318/v1/traces"; empty disables export).
+    pub otel_exporter_otlp_endpoint: String,
+    /// Service name reported with exported spans.
+    pub otel_service_name: String,
+
+    // --- Business metrics ---
+    /// Delivery services reported as labels of the order KPI metrics
+    /// (comma-separated in env); other values are reported as "other".
+    #[serde(deserialize_with = "deserialize_list")]
+    pub kpi_delivery_services: Vec<String>,
+    /// Order entries reported as KPI labels; other values are reported as "other".
+    #[serde(deserialize_with = "deserialize_list")]
+    pub kpi_entries: Vec<String>,
+    /// Locales reported as KPI labels; other values are reported as "other".
+    #[serde(deserialize_with = "deserialize_list")]
+    pub kpi_locales: Vec<String>,
+    /// Currencies reported as revenue labels; other values are reported as "other".
+    #[serde(deserialize_with = "deserialize_list")]
+    pub kpi_currencies: Vec<String>,
 
     // --- HTTP server ---
     /// The port on which the HTTP server will listen.
     pub http_port: u16,
+    /// Whether API routes require an API key or JWT (default: true).
+    pub auth_enabled: bool,
+    /// Static API keys as a comma-separated list of `NAME:SHA256:SCOPE[+SCOPE...]`
+    /// entries, where SHA256 is the hex SHA-256 digest of the key.
+    #[serde(deserialize_with = "deserialize_api_keys")]
+    pub auth_api_keys: Vec<ApiKeyConfig>,
+    /// Shared secret for HS256-signed JWTs (empty disables HS256).
+    pub auth_jwt_hs256_secret: String,
+    /// Path to the PEM public key for RS256-signed JWTs (empty disables RS256).
+    pub auth_jwt_rs256_public_key_path: String,
+    /// Required JWT `iss` claim (empty accepts any issuer).
+    pub auth_jwt_issuer: String,
+    /// Required JWT `aud` claim (empty accepts any audience).
+    pub auth_jwt_audience: String,
+    /// Whether `/health` and `/metrics` stay reachable without credentials.
+    pub auth_public_health_metrics: bool,
+    /// Per-client quota for routes without their own, as `RATE/BURST` in requests per
+    /// second (empty disables rate limiting of those routes).
+    #[serde(deserialize_with = "deserialize_optional_quota")]
+    pub rate_limit_default: Option<RateQuota>,
+    /// Per-route quotas as a comma-separated list of `ROUTE=RATE/BURST` entries,
+    /// where ROUTE is the route template, e.g. "/order/{id}=5/10".
+    #[serde(deserialize_with = "deserialize_route_quotas")]
+    pub rate_limit_routes: Vec<RouteQuota>,
+    /// Maximum number of requests handled at once; further requests are shed
+    /// with 503 (0 means unlimited).
+    pub http_max_concurrent_requests: usize,
+    /// Minimum response body size, in bytes, compressed with gzip, brotli or zstd
+    /// when the client accepts it (0 disables response compression).
+    pub http_compression_min_size: u16,
+    /// Number of recent orders kept for clients of the live order stream to resume
+    /// from; also how far a live client, on the stream or the order WebSocket, may
+    /// fall behind before it misses orders.
+    pub order_stream_buffer: usize,
+    /// Interval of heartbeats sent on idle live order streams (human-friendly
+    /// format, e.g. "15s").
+    #[serde(deserialize_with = "deserialize_duration_secs")]
+    pub order_stream_heartbeat: Duration,
+    /// Maximum number of subscriptions a client of the order WebSocket may hold.
+    pub order_ws_max_subscriptions: usize,
+    /// Interval of pings sent to order WebSocket clients; a client that has not
+    /// answered by the next ping is disconnected (human-friendly format, e.g. "30s").
+    #[serde(deserialize_with = "deserialize_duration_secs")]
+    pub order_ws_ping_interval: Duration,
+    /// Number of orders read per database query by order exports.
+    pub export_page_size: usize,
+    /// Upper bounds, in seconds, of the HTTP request duration histogram buckets
+    /// (comma-separated in env, e.g. "0.01,0.1,1").
+    #[serde(deserialize_with = "deserialize_buckets")]
+    pub http_duration_buckets: Vec<f64>,
+
+    // --- Cache ---
+    /// How long order ids confirmed missing in the DB are remembered by the cache
+    /// (human-friendly format, e.g. "30s"; "0s" disables negative caching).
+    #[serde(deserialize_with = "deserialize_duration_secs")]
+    pub cache_negative_ttl: Duration,
+    /// Interval between background cache consistency runs ("0s" disables the job).
+    #[serde(deserialize_with = "deserialize_duration_secs")]
+    pub consistency_check_interval: Duration,
+    /// Number of orders sampled per background consistency run (0 means full scan).
+    pub consistency_check_sample_size: usize,
+    /// Whether background consistency runs repair the differences they find.
+    pub consistency_check_repair: bool,
 
     // --- Shutdown timeout ---
     /// Graceful shutdown timeout (human-friendly format, e.g. "5s", "1m").
@@ -61,7 +179,220 @@ pub struct AppConfig {
     pub kafka_exporter_port: u16,
 }
 
-/// Custom deserializer for graceful shutdown timeout.
+/// One Kafka topic (or `^`-prefixed regex pattern) the consumer subscribes to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubscriptionConfig {
+    /// Topic name, or a regex pattern if it starts with `^`.
+    pub topic: String,
+    /// Name of the payload decoder (default: "json").
+    pub decoder: String,
+    /// Name of the validation profile (default: "default").
+    pub validation: String,
+}
+
+impl SubscriptionConfig {
+    /// Parses a `TOPIC[=DECODER[:PROFILE]]` entry.
+    pub fn parse(entry: &str) -> Result<Self> {
+        let (topic, rest) = match entry.split_once('=') {
+            Some((topic, rest)) => (topic.trim(), Some(rest.trim())),
+            None => (entry.trim(), None),
+        };
+        if topic.is_empty() {
+            anyhow::bail!("Subscription '{entry}' has no topic");
+        }
+        let (decoder, validation) = match rest {
+            None => ("json", "default"),
+            Some(rest) => match rest.split_once(':') {
+                Some((decoder, validation)) => (decoder.trim(), validation.trim()),
+                None => (rest, "default"),
+            },
+        };
+        Ok(Self {
+            topic: topic.to_string(),
+            decoder: decoder.to_string(),
+            validation: validation.to_string(),
+        })
+    }
+}
+
+/// A static API key accepted by the HTTP API.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKeyConfig {
+    /// Name identifying the key's owner in logs.
+    pub name: String,
+    /// Lowercase hex SHA-256 digest of the key.
+    pub key_sha256: String,
+    /// Scopes granted to the key, e.g. "orders:read".
+    pub scopes: Vec<String>,
+}
+
+impl ApiKeyConfig {
+    /// Parses a `NAME:SHA256:SCOPE[+SCOPE...]` entry.
+    pub fn parse(entry: &str) -> Result<Self> {
+        let mut parts = entry.trim().splitn(3, ':');
+        let (Some(name), Some(digest), Some(scopes)) = (parts.next(), parts.next(), parts.next())
+        else {
+            anyhow::bail!("API key '{entry}' must have the form NAME:SHA256:SCOPES");
+        };
+        let digest = digest.trim().to_ascii_lowercase();
+        if digest.len() != 64 || !digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+            anyhow::bail!("API key '{}' has an invalid SHA-256 digest", name.trim());
+        }
+        Ok(Self {
+            name: name.trim().to_string(),
+            key_sha256: digest,
+            scopes: scopes
+                .split('+')
+                .map(str::trim)
+                .filter(|scope| !scope.is_empty())
+                .map(str::to_string)
+                .collect(),
+        })
+    }
+}
+
+/// A token bucket quota: `per_second` requests on average, bursts of up to `burst`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateQuota {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+impl RateQuota {
+    /// Parses a `RATE/BURST` quota, e.g. "0.5/5".
+    pub fn parse(quota: &str) -> Result<Self> {
+        let (rate, burst) = quota
+            .trim()
+            .split_once('/')
+            .with_context(|| format!("Quota '{quota}' must have the form RATE/BURST"))?;
+        let per_second: f64 = rate
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid rate in quota '{quota}'"))?;
+        let burst: u32 = burst
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid burst in quota '{quota}'"))?;
+        if !(per_second > 0.0 && per_second.is_finite()) || burst == 0 {
+            anyhow::bail!("Quota '{quota}' must have a positive rate and burst");
+        }
+        Ok(Self { per_second, burst })
+    }
+}
+
+/// A quota for one route template.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteQuota {
+    pub route: String,
+    pub quota: RateQuota,
+}
+
+impl RouteQuota {
+    /// Parses a `ROUTE=RATE/BURST` entry.
+    pub fn parse(entry: &str) -> Result<Self> {
+        let (route, quota) = entry.split_once('=').with_context(|| {
+            format!("Route quota '{entry}' must have the form ROUTE=RATE/BURST")
+        })?;
+        Ok(Self {
+            route: route.trim().to_string(),
+            quota: RateQuota::parse(quota)?,
+        })
+    }
+}
+
+/// Custom deserializer for an optional `RATE/BURST` quota; empty means none.
+fn deserialize_optional_quota<'de, D>(deserializer: D) -> Result<Option<RateQuota>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::de::Error;
+    let val = String::deserialize(deserializer)?;
+    if val.trim().is_empty() {
+        return Ok(None);
+    }
+    RateQuota::parse(&val).map(Some).map_err(D::Error::custom)
+}
+
+/// Custom deserializer for `rate_limit_routes`, parsing a comma-separated list of entries.
+fn deserialize_route_quotas<'de, D>(deserializer: D) -> Result<Vec<RouteQuota>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::de::Error;
+    let val = String::deserialize(deserializer)?;
+    val.split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .map(|entry| RouteQuota::parse(entry).map_err(D::Error::custom))
+        .collect()
+}
+
+/// Custom deserializer for `auth_api_keys`, parsing a comma-separated list of entries.
+fn deserialize_api_keys<'de, D>(deserializer: D) -> Result<Vec<ApiKeyConfig>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::de::Error;
+    let val = String::deserialize(deserializer)?;
+    val.split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .map(|entry| ApiKeyConfig::parse(entry).map_err(D::Error::custom))
+        .collect()
+}
+
+/// Custom deserializer for `kafka_subscriptions`, parsing a comma-separated list of entries.
+fn deserialize_subscriptions<'de, D>(deserializer: D) -> Result<Vec<SubscriptionConfig>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::de::Error;
+    let val = String::deserialize(deserializer)?;
+    val.split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .map(|entry| SubscriptionConfig::parse(entry).map_err(D::Error::custom))
+        .collect()
+}
+
+/// Custom deserializer for plain lists, parsing a comma-separated string.
+fn deserialize_list<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let val = String::deserialize(deserializer)?;
+    Ok(val
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Custom deserializer for histogram buckets, parsing a comma-separated list of
+/// strictly increasing numbers.
+fn deserialize_buckets<'de, D>(deserializer: D) -> Result<Vec<f64>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::de::Error;
+    let val = String::deserialize(deserializer)?;
+    let buckets = val
+        .split(',')
+        .filter(|bound| !bound.trim().is_empty())
+        .map(|bound| {
+            bound
+                .trim()
+                .parse::<f64>()
+                .map_err(|e| D::Error::custom(format!("Invalid bucket bound '{bound}': {e}")))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if buckets.is_empty() || buckets.windows(2).any(|w| w[0] >= w[1]) {
+        return Err(D::Error::custom(format!(
+            "Buckets '{val}' must be a non-empty, strictly increasing list"
+        )));
+    }
+    Ok(buckets)
+}
+
+/// Custom deserializer for duration settings such as the graceful shutdown timeout.
 /// Accepts human-readable formats like "5s", "1m", etc.
 fn deserialize_duration_secs<'de, D>(deserializer: D) -> Result<Duration, D::Error>
 where
@@ -97,9 +428,58 @@ impl AppConfig {
             // Kafka
             .set_default("kafka_brokers", vec!["localhost:9092"])? // Use localhost for local development
             .set_default("kafka_topic", "orders")?
+            .set_default("kafka_subscriptions", "")?
             .set_default("kafka_group_id", "orders_group")?
+            .set_default("kafka_batch_size", 0)?
+            .set_default("kafka_batch_timeout", "100ms")?
+            .set_default("kafka_partition_workers", 0)?
+            .set_default("kafka_dlq_topic", "")?
+            .set_default("kafka_statistics_interval", "15s")?
+            .set_default("kafka_schema_registry_url", "")?
+            .set_default("kafka_producer_format", "json")?
+            // Logging
+            .set_default("log_format", "text")?
+            .set_default("log_level", "info")?
+            .set_default("log_dir", "")?
+            .set_default("log_rotation", "daily")?
+            // Tracing
+            .set_default("otel_exporter_otlp_endpoint", "")?
+            .set_default("otel_service_name", "shoppingcart-backend")?
+            // Business metrics
+            .set_default("kpi_delivery_services", "meest")?
+            .set_default("kpi_entries", "WBIL")?
+            .set_default("kpi_locales", "en,ru,de,fr")?
+            .set_default("kpi_currencies", "USD,EUR,GBP,JPY,RUB")?
             // HTTP
             .set_default("http_port", 8081)?
+            .set_default("auth_enabled", true)?
+            .set_default("auth_api_keys", "")?
+            .set_default("auth_jwt_hs256_secret", "")?
+            .set_default("auth_jwt_rs256_public_key_path", "")?
+            .set_default("auth_jwt_issuer", "")?
+            .set_default("auth_jwt_audience", "")?
+            .set_default("auth_public_health_metrics", true)?
+            .set_default("rate_limit_default", "100/200")?
+            .set_default(
+                "rate_limit_routes",
+                "/api/send-test-order=1/5,/api/orders=20/40",
+            )?
+            .set_default("http_max_concurrent_requests", 512)?
+            .set_default("http_compression_min_size", 1024)?
+            .set_default("order_stream_buffer", 1000)?
+            .set_default("order_stream_heartbeat", "15s")?
+            .set_default("order_ws_max_subscriptions", 100)?
+            .set_default("order_ws_ping_interval", "30s")?
+            .set_default("export_page_size", 500)?
+            .set_default(
+                "http_duration_buckets",
+                "0.005,0.01,0.025,0.05,0.1,0.25,0.5,1,2.5,5,10",
+            )?
+            // Cache
+            .set_default("cache_negative_ttl", "30s")?
+            .set_default("consistency_check_interval", "0s")?
+            .set_default("consistency_check_sample_size", 1000)?
+            .set_default("consistency_check_repair", false)?
             // Shutdown
             .set_default("shutdown_timeout", "5s")?
             // Grafana
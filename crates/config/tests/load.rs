@@ -6,3 +6,68 @@ fn test_load_default_config() {
     // Default is now "postgres" for Docker Compose compatibility
     assert_eq!(cfg.db_host, "postgres");
 }
+
+#[test]
+fn test_auth_is_enabled_by_default() {
+    assert!(AppConfig::load().unwrap().auth_enabled);
+}
+
+#[test]
+fn test_parse_subscription() {
+    use app_config::SubscriptionConfig;
+
+    let sub = SubscriptionConfig::parse("orders").unwrap();
+    assert_eq!(
+        (
+            sub.topic.as_str(),
+            sub.decoder.as_str(),
+            sub.validation.as_str()
+        ),
+        ("orders", "json", "default")
+    );
+
+    let sub = SubscriptionConfig::parse(" ^orders-eu-.* = json:strict ").unwrap();
+    assert_eq!(
+        (
+            sub.topic.as_str(),
+            sub.decoder.as_str(),
+            sub.validation.as_str()
+        ),
+        ("^orders-eu-.*", "json", "strict")
+    );
+
+    assert!(SubscriptionConfig::parse("=json").is_err());
+}
+
+#[test]
+fn test_parse_api_key() {
+    use app_config::ApiKeyConfig;
+
+    let digest = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+    let key = ApiKeyConfig::parse(&format!(" support : {digest} : orders:read+admin ")).unwrap();
+    assert_eq!(key.name, "support");
+    assert_eq!(key.key_sha256, digest);
+    assert_eq!(key.scopes, ["orders:read", "admin"]);
+
+    assert!(ApiKeyConfig::parse("support:not-a-digest:orders:read").is_err());
+    assert!(ApiKeyConfig::parse(&format!("support:{digest}")).is_err());
+}
+
+#[test]
+fn test_parse_route_quota() {
+    use app_config::{RateQuota, RouteQuota};
+
+    let quota = RouteQuota::parse(" /order/{id} = 0.5/10 ").unwrap();
+    assert_eq!(quota.route, "/order/{id}");
+    assert_eq!(
+        quota.quota,
+        RateQuota {
+            per_second: 0.5,
+            burst: 10
+        }
+    );
+
+    assert!(RateQuota::parse("10").is_err());
+    assert!(RateQuota::parse("0/10").is_err());
+    assert!(RateQuota::parse("10/0").is_err());
+}
@@ -108,8 +108,14 @@ pub async fn run_migrations(client: &Client, migrations_dir: &str) -> Result<()>
         .await
         .context("Failed to read migrations directory")?;
 
+    // Apply migrations in file name order; later files may depend on earlier ones
+    let mut paths = Vec::new();
     while let Some(entry) = entries.next_entry().await? {
-        let path = entry.path();
+        paths.push(entry.path());
+    }
+    paths.sort();
+
+    for path in paths {
         if let Some(ext) = path.extension() {
             if ext == "sql" {
                 let file_name = path.file_name().unwrap().to_string_lossy();
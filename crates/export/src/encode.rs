@@ -0,0 +1,351 @@
+//! Encoding of exported orders into CSV, NDJSON and Parquet.
+//!
+//! CSV and Parquet share the flattened columns defined here: the order's own
+//! fields, then its delivery and payment fields prefixed with `delivery_` and
+//! `payment_`, then either its item count or the fields of one item prefixed
+//! with `item_`.
+
+use crate::parquet::ParquetRows;
+use crate::{ExportError, ExportFormat, RowLayout};
+use chrono::{DateTime, SecondsFormat, Utc};
+use model::{Item, Order, PiiView};
+use std::iter;
+
+/// Type of a flattened column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Kind {
+    Text,
+    Int,
+    BigInt,
+    Timestamp,
+}
+
+/// A value of a flattened column.
+pub(crate) enum Value<'a> {
+    Text(&'a str),
+    Int(i32),
+    BigInt(i64),
+    Timestamp(DateTime<Utc>),
+}
+
+/// A flattened column and how to read it from an order row.
+pub(crate) struct Column {
+    pub(crate) name: &'static str,
+    pub(crate) kind: Kind,
+    /// Reads the value from an order and, in the item layout, one of its items.
+    pub(crate) get: for<'a> fn(&'a Order, Option<&'a Item>) -> Value<'a>,
+}
+
+macro_rules! column {
+    ($name:literal, $kind:ident, |$order:ident, $item:ident| $value:expr) => {
+        Column {
+            name: $name,
+            kind: Kind::$kind,
+            get: |$order, $item| $value,
+        }
+    };
+}
+
+/// Columns of every row.
+const ORDER_COLUMNS: &[Column] = &[
+    column!("order_uid", Text, |o, _i| Value::Text(&o.order_uid)),
+    column!("track_number", Text, |o, _i| Value::Text(&o.track_number)),
+    column!("entry", Text, |o, _i| Value::Text(&o.entry)),
+    column!("locale", Text, |o, _i| Value::Text(&o.locale)),
+    column!("internal_signature", Text, |o, _i| Value::Text(
+        &o.internal_signature
+    )),
+    column!("customer_id", Text, |o, _i| Value::Text(&o.customer_id)),
+    column!("delivery_service", Text, |o, _i| Value::Text(
+        &o.delivery_service
+    )),
+    column!("shardkey", Text, |o, _i| Value::Text(&o.shardkey)),
+    column!("sm_id", Int, |o, _i| Value::Int(o.sm_id)),
+    column!("date_created", Timestamp, |o, _i| Value::Timestamp(
+        o.date_created
+    )),
+    column!("oof_shard", Text, |o, _i| Value::Text(&o.oof_shard)),
+    column!("source_topic", Text, |o, _i| Value::Text(
+        o.source_topic.as_deref().unwrap_or_default()
+    )),
+    column!("delivery_name", Text, |o, _i| Value::Text(&o.delivery.name)),
+    column!("delivery_phone", Text, |o, _i| Value::Text(
+        &o.delivery.phone
+    )),
+    column!("delivery_zip", Text, |o, _i| Value::Text(&o.delivery.zip)),
+    column!("delivery_city", Text, |o, _i| Value::Text(&o.delivery.city)),
+    column!("delivery_address", Text, |o, _i| Value::Text(
+        &o.delivery.address
+    )),
+    column!("delivery_region", Text, |o, _i| Value::Text(
+        &o.delivery.region
+    )),
+    column!("delivery_email", Text, |o, _i| Value::Text(
+        &o.delivery.email
+    )),
+    column!("payment_transaction", Text, |o, _i| Value::Text(
+        &o.payment.transaction
+    )),
+    column!("payment_request_id", Text, |o, _i| Value::Text(
+        &o.payment.request_id
+    )),
+    column!("payment_currency", Text, |o, _i| Value::Text(
+        &o.payment.currency
+    )),
+    column!("payment_provider", Text, |o, _i| Value::Text(
+        &o.payment.provider
+    )),
+    column!("payment_amount", Int, |o, _i| Value::Int(o.payment.amount)),
+    column!("payment_dt", BigInt, |o, _i| Value::BigInt(
+        o.payment.payment_dt
+    )),
+    column!("payment_bank", Text, |o, _i| Value::Text(&o.payment.bank)),
+    column!("payment_delivery_cost", Int, |o, _i| Value::Int(
+        o.payment.delivery_cost
+    )),
+    column!("payment_goods_total", Int, |o, _i| Value::Int(
+        o.payment.goods_total
+    )),
+    column!("payment_custom_fee", Int, |o, _i| Value::Int(
+        o.payment.custom_fee
+    )),
+];
+
+/// Columns added in the order layout.
+const ORDER_LAYOUT_COLUMNS: &[Column] = &[column!("item_count", Int, |o, _i| Value::Int(
+    o.items.len().try_into().unwrap_or(i32::MAX)
+))];
+
+/// Columns added in the item layout; every row there has an item.
+const ITEM_LAYOUT_COLUMNS: &[Column] = &[
+    column!("item_chrt_id", Int, |_o, i| Value::Int(item(i).chrt_id)),
+    column!("item_track_number", Text, |_o, i| Value::Text(
+        &item(i).track_number
+    )),
+    column!("item_price", Int, |_o, i| Value::Int(item(i).price)),
+    column!("item_rid", Text, |_o, i| Value::Text(&item(i).rid)),
+    column!("item_name", Text, |_o, i| Value::Text(&item(i).name)),
+    column!("item_sale", Int, |_o, i| Value::Int(item(i).sale)),
+    column!("item_size", Text, |_o, i| Value::Text(&item(i).size)),
+    column!("item_total_price", Int, |_o, i| Value::Int(
+        item(i).total_price
+    )),
+    column!("item_nm_id", Int, |_o, i| Value::Int(item(i).nm_id)),
+    column!("item_brand", Text, |_o, i| Value::Text(&item(i).brand)),
+    column!("item_status", Int, |_o, i| Value::Int(item(i).status)),
+];
+
+fn item(item: Option<&Item>) -> &Item {
+    item.expect("item columns are only read in the item layout")
+}
+
+/// The flattened columns of `layout`.
+pub(crate) fn columns(layout: RowLayout) -> Vec<&'static Column> {
+    let extra = match layout {
+        RowLayout::Order => ORDER_LAYOUT_COLUMNS,
+        RowLayout::Item => ITEM_LAYOUT_COLUMNS,
+    };
+    ORDER_COLUMNS.iter().chain(extra).collect()
+}
+
+/// The rows of `order` in `layout`: the order itself, or each of its items.
+///
+/// An order without items has no rows in the item layout.
+pub(crate) fn rows(
+    order: &Order,
+    layout: RowLayout,
+) -> Box<dyn Iterator<Item = Option<&Item>> + '_> {
+    match layout {
+        RowLayout::Order => Box::new(iter::once(None)),
+        RowLayout::Item => Box::new(order.items.iter().map(Some)),
+    }
+}
+
+enum Output {
+    /// Whether the header row is still to be written.
+    Csv {
+        header_pending: bool,
+    },
+    NdJson,
+    Parquet(Box<ParquetRows>),
+}
+
+/// Turns pages of orders into the bytes of an export.
+///
+/// Feed every page to [`Encoder::encode`] and write out what it returns, then
+/// write out what [`Encoder::finish`] returns.
+pub struct Encoder {
+    layout: RowLayout,
+    columns: Vec<&'static Column>,
+    view: PiiView,
+    output: Output,
+}
+
+impl Encoder {
+    /// Creates an encoder of `format`, exporting orders through `view`.
+    ///
+    /// `layout` applies to CSV and Parquet; NDJSON always has one order per line.
+    pub fn new(
+        format: ExportFormat,
+        layout: RowLayout,
+        view: PiiView,
+    ) -> Result<Self, ExportError> {
+        let columns = columns(layout);
+        let output = match format {
+            ExportFormat::Csv => Output::Csv {
+                header_pending: true,
+            },
+            ExportFormat::NdJson => Output::NdJson,
+            ExportFormat::Parquet => Output::Parquet(Box::new(ParquetRows::new(&columns)?)),
+        };
+        Ok(Self {
+            layout,
+            columns,
+            view,
+            output,
+        })
+    }
+
+    /// Encodes a page of orders, returning the bytes ready to be written.
+    ///
+    /// Parquet output is buffered in row groups, so it may return nothing.
+    pub fn encode(&mut self, orders: &[Order]) -> Result<Vec<u8>, ExportError> {
+        match &mut self.output {
+            Output::Csv { header_pending } => {
+                let mut writer = csv::Writer::from_writer(Vec::new());
+                if std::mem::take(header_pending) {
+                    writer.write_record(self.columns.iter().map(|c| c.name))?;
+                }
+                let mut record = Vec::with_capacity(self.columns.len());
+                for order in orders {
+                    let order = order.view(self.view);
+                    for item in rows(&order, self.layout) {
+                        record.clear();
+                        record.extend(
+                            self.columns
+                                .iter()
+                                .map(|c| csv_field((c.get)(&order, item))),
+                        );
+                        writer.write_record(&record)?;
+                    }
+                }
+                csv_bytes(writer)
+            }
+            Output::NdJson => {
+                let mut out = Vec::new();
+                for order in orders {
+                    serde_json::to_writer(&mut out, &order.view(self.view))?;
+                    out.push(b'\n');
+                }
+                Ok(out)
+            }
+            Output::Parquet(parquet) => {
+                for order in orders {
+                    let order = order.view(self.view);
+                    for item in rows(&order, self.layout) {
+                        parquet.push(&self.columns, &order, item);
+                    }
+                }
+                parquet.flush_full()
+            }
+        }
+    }
+
+    /// Completes the export, returning the remaining bytes.
+    pub fn finish(mut self) -> Result<Vec<u8>, ExportError> {
+        match self.output {
+            // Without orders the export is the header alone
+            Output::Csv {
+                header_pending: true,
+            } => self.encode(&[]),
+            Output::Csv { .. } => Ok(Vec::new()),
+            Output::NdJson => Ok(Vec::new()),
+            Output::Parquet(parquet) => parquet.finish(),
+        }
+    }
+}
+
+fn csv_bytes(writer: csv::Writer<Vec<u8>>) -> Result<Vec<u8>, ExportError> {
+    writer
+        .into_inner()
+        .map_err(|e| ExportError::Csv(e.into_error().into()))
+}
+
+fn csv_field(value: Value<'_>) -> String {
+    match value {
+        Value::Text(text) => text.to_string(),
+        Value::Int(n) => n.to_string(),
+        Value::BigInt(n) => n.to_string(),
+        Value::Timestamp(time) => time.to_rfc3339_opts(SecondsFormat::Micros, true),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn order() -> Order {
+        let mut order = Order {
+            order_uid: "b563feb7b2b84b6test".into(),
+            delivery_service: "meest".into(),
+            items: vec![
+                Item {
+                    chrt_id: 9934930,
+                    name: "Mascaras".into(),
+                    ..Default::default()
+                },
+                Item {
+                    chrt_id: 9934931,
+                    name: "Lipstick, red".into(),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        order.delivery.phone = "+9720000000".into();
+        order
+    }
+
+    #[test]
+    fn test_csv_rows_per_item_and_per_order() {
+        let mut encoder =
+            Encoder::new(ExportFormat::Csv, RowLayout::Item, PiiView::Masked).unwrap();
+        let mut out = encoder.encode(&[order()]).unwrap();
+        out.extend(encoder.finish().unwrap());
+        let mut reader = csv::Reader::from_reader(out.as_slice());
+        let headers = reader.headers().unwrap().clone();
+        let phone = headers.iter().position(|h| h == "delivery_phone").unwrap();
+        let name = headers.iter().position(|h| h == "item_name").unwrap();
+        let records: Vec<_> = reader.records().map(Result::unwrap).collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(&records[0][phone], "***0000");
+        assert_eq!(&records[1][name], "Lipstick, red");
+
+        let mut encoder = Encoder::new(ExportFormat::Csv, RowLayout::Order, PiiView::Full).unwrap();
+        let out = encoder.encode(&[order(), order()]).unwrap();
+        let mut reader = csv::Reader::from_reader(out.as_slice());
+        assert_eq!(
+            reader.headers().unwrap().iter().next_back(),
+            Some("item_count")
+        );
+        let records: Vec<_> = reader.records().map(Result::unwrap).collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].iter().next_back(), Some("2"));
+        assert!(records[0].iter().any(|field| field == "+9720000000"));
+    }
+
+    #[test]
+    fn test_ndjson_has_full_orders() {
+        let mut encoder =
+            Encoder::new(ExportFormat::NdJson, RowLayout::Item, PiiView::Masked).unwrap();
+        let out = encoder.encode(&[order(), order()]).unwrap();
+        let lines: Vec<Order> = out
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].items.len(), 2);
+        assert_eq!(lines[0].delivery.phone, "***0000");
+    }
+}
@@ -0,0 +1,142 @@
+//! Bulk export of stored orders.
+//!
+//! Orders matching an [`ExportFilter`] are read from Postgres page by page with
+//! [`OrderPages`], so an export of any size holds only one page in memory, and
+//! each page is turned into output bytes by an [`Encoder`]:
+//!
+//! - [`ExportFormat::Csv`]: flattened rows, one per order or one per item
+//!   ([`RowLayout`]), with delivery and payment fields prefixed by their entity.
+//! - [`ExportFormat::NdJson`]: one full [`Order`](model::Order) per line.
+//! - [`ExportFormat::Parquet`]: the same flattened rows as CSV, snappy-compressed.
+//!
+//! Both the HTTP export endpoint and the `export` subcommand are built on this crate.
+
+mod encode;
+mod parquet;
+mod query;
+
+pub use encode::Encoder;
+pub use query::OrderPages;
+
+use chrono::{DateTime, Utc};
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Orders read per database round trip unless configured otherwise.
+pub const DEFAULT_PAGE_SIZE: usize = 500;
+
+/// Errors of reading or encoding an export.
+#[derive(Debug, Error)]
+pub enum ExportError {
+    /// A query failed.
+    #[error("Database error: {0}")]
+    Db(#[from] tokio_postgres::Error),
+    /// Failed to obtain a database connection from the pool.
+    #[error("Pool error: {0}")]
+    Pool(#[from] deadpool_postgres::PoolError),
+    #[error("CSV error: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Parquet error: {0}")]
+    Parquet(#[from] ::parquet::errors::ParquetError),
+}
+
+/// Output format of an export.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    NdJson,
+    Parquet,
+}
+
+impl ExportFormat {
+    /// The `Content-Type` of this format.
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::NdJson => "application/x-ndjson",
+            Self::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+
+    /// The usual file extension of this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::NdJson => "ndjson",
+            Self::Parquet => "parquet",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(Self::Csv),
+            "ndjson" => Ok(Self::NdJson),
+            "parquet" => Ok(Self::Parquet),
+            other => Err(format!(
+                "unknown export format '{other}' (expected csv, ndjson or parquet)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.extension())
+    }
+}
+
+/// What a row of a flattened (CSV or Parquet) export stands for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RowLayout {
+    /// One row per order, with its number of items.
+    #[default]
+    Order,
+    /// One row per item, repeating the fields of its order.
+    Item,
+}
+
+impl FromStr for RowLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "order" => Ok(Self::Order),
+            "item" => Ok(Self::Item),
+            other => Err(format!(
+                "unknown row layout '{other}' (expected order or item)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for RowLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Order => "order",
+            Self::Item => "item",
+        })
+    }
+}
+
+/// Which orders to export.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExportFilter {
+    /// Orders created at or after this time.
+    pub from: Option<DateTime<Utc>>,
+    /// Orders created before this time.
+    pub to: Option<DateTime<Utc>>,
+    /// Delivery services to include, case-insensitive; all if empty.
+    pub delivery_services: Vec<String>,
+    /// Payment currencies to include, case-insensitive; all if empty.
+    pub currencies: Vec<String>,
+    /// Only orders of this customer.
+    pub customer_id: Option<String>,
+}
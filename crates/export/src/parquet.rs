@@ -0,0 +1,218 @@
+//! Parquet output of flattened order rows.
+//!
+//! Rows are collected column by column and written as a row group once
+//! [`ROW_GROUP_SIZE`] rows are buffered. The file writer writes into a buffer
+//! shared with [`ParquetRows`], which hands out each completed row group as it
+//! is written, so only one row group is held in memory.
+
+use crate::ExportError;
+use crate::encode::{Column, Kind, Value};
+use model::{Item, Order};
+use parquet::basic::Compression;
+use parquet::data_type::{ByteArray, ByteArrayType, Int32Type, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Rows per row group.
+const ROW_GROUP_SIZE: usize = 64 * 1024;
+
+/// Bytes written by the file writer and not yet handed out.
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Buffered values of one column.
+enum Values {
+    Text(Vec<ByteArray>),
+    Int(Vec<i32>),
+    BigInt(Vec<i64>),
+}
+
+impl Values {
+    fn len(&self) -> usize {
+        match self {
+            Self::Text(values) => values.len(),
+            Self::Int(values) => values.len(),
+            Self::BigInt(values) => values.len(),
+        }
+    }
+}
+
+/// Writer of flattened rows into a Parquet file.
+pub(crate) struct ParquetRows {
+    writer: SerializedFileWriter<SharedBuffer>,
+    buffer: SharedBuffer,
+    values: Vec<Values>,
+}
+
+impl ParquetRows {
+    pub(crate) fn new(columns: &[&Column]) -> Result<Self, ExportError> {
+        let fields: String = columns
+            .iter()
+            .map(|c| {
+                let ty = match c.kind {
+                    Kind::Text => "BYTE_ARRAY",
+                    Kind::Int => "INT32",
+                    Kind::BigInt | Kind::Timestamp => "INT64",
+                };
+                let annotation = match c.kind {
+                    Kind::Text => " (UTF8)",
+                    Kind::Timestamp => " (TIMESTAMP(MICROS,true))",
+                    Kind::Int | Kind::BigInt => "",
+                };
+                format!("REQUIRED {ty} {}{annotation};\n", c.name)
+            })
+            .collect();
+        let schema = parse_message_type(&format!("message order {{\n{fields}}}"))?;
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .set_max_row_group_size(ROW_GROUP_SIZE)
+            .build();
+        let buffer = SharedBuffer::default();
+        let writer =
+            SerializedFileWriter::new(buffer.clone(), Arc::new(schema), Arc::new(properties))?;
+        let values = columns
+            .iter()
+            .map(|c| match c.kind {
+                Kind::Text => Values::Text(Vec::new()),
+                Kind::Int => Values::Int(Vec::new()),
+                Kind::BigInt | Kind::Timestamp => Values::BigInt(Vec::new()),
+            })
+            .collect();
+        Ok(Self {
+            writer,
+            buffer,
+            values,
+        })
+    }
+
+    /// Buffers one row; `columns` must be the ones the writer was created with.
+    pub(crate) fn push(&mut self, columns: &[&Column], order: &Order, item: Option<&Item>) {
+        for (column, values) in columns.iter().zip(&mut self.values) {
+            match ((column.get)(order, item), values) {
+                (Value::Text(text), Values::Text(values)) => values.push(text.into()),
+                (Value::Int(n), Values::Int(values)) => values.push(n),
+                (Value::BigInt(n), Values::BigInt(values)) => values.push(n),
+                (Value::Timestamp(time), Values::BigInt(values)) => {
+                    values.push(time.timestamp_micros())
+                }
+                _ => unreachable!("column {} has values of its kind", column.name),
+            }
+        }
+    }
+
+    /// Writes out the buffered rows if they fill a row group.
+    pub(crate) fn flush_full(&mut self) -> Result<Vec<u8>, ExportError> {
+        if self.values.first().map_or(0, Values::len) >= ROW_GROUP_SIZE {
+            self.write_row_group()?;
+        }
+        Ok(self.buffer.take())
+    }
+
+    /// Writes the remaining rows and the file footer.
+    pub(crate) fn finish(mut self) -> Result<Vec<u8>, ExportError> {
+        if self.values.first().map_or(0, Values::len) > 0 {
+            self.write_row_group()?;
+        }
+        self.writer.close()?;
+        Ok(self.buffer.take())
+    }
+
+    fn write_row_group(&mut self) -> Result<(), ExportError> {
+        let mut row_group = self.writer.next_row_group()?;
+        for values in &mut self.values {
+            let Some(mut column) = row_group.next_column()? else {
+                break;
+            };
+            match values {
+                Values::Text(values) => {
+                    column
+                        .typed::<ByteArrayType>()
+                        .write_batch(values, None, None)?;
+                    values.clear();
+                }
+                Values::Int(values) => {
+                    column
+                        .typed::<Int32Type>()
+                        .write_batch(values, None, None)?;
+                    values.clear();
+                }
+                Values::BigInt(values) => {
+                    column
+                        .typed::<Int64Type>()
+                        .write_batch(values, None, None)?;
+                    values.clear();
+                }
+            }
+            column.close()?;
+        }
+        row_group.close()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encode::tests::order;
+    use crate::{Encoder, ExportFormat, RowLayout};
+    use model::PiiView;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::RowAccessor;
+
+    #[test]
+    fn test_parquet_round_trip() {
+        let mut encoder =
+            Encoder::new(ExportFormat::Parquet, RowLayout::Item, PiiView::Masked).unwrap();
+        let mut out = encoder.encode(&[order(), order()]).unwrap();
+        assert!(
+            out.len() < 8,
+            "rows are buffered until the row group is full"
+        );
+        out.extend(encoder.finish().unwrap());
+
+        let reader = SerializedFileReader::new(bytes::Bytes::from(out)).unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 4);
+        let schema = reader.metadata().file_metadata().schema_descr_ptr();
+        let column = |name: &str| {
+            (0..schema.num_columns())
+                .find(|i| schema.column(*i).name() == name)
+                .unwrap()
+        };
+        let rows: Vec<_> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            rows[0].get_string(column("delivery_phone")).unwrap(),
+            "***0000"
+        );
+        assert_eq!(
+            rows[1].get_string(column("item_name")).unwrap(),
+            "Lipstick, red"
+        );
+        assert_eq!(rows[3].get_int(column("item_chrt_id")).unwrap(), 9934931);
+    }
+}
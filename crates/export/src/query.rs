@@ -0,0 +1,172 @@
+//! Paged reads of the orders to export.
+
+use crate::{ExportError, ExportFilter};
+use chrono::{DateTime, Utc};
+use deadpool_postgres::Pool;
+use model::{Delivery, Item, Order, Payment};
+use std::collections::HashMap;
+use tracing::{debug, instrument};
+
+/// Orders with their delivery and payment, after the keyset `($6, $7)`.
+const ORDERS_PAGE: &str = r#"
+    SELECT o.order_uid, o.track_number, o.entry, o.locale, o.internal_signature,
+           o.customer_id, o.delivery_service, o.shardkey, o.sm_id, o.date_created,
+           o.oof_shard, o.source_topic,
+           d.name, d.phone, d.zip, d.city, d.address, d.region, d.email,
+           p.transaction, p.request_id, p.currency, p.provider, p.amount, p.payment_dt,
+           p.bank, p.delivery_cost, p.goods_total, p.custom_fee
+    FROM orders o
+    JOIN deliveries d ON d.order_uid = o.order_uid
+    JOIN payments p ON p.order_uid = o.order_uid
+    WHERE ($1::timestamptz IS NULL OR o.date_created >= $1)
+      AND ($2::timestamptz IS NULL OR o.date_created < $2)
+      AND (cardinality($3::text[]) = 0 OR lower(o.delivery_service) = ANY($3))
+      AND (cardinality($4::text[]) = 0 OR lower(p.currency) = ANY($4))
+      AND ($5::text IS NULL OR o.customer_id = $5)
+      AND ($6::timestamptz IS NULL OR (o.date_created, o.order_uid) > ($6, $7::text))
+    ORDER BY o.date_created, o.order_uid
+    LIMIT $8
+"#;
+
+/// Items of a page of orders, in insertion order.
+const PAGE_ITEMS: &str = r#"
+    SELECT order_uid, chrt_id, track_number, price, rid, name, sale, size, total_price,
+           nm_id, brand, status
+    FROM items WHERE order_uid = ANY($1)
+    ORDER BY id
+"#;
+
+/// Reads the orders matching a filter, oldest first, one page at a time.
+///
+/// Pages continue after the last order of the previous page rather than at an
+/// offset, so every page costs the same. Each page is read on its own, without
+/// a transaction spanning the export: orders saved during an export are
+/// included if they sort after the current page.
+pub struct OrderPages {
+    pool: Pool,
+    filter: ExportFilter,
+    delivery_services: Vec<String>,
+    currencies: Vec<String>,
+    page_size: i64,
+    /// `date_created` and `order_uid` of the last order read.
+    after: Option<(DateTime<Utc>, String)>,
+    done: bool,
+}
+
+impl OrderPages {
+    /// Reads orders matching `filter` from `pool`, `page_size` orders at a time.
+    pub fn new(pool: Pool, filter: ExportFilter, page_size: usize) -> Self {
+        let lowercase = |values: &[String]| -> Vec<String> {
+            values.iter().map(|v| v.trim().to_lowercase()).collect()
+        };
+        Self {
+            delivery_services: lowercase(&filter.delivery_services),
+            currencies: lowercase(&filter.currencies),
+            pool,
+            filter,
+            page_size: page_size.max(1) as i64,
+            after: None,
+            done: false,
+        }
+    }
+
+    /// Reads the next page; `None` once all matching orders were read.
+    #[instrument(name = "export.next_page", skip_all)]
+    pub async fn next_page(&mut self) -> Result<Option<Vec<Order>>, ExportError> {
+        if self.done {
+            return Ok(None);
+        }
+        let client = self.pool.get().await?;
+        let (after_date, after_uid) = match &self.after {
+            Some((date, uid)) => (Some(*date), Some(uid.as_str())),
+            None => (None, None),
+        };
+        let rows = client
+            .query(
+                ORDERS_PAGE,
+                &[
+                    &self.filter.from,
+                    &self.filter.to,
+                    &self.delivery_services,
+                    &self.currencies,
+                    &self.filter.customer_id,
+                    &after_date,
+                    &after_uid,
+                    &self.page_size,
+                ],
+            )
+            .await?;
+        if (rows.len() as i64) < self.page_size {
+            self.done = true;
+        }
+        if rows.is_empty() {
+            return Ok(None);
+        }
+
+        let mut orders: Vec<Order> = rows
+            .iter()
+            .map(|row| Order {
+                order_uid: row.get("order_uid"),
+                track_number: row.get("track_number"),
+                entry: row.get("entry"),
+                delivery: Delivery {
+                    name: row.get("name"),
+                    phone: row.get("phone"),
+                    zip: row.get("zip"),
+                    city: row.get("city"),
+                    address: row.get("address"),
+                    region: row.get("region"),
+                    email: row.get("email"),
+                },
+                payment: Payment {
+                    transaction: row.get("transaction"),
+                    request_id: row.get("request_id"),
+                    currency: row.get("currency"),
+                    provider: row.get("provider"),
+                    amount: row.get("amount"),
+                    payment_dt: row.get("payment_dt"),
+                    bank: row.get("bank"),
+                    delivery_cost: row.get("delivery_cost"),
+                    goods_total: row.get("goods_total"),
+                    custom_fee: row.get("custom_fee"),
+                },
+                items: Vec::new(),
+                locale: row.get("locale"),
+                internal_signature: row.get("internal_signature"),
+                customer_id: row.get("customer_id"),
+                delivery_service: row.get("delivery_service"),
+                shardkey: row.get("shardkey"),
+                sm_id: row.get("sm_id"),
+                date_created: row.get("date_created"),
+                oof_shard: row.get("oof_shard"),
+                source_topic: row.get("source_topic"),
+            })
+            .collect();
+
+        let uids: Vec<&str> = orders.iter().map(|o| o.order_uid.as_str()).collect();
+        let mut items: HashMap<String, Vec<Item>> = HashMap::new();
+        for row in client.query(PAGE_ITEMS, &[&uids]).await? {
+            items.entry(row.get("order_uid")).or_default().push(Item {
+                chrt_id: row.get("chrt_id"),
+                track_number: row.get("track_number"),
+                price: row.get("price"),
+                rid: row.get("rid"),
+                name: row.get("name"),
+                sale: row.get("sale"),
+                size: row.get("size"),
+                total_price: row.get("total_price"),
+                nm_id: row.get("nm_id"),
+                brand: row.get("brand"),
+                status: row.get("status"),
+            });
+        }
+        for order in &mut orders {
+            order.items = items.remove(&order.order_uid).unwrap_or_default();
+        }
+
+        let last = orders.last().expect("the page is not empty");
+        self.after = Some((last.date_created, last.order_uid.clone()));
+        debug!("Read a page of {} orders to export", orders.len());
+        Ok(Some(orders))
+    }
+}
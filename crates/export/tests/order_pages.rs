@@ -0,0 +1,229 @@
+//! Paged reads of `OrderPages` against a real database.
+//!
+//! The tests are ignored unless run with `--ignored`; see `test_db` for the required
+//! `DATABASE_URL`. Every test owns the orders of its own `customer_id`, filters on it
+//! and deletes its rows afterwards.
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use deadpool_postgres::Pool;
+use export::{ExportFilter, OrderPages};
+use model::Order;
+use repository::{
+    DeliveriesRepository, ItemsRepository, OrdersRepository, PaymentsRepository,
+    PgDeliveriesRepository, PgItemsRepository, PgOrdersRepository, PgPaymentsRepository,
+};
+use test_db::{database_url, pool};
+use tokio_postgres::Client;
+
+/// Writes test orders the way the service stores them.
+struct Store {
+    orders: PgOrdersRepository,
+    deliveries: PgDeliveriesRepository,
+    payments: PgPaymentsRepository,
+    items: PgItemsRepository,
+}
+
+impl Store {
+    /// Stores an order of `customer` with two items.
+    async fn order(
+        &self,
+        customer: &str,
+        suffix: &str,
+        created: DateTime<Utc>,
+        delivery_service: &str,
+        currency: &str,
+    ) {
+        let uid = format!("{customer}-{suffix}");
+        let mut order = test_db::order(&uid, 2);
+        order.customer_id = customer.to_string();
+        order.delivery_service = delivery_service.to_string();
+        order.date_created = created;
+        order.payment.currency = currency.to_string();
+        self.orders.insert(&order).await.unwrap();
+        self.deliveries.insert(&order.delivery, &uid).await.unwrap();
+        self.payments.insert(&order.payment, &uid).await.unwrap();
+        self.items.insert(&order.items, &uid).await.unwrap();
+    }
+}
+
+/// Connects to `DATABASE_URL`.
+async fn connect() -> (Pool, Store, Client) {
+    let dsn = database_url();
+    let store = Store {
+        orders: PgOrdersRepository::new(test_db::connect(&dsn).await),
+        deliveries: PgDeliveriesRepository::new(test_db::connect(&dsn).await),
+        payments: PgPaymentsRepository::new(test_db::connect(&dsn).await),
+        items: PgItemsRepository::new(test_db::connect(&dsn).await),
+    };
+    (pool(&dsn), store, test_db::connect(&dsn).await)
+}
+
+fn base_time() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap()
+}
+
+async fn cleanup(db: &Client, customer: &str) {
+    for table in ["items", "payments", "deliveries"] {
+        db.execute(
+            &format!(
+                "DELETE FROM {table} WHERE order_uid IN
+                 (SELECT order_uid FROM orders WHERE customer_id = $1)"
+            ),
+            &[&customer],
+        )
+        .await
+        .unwrap();
+    }
+    db.execute("DELETE FROM orders WHERE customer_id = $1", &[&customer])
+        .await
+        .unwrap();
+}
+
+/// Reads all pages, returning the size of each and the orders in read order.
+async fn read_all(pool: &Pool, filter: ExportFilter, page_size: usize) -> (Vec<usize>, Vec<Order>) {
+    let mut pages = OrderPages::new(pool.clone(), filter, page_size);
+    let mut sizes = Vec::new();
+    let mut orders = Vec::new();
+    while let Some(page) = pages.next_page().await.unwrap() {
+        sizes.push(page.len());
+        orders.extend(page);
+    }
+    (sizes, orders)
+}
+
+fn uids(orders: &[Order]) -> Vec<&str> {
+    orders.iter().map(|o| o.order_uid.as_str()).collect()
+}
+
+#[tokio::test]
+#[ignore = "needs a migrated database in DATABASE_URL"]
+async fn test_pages_continue_after_the_last_order() {
+    let (pool, store, db) = connect().await;
+    let customer = format!("export-pages-{}", std::process::id());
+    cleanup(&db, &customer).await;
+    let t = base_time();
+    // Inserted out of order; "b" and "c" share a timestamp across a page boundary
+    store
+        .order(&customer, "d", t + Duration::hours(3), "dhl", "USD")
+        .await;
+    store
+        .order(&customer, "c", t + Duration::hours(1), "dhl", "USD")
+        .await;
+    store.order(&customer, "a", t, "dhl", "USD").await;
+    store
+        .order(&customer, "e", t + Duration::hours(4), "dhl", "USD")
+        .await;
+    store
+        .order(&customer, "b", t + Duration::hours(1), "dhl", "USD")
+        .await;
+
+    let filter = ExportFilter {
+        customer_id: Some(customer.clone()),
+        ..Default::default()
+    };
+    let (sizes, orders) = read_all(&pool, filter.clone(), 2).await;
+    assert_eq!(sizes, [2, 2, 1]);
+    let expected: Vec<String> = ["a", "b", "c", "d", "e"]
+        .iter()
+        .map(|s| format!("{customer}-{s}"))
+        .collect();
+    assert_eq!(uids(&orders), expected);
+    for order in &orders {
+        assert_eq!(order.delivery.name, "Test User");
+        assert_eq!(order.payment.transaction, order.order_uid);
+        let rids: Vec<String> = order.items.iter().map(|i| i.rid.clone()).collect();
+        assert_eq!(
+            rids,
+            [
+                format!("{}-0", order.order_uid),
+                format!("{}-1", order.order_uid)
+            ]
+        );
+    }
+
+    // A page size dividing the count exactly ends with an empty read
+    let (sizes, _) = read_all(&pool, filter, 5).await;
+    assert_eq!(sizes, [5]);
+    cleanup(&db, &customer).await;
+}
+
+#[tokio::test]
+#[ignore = "needs a migrated database in DATABASE_URL"]
+async fn test_filters_select_matching_orders() {
+    let (pool, store, db) = connect().await;
+    let customer = format!("export-filters-{}", std::process::id());
+    cleanup(&db, &customer).await;
+    let t = base_time();
+    store.order(&customer, "1", t, "DHL", "USD").await;
+    store
+        .order(&customer, "2", t + Duration::days(1), "meest", "EUR")
+        .await;
+    store
+        .order(&customer, "3", t + Duration::days(2), "dhl", "EUR")
+        .await;
+    store
+        .order(&customer, "4", t + Duration::days(3), "ups", "usd")
+        .await;
+
+    let read = |filter: ExportFilter| {
+        let pool = pool.clone();
+        let customer = customer.clone();
+        async move {
+            let filter = ExportFilter {
+                customer_id: Some(customer.clone()),
+                ..filter
+            };
+            let (_, orders) = read_all(&pool, filter, 2).await;
+            orders
+                .iter()
+                .map(|o| {
+                    o.order_uid
+                        .trim_start_matches(&format!("{customer}-"))
+                        .to_string()
+                })
+                .collect::<Vec<_>>()
+        }
+    };
+
+    assert_eq!(
+        read(ExportFilter {
+            from: Some(t + Duration::days(1)),
+            to: Some(t + Duration::days(3)),
+            ..Default::default()
+        })
+        .await,
+        ["2", "3"]
+    );
+    assert_eq!(
+        read(ExportFilter {
+            delivery_services: vec![" Dhl ".into()],
+            ..Default::default()
+        })
+        .await,
+        ["1", "3"]
+    );
+    assert_eq!(
+        read(ExportFilter {
+            currencies: vec!["USD".into(), "gbp".into()],
+            ..Default::default()
+        })
+        .await,
+        ["1", "4"]
+    );
+    assert_eq!(
+        read(ExportFilter {
+            delivery_services: vec!["dhl".into(), "ups".into()],
+            currencies: vec!["eur".into()],
+            ..Default::default()
+        })
+        .await,
+        ["3"]
+    );
+
+    let others = ExportFilter {
+        customer_id: Some(format!("{customer}-nobody")),
+        ..Default::default()
+    };
+    assert!(read_all(&pool, others, 2).await.1.is_empty());
+    cleanup(&db, &customer).await;
+}
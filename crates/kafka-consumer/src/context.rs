@@ -0,0 +1,115 @@
+//! rdkafka client context used by [`KafkaConsumer`](crate::KafkaConsumer).
+//!
+//! Starts and stops per-partition workers from inside the rebalance callback, so
+//! the queue of an assigned partition is split off before any poll can return its
+//! messages and a revocation completes only once the partition's worker has
+//! stopped and committed. Also exports the per-partition consumer lag reported by
+//! librdkafka statistics.
+
+use prometheus::IntGaugeVec;
+use rdkafka::Statistics;
+use rdkafka::client::ClientContext;
+use rdkafka::consumer::{BaseConsumer, ConsumerContext, Rebalance};
+use rdkafka::topic_partition_list::TopicPartitionList;
+use std::sync::{Arc, Mutex, PoisonError};
+use tracing::{info, warn};
+
+/// A topic/partition pair.
+pub(crate) type TopicPartition = (String, i32);
+
+/// Starts and stops the workers of assigned partitions.
+///
+/// Called from the rebalance callback, on the thread polling the main queue.
+pub(crate) trait PartitionWorkers: Send + Sync {
+    /// Splits off the queues of newly assigned partitions and starts their workers.
+    fn assigned(&self, partitions: Vec<TopicPartition>);
+    /// Stops the workers of revoked partitions, returning once they have committed
+    /// what they processed.
+    fn revoked(&self, partitions: Vec<TopicPartition>);
+}
+
+/// Consumer context that manages partition workers on rebalances and records partition lag.
+pub(crate) struct OrderConsumerContext {
+    /// Set while per-partition workers run; rebalances are only logged otherwise.
+    workers: Mutex<Option<Arc<dyn PartitionWorkers>>>,
+    partition_lag: IntGaugeVec,
+}
+
+impl OrderConsumerContext {
+    pub fn new(partition_lag: IntGaugeVec) -> Self {
+        Self {
+            workers: Mutex::new(None),
+            partition_lag,
+        }
+    }
+
+    /// Hands rebalances to `workers` from now on, or stops doing so with `None`.
+    pub fn set_workers(&self, workers: Option<Arc<dyn PartitionWorkers>>) {
+        *self.workers.lock().unwrap_or_else(PoisonError::into_inner) = workers;
+    }
+
+    fn workers(&self) -> Option<Arc<dyn PartitionWorkers>> {
+        // Cloned out, so the lock is not held while workers start or stop
+        self.workers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+fn partitions(tpl: &TopicPartitionList) -> Vec<TopicPartition> {
+    tpl.elements()
+        .iter()
+        .map(|e| (e.topic().to_string(), e.partition()))
+        .collect()
+}
+
+impl ClientContext for OrderConsumerContext {
+    fn stats(&self, statistics: Statistics) {
+        for (topic, stats) in &statistics.topics {
+            for (partition, stats) in &stats.partitions {
+                // librdkafka reports the internal "unassigned" partition as -1, and a lag
+                // of -1 when it is not known yet.
+                if *partition < 0 || stats.consumer_lag < 0 {
+                    continue;
+                }
+                self.partition_lag
+                    .with_label_values(&[topic.as_str(), &partition.to_string()])
+                    .set(stats.consumer_lag);
+            }
+        }
+    }
+}
+
+impl ConsumerContext for OrderConsumerContext {
+    fn pre_rebalance(&self, _consumer: &BaseConsumer<Self>, rebalance: &Rebalance<'_>) {
+        if let Rebalance::Revoke(tpl) = rebalance {
+            info!("Kafka partitions revoked: {:?}", partitions(tpl));
+            for (topic, partition) in partitions(tpl) {
+                // Another consumer reports the lag of a revoked partition from now on.
+                let _ = self
+                    .partition_lag
+                    .remove_label_values(&[topic.as_str(), &partition.to_string()]);
+            }
+            // Blocks the rebalance until the workers are done with the partitions.
+            if let Some(workers) = self.workers() {
+                workers.revoked(partitions(tpl));
+            }
+        }
+    }
+
+    fn post_rebalance(&self, _consumer: &BaseConsumer<Self>, rebalance: &Rebalance<'_>) {
+        match rebalance {
+            Rebalance::Assign(tpl) => {
+                info!("Kafka partitions assigned: {:?}", partitions(tpl));
+                // Assigning resets partition queues, so they are split after it, still
+                // before the poll serving this callback returns.
+                if let Some(workers) = self.workers() {
+                    workers.assigned(partitions(tpl));
+                }
+            }
+            Rebalance::Revoke(_) => {}
+            Rebalance::Error(e) => warn!("Kafka rebalance error: {e}"),
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//! Payload decoders turning Kafka message payloads into [`Order`]s.
+//!
+//! Each topic subscription names the decoder for its payload format; decoders
+//! are looked up by that name with [`build_decoder`]. A message may override its
+//! subscription's decoder with a `content-type` header naming a self-describing
+//! format (see [`decoder_for_content_type`]).
+
+use anyhow::{Context, Result, bail};
+use async_trait::async_trait;
+use codec::avro::{check_compatible, decode_order, order_schema};
+use codec::confluent::unframe;
+use codec::registry::SchemaRegistry;
+use codec::{CONTENT_TYPE_JSON, CONTENT_TYPE_PROTOBUF};
+use model::Order;
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+
+/// Decodes a message payload into an order.
+#[async_trait]
+pub trait OrderDecoder: Send + Sync {
+    /// Name used to select the decoder in the subscription config.
+    fn name(&self) -> &'static str;
+
+    /// Decodes one message payload.
+    async fn decode(&self, payload: &[u8]) -> Result<Order>;
+}
+
+/// Decoder for JSON-encoded orders.
+pub struct JsonDecoder;
+
+#[async_trait]
+impl OrderDecoder for JsonDecoder {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    async fn decode(&self, payload: &[u8]) -> Result<Order> {
+        Ok(serde_json::from_slice(payload)?)
+    }
+}
+
+/// Decoder for protobuf-encoded orders (`schemas/order.proto`).
+pub struct ProtobufDecoder;
+
+#[async_trait]
+impl OrderDecoder for ProtobufDecoder {
+    fn name(&self) -> &'static str {
+        "protobuf"
+    }
+
+    async fn decode(&self, payload: &[u8]) -> Result<Order> {
+        Ok(codec::protobuf::decode_order(payload)?)
+    }
+}
+
+/// Decoder for Avro-encoded orders in the Confluent schema registry wire format.
+///
+/// The writer's schema is looked up in the registry by the id in the message
+/// header and must be compatible with the order schema checked into the repo;
+/// each schema id is checked once.
+pub struct AvroDecoder {
+    registry: Arc<SchemaRegistry>,
+    compatible: RwLock<HashSet<u32>>,
+}
+
+impl AvroDecoder {
+    pub fn new(registry: Arc<SchemaRegistry>) -> Self {
+        Self {
+            registry,
+            compatible: RwLock::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl OrderDecoder for AvroDecoder {
+    fn name(&self) -> &'static str {
+        "avro"
+    }
+
+    async fn decode(&self, payload: &[u8]) -> Result<Order> {
+        let (schema_id, datum) = unframe(payload)?;
+        let writer = self.registry.schema_by_id(schema_id).await?;
+        if !self.compatible.read().unwrap().contains(&schema_id) {
+            check_compatible(order_schema(), &writer)
+                .with_context(|| format!("Writer schema {schema_id} is not compatible"))?;
+            self.compatible.write().unwrap().insert(schema_id);
+        }
+        Ok(decode_order(datum, &writer)?)
+    }
+}
+
+/// Returns the decoder registered under `name`.
+///
+/// # Errors
+/// Returns an error if no decoder has that name, or if the decoder needs a
+/// schema registry and none is configured.
+pub fn build_decoder(
+    name: &str,
+    schema_registry: Option<&Arc<SchemaRegistry>>,
+) -> Result<Arc<dyn OrderDecoder>> {
+    match name {
+        "json" => Ok(Arc::new(JsonDecoder)),
+        "protobuf" => Ok(Arc::new(ProtobufDecoder)),
+        "avro" => {
+            let registry =
+                schema_registry.context("The avro decoder requires a schema registry URL")?;
+            Ok(Arc::new(AvroDecoder::new(registry.clone())))
+        }
+        other => bail!("Unknown payload decoder '{other}'"),
+    }
+}
+
+/// Returns the decoder for a content type header value, if it names a format that
+/// can be decoded without further configuration.
+///
+/// Parameters such as `; charset=utf-8` are ignored.
+pub fn decoder_for_content_type(content_type: &str) -> Option<&'static dyn OrderDecoder> {
+    let mime = content_type.split(';').next().unwrap_or_default().trim();
+    if mime.eq_ignore_ascii_case(CONTENT_TYPE_JSON) {
+        Some(&JsonDecoder)
+    } else if mime.eq_ignore_ascii_case(CONTENT_TYPE_PROTOBUF) {
+        Some(&ProtobufDecoder)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decoder_for_content_type() {
+        let name = |ct| decoder_for_content_type(ct).map(|d| d.name());
+        assert_eq!(name("application/json; charset=utf-8"), Some("json"));
+        assert_eq!(name("Application/X-Protobuf"), Some("protobuf"));
+        assert_eq!(name("text/plain"), None);
+    }
+}
@@ -1,68 +1,432 @@
 //! Kafka consumer for ingesting orders and persisting them via OrderService.
 //!
-//! Reads JSON-encoded order messages from a Kafka topic, saves them to the DB
+//! Reads order messages from one or more Kafka topics, saves them to the DB
 //! using `OrderService`, and updates the in-memory cache.
+//!
+//! Every subscription (a topic name or a `^`-prefixed regex pattern) names the
+//! [`OrderDecoder`] for its payload format and the [`ValidationProfile`] its
+//! orders must pass. The topic a message came from is recorded on the order as
+//! `source_topic`.
+//!
+//! Messages are processed either one at a time, each in its own DB transaction,
+//! or in batch mode: up to N messages (or whatever arrives within T milliseconds)
+//! are saved in one transaction with per-order savepoints, and their offsets are
+//! committed once the batch is persisted. A batch the database rejects as a whole
+//! (e.g. during an outage) is not committed: its partitions are rewound and it is
+//! consumed again after a pause.
+//!
+//! By default all partitions are consumed sequentially by one loop. With
+//! per-partition workers enabled, every assigned partition gets its own queue and
+//! worker task, which keeps per-key ordering (a key always maps to one partition)
+//! while processing different partitions in parallel. Workers are started and
+//! stopped inside the rebalance callback: an assigned partition's queue is split
+//! off before any of its messages can be polled, and a revocation waits until the
+//! partition's worker has finished and committed. A semaphore bounds how many
+//! workers touch the database at the same time.
+//!
+//! Messages that cannot be decoded or saved are counted by failure reason and,
+//! when a dead-letter topic is configured, forwarded there together with headers
+//! describing where they came from and why they failed. Throughput, latency and
+//! per-partition lag metrics are registered in the shared Prometheus registry.
+//!
+//! [`replay`] re-processes a bounded range of the topic under a separate consumer
+//! group, for reprocessing history after a persistence bug has been fixed.
+
+mod context;
+mod decoder;
+mod metrics;
+mod replay;
 
 use anyhow::Result;
-use cache::OrderCache;
+use cache::{OrderCache, OrderFeed};
+use context::{OrderConsumerContext, PartitionWorkers, TopicPartition};
+use metrics::ConsumerMetrics;
 use model::Order;
+use prometheus::Registry;
 use rdkafka::config::ClientConfig;
-use rdkafka::consumer::{Consumer, StreamConsumer};
-use rdkafka::error::KafkaError;
-use rdkafka::message::{BorrowedMessage, Message};
-use serde_json::from_slice;
-use service::OrderService;
-use std::sync::Arc;
-use tokio_stream::StreamExt;
-use tracing::{debug, error, info};
+use rdkafka::consumer::{CommitMode, Consumer, StreamConsumer};
+use rdkafka::error::{KafkaError, KafkaResult};
+use rdkafka::message::{BorrowedMessage, Header, Headers, Message, OwnedHeaders};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::{Offset, TopicPartitionList};
+use regex::Regex;
+use service::{OrderService, ValidationProfile};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError, mpsc};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use telemetry::REQUEST_ID_HEADER;
+use tokio::runtime::Handle;
+use tokio::sync::{Notify, Semaphore, SemaphorePermit};
+use tokio_stream::{Stream, StreamExt};
+use tracing::{Instrument, debug, error, info, info_span, warn};
+
+pub use codec::{CONTENT_TYPE_HEADER, CONTENT_TYPE_JSON, CONTENT_TYPE_PROTOBUF};
+pub use decoder::{
+    AvroDecoder, JsonDecoder, OrderDecoder, ProtobufDecoder, build_decoder,
+    decoder_for_content_type,
+};
+pub use replay::{ReplayBound, ReplayConfig, ReplayReport, replay};
+
+/// Number of attempts to persist a batch before it is rewound and consumed again.
+const BATCH_SAVE_ATTEMPTS: u32 = 3;
+
+/// Pause after rewinding a batch that could not be saved, before it is consumed again.
+const BATCH_RETRY_BACKOFF: Duration = Duration::from_secs(5);
+
+/// How long to wait for a seek back to a message to be applied.
+const SEEK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait for the dead-letter topic to acknowledge a message.
+const DLQ_SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Settings for batch consumption mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchConfig {
+    /// Maximum number of messages collected into one batch.
+    pub max_messages: usize,
+    /// Maximum time to wait for a batch to fill up after its first message arrives.
+    pub max_wait: Duration,
+}
+
+/// A topic (or topic pattern) to consume, with the format and checks of its payloads.
+#[derive(Clone)]
+pub struct Subscription {
+    /// Topic name, or a regex pattern if it starts with `^`.
+    pub topic: String,
+    /// Decoder for the topic's payload format.
+    pub decoder: Arc<dyn OrderDecoder>,
+    /// Extra checks for orders from this topic.
+    pub validation: ValidationProfile,
+    /// The compiled topic pattern, for topics starting with `^`.
+    pattern: Option<Regex>,
+}
+
+impl fmt::Debug for Subscription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subscription")
+            .field("topic", &self.topic)
+            .field("decoder", &self.decoder.name())
+            .field("validation", &self.validation)
+            .finish()
+    }
+}
+
+impl Subscription {
+    /// Creates a subscription, compiling `topic` if it is a pattern.
+    ///
+    /// # Errors
+    /// Returns an error if `topic` starts with `^` but is not a valid regex.
+    pub fn new(
+        topic: impl Into<String>,
+        decoder: Arc<dyn OrderDecoder>,
+        validation: ValidationProfile,
+    ) -> Result<Self> {
+        let topic = topic.into();
+        let pattern = if topic.starts_with('^') {
+            Some(Regex::new(&topic)?)
+        } else {
+            None
+        };
+        Ok(Self {
+            topic,
+            decoder,
+            validation,
+            pattern,
+        })
+    }
+
+    /// Whether a message from `topic` belongs to this subscription.
+    pub fn matches(&self, topic: &str) -> bool {
+        match &self.pattern {
+            Some(pattern) => pattern.is_match(topic),
+            None => self.topic == topic,
+        }
+    }
+}
+
+/// Connection and processing settings for [`KafkaConsumer`].
+#[derive(Debug, Clone)]
+pub struct ConsumerConfig {
+    /// Kafka bootstrap brokers.
+    pub brokers: Vec<String>,
+    /// Topics to subscribe to; the first matching subscription handles a message.
+    pub subscriptions: Vec<Subscription>,
+    /// Consumer group ID.
+    pub group_id: String,
+    /// Batch mode settings; `None` processes messages one at a time.
+    pub batch: Option<BatchConfig>,
+    /// Maximum number of partitions processed concurrently by per-partition workers;
+    /// `None` consumes all partitions sequentially in a single loop.
+    pub partition_workers: Option<usize>,
+    /// Topic that receives messages which could not be processed; `None` drops them.
+    pub dlq_topic: Option<String>,
+    /// How often librdkafka reports statistics, used for the partition lag metric.
+    pub statistics_interval: Duration,
+}
+
+/// Why a message could not be processed; used as the `reason` metric label.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FailureReason {
+    EmptyPayload,
+    /// No subscription matches the message's topic.
+    NoRoute,
+    Deserialize,
+    Validation,
+    Db,
+}
+
+impl FailureReason {
+    fn as_str(self) -> &'static str {
+        match self {
+            FailureReason::EmptyPayload => "empty_payload",
+            FailureReason::NoRoute => "no_route",
+            FailureReason::Deserialize => "deserialize",
+            FailureReason::Validation => "validation",
+            FailureReason::Db => "db",
+        }
+    }
+}
+
+/// A consumed message, decoded and detached from the consumer's buffers.
+struct DecodedMessage {
+    topic: String,
+    partition: i32,
+    offset: i64,
+    /// Producer or broker timestamp, in milliseconds since the epoch.
+    timestamp_ms: Option<i64>,
+    key: Option<Vec<u8>>,
+    /// Raw payload, kept for the dead-letter topic.
+    payload: Option<Vec<u8>>,
+    /// Message headers, kept for trace context and request id propagation.
+    headers: Vec<(String, Vec<u8>)>,
+    /// The decoded order, or why the payload could not be decoded.
+    order: Result<Order, (FailureReason, String)>,
+}
+
+impl DecodedMessage {
+    /// The message's headers, as read by the trace context propagator.
+    fn trace_headers(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.headers.iter().map(|(k, v)| (k.as_str(), v.as_slice()))
+    }
+
+    /// Id of the HTTP request that produced the message, if it came from the API.
+    fn request_id(&self) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k == REQUEST_ID_HEADER)
+            .and_then(|(_, v)| std::str::from_utf8(v).ok())
+    }
+}
+
+/// A running per-partition worker task.
+struct PartitionWorker {
+    stop: Arc<Notify>,
+    /// Disconnected once the task has ended; nothing is ever sent.
+    stopped: mpsc::Receiver<()>,
+}
 
 /// KafkaConsumer wraps the underlying StreamConsumer and business dependencies.
 pub struct KafkaConsumer<S: OrderService + Send + Sync + 'static> {
-    consumer: StreamConsumer,
+    consumer: Arc<StreamConsumer<OrderConsumerContext>>,
+    subscriptions: Vec<Subscription>,
     order_service: Arc<S>,
     order_cache: Arc<OrderCache>,
+    batch: Option<BatchConfig>,
+    /// Bounds concurrent processing; set if and only if per-partition workers are enabled.
+    concurrency: Option<Semaphore>,
+    /// Producer and topic for unprocessable messages.
+    dlq: Option<(FutureProducer, String)>,
+    metrics: ConsumerMetrics,
+    /// Live feed receiving every saved order.
+    feed: Option<Arc<OrderFeed>>,
 }
 
 impl<S: OrderService + Send + Sync + 'static> KafkaConsumer<S> {
-    /// Create a new Kafka consumer for the specified brokers/topic/group.
+    /// Create a new Kafka consumer for the configured brokers/subscriptions/group.
+    ///
+    /// In batch mode auto-commit is disabled and offsets are committed per batch.
+    /// Consumer metrics are registered in `registry`.
     pub fn new(
-        brokers: &[String],
-        topic: &str,
-        group_id: &str,
+        config: &ConsumerConfig,
         order_service: Arc<S>,
         order_cache: Arc<OrderCache>,
-    ) -> Result<Self, KafkaError> {
-        let consumer: StreamConsumer = ClientConfig::new()
-            .set("bootstrap.servers", brokers.join(","))
-            .set("group.id", group_id)
+        registry: &Registry,
+    ) -> Result<Self> {
+        let auto_commit = if config.batch.is_some() {
+            "false"
+        } else {
+            "true"
+        };
+        let metrics = ConsumerMetrics::new(registry)?;
+        let context = OrderConsumerContext::new(metrics.partition_lag.clone());
+        let consumer: StreamConsumer<OrderConsumerContext> = ClientConfig::new()
+            .set("bootstrap.servers", config.brokers.join(","))
+            .set("group.id", &config.group_id)
             .set("enable.partition.eof", "false")
             .set("auto.offset.reset", "earliest")
-            .set("enable.auto.commit", "true")
-            .create()?;
+            .set("enable.auto.commit", auto_commit)
+            .set(
+                "statistics.interval.ms",
+                config.statistics_interval.as_millis().to_string(),
+            )
+            .create_with_context(context)?;
 
-        consumer.subscribe(&[topic])?;
+        let dlq = match &config.dlq_topic {
+            Some(topic) => {
+                let producer: FutureProducer = ClientConfig::new()
+                    .set("bootstrap.servers", config.brokers.join(","))
+                    .set("message.timeout.ms", "5000")
+                    .create()?;
+                Some((producer, topic.clone()))
+            }
+            None => None,
+        };
+
+        let topics: Vec<&str> = config
+            .subscriptions
+            .iter()
+            .map(|s| s.topic.as_str())
+            .collect();
+        consumer.subscribe(&topics)?;
         Ok(Self {
-            consumer,
+            consumer: Arc::new(consumer),
+            subscriptions: config.subscriptions.clone(),
             order_service,
             order_cache,
+            batch: config.batch,
+            concurrency: config
+                .partition_workers
+                .map(|workers| Semaphore::new(workers.max(1))),
+            dlq,
+            metrics,
+            feed: None,
         })
     }
 
+    /// Publishes every saved and cached order to `feed`.
+    pub fn with_feed(mut self, feed: Arc<OrderFeed>) -> Self {
+        self.feed = Some(feed);
+        self
+    }
+
+    /// Caches a saved order, then publishes it to the live feed, so subscribers
+    /// can already look it up when they are notified.
+    async fn cache_saved(&self, order: Order) {
+        match &self.feed {
+            Some(feed) => {
+                self.order_cache.set(order.clone()).await;
+                feed.publish(order);
+            }
+            None => self.order_cache.set(order).await,
+        }
+    }
+
     /// Runs the main consumption loop until the given context is cancelled.
     ///
     /// # Arguments
     /// * `shutdown`: a signal for graceful shutdown (e.g., tokio::sync::Notify).
-    pub async fn run(&self, shutdown: Arc<tokio::sync::Notify>) -> Result<()> {
+    pub async fn run(self: Arc<Self>, shutdown: Arc<Notify>) -> Result<()> {
+        if self.concurrency.is_some() {
+            self.run_partitioned(shutdown).await
+        } else {
+            self.consume(self.consumer.stream(), &shutdown).await;
+            Ok(())
+        }
+    }
+
+    /// Runs one worker per assigned partition, started and stopped by rebalances.
+    ///
+    /// The main queue then only serves rebalance callbacks and events. It is polled
+    /// on a blocking thread, since a revocation blocks the callback until the
+    /// revoked partitions' workers have stopped.
+    async fn run_partitioned(self: Arc<Self>, shutdown: Arc<Notify>) -> Result<()> {
+        let runtime = Handle::current();
+        let workers = Arc::new(PartitionWorkerSet {
+            consumer: self.clone(),
+            runtime: runtime.clone(),
+            workers: Mutex::default(),
+        });
+        self.consumer.context().set_workers(Some(workers.clone()));
+
+        let this = self.clone();
+        let polled = tokio::task::spawn_blocking(move || {
+            runtime.block_on(this.serve_main_queue(&shutdown));
+            workers.stop_all();
+        })
+        .await;
+        // Also breaks the reference cycle through the consumer's context
+        self.consumer.context().set_workers(None);
+        Ok(polled?)
+    }
+
+    /// Polls the main queue until `shutdown` is notified.
+    ///
+    /// Messages of split partitions never arrive here. Should one have been
+    /// fetched before its partition was split anyway, it is not processed out of
+    /// order: seeking back to it fetches it again into the partition's queue.
+    async fn serve_main_queue(&self, shutdown: &Notify) {
         let mut stream = self.consumer.stream();
+        loop {
+            tokio::select! {
+                maybe_msg = stream.next() => match maybe_msg {
+                    Some(Ok(msg)) => {
+                        warn!(
+                            "Message {}/{}@{} arrived on the main queue, seeking back to it",
+                            msg.topic(),
+                            msg.partition(),
+                            msg.offset()
+                        );
+                        let offset = Offset::Offset(msg.offset());
+                        if let Err(e) = self.consumer.seek(msg.topic(), msg.partition(), offset, SEEK_TIMEOUT) {
+                            error!("Failed to seek {}/{}: {e}", msg.topic(), msg.partition());
+                        }
+                    }
+                    Some(Err(e)) => error!("Kafka error: {e}"),
+                    None => {
+                        debug!("Kafka stream ended.");
+                        break;
+                    }
+                },
+                _ = shutdown.notified() => {
+                    info!("Kafka consumer received shutdown signal.");
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Acquires a processing slot when per-partition workers are enabled.
+    async fn permit(&self) -> Option<SemaphorePermit<'_>> {
+        match &self.concurrency {
+            Some(semaphore) => semaphore.acquire().await.ok(),
+            None => None,
+        }
+    }
 
+    /// Consumes `stream` one message or one batch at a time until it ends or `stop` is notified.
+    async fn consume<'a, St>(&self, stream: St, stop: &Notify)
+    where
+        St: Stream<Item = KafkaResult<BorrowedMessage<'a>>> + Unpin,
+    {
+        match self.batch {
+            Some(batch) => self.consume_batched(batch, stream, stop).await,
+            None => self.consume_single(stream, stop).await,
+        }
+    }
+
+    /// Processes messages one at a time, each in its own DB transaction.
+    async fn consume_single<'a, St>(&self, mut stream: St, stop: &Notify)
+    where
+        St: Stream<Item = KafkaResult<BorrowedMessage<'a>>> + Unpin,
+    {
         loop {
             tokio::select! {
                 maybe_msg = stream.next() => {
                     match maybe_msg {
                         Some(Ok(msg)) => {
-                            if let Err(e) = self.handle_message(&msg).await {
-                                error!("Failed to handle Kafka message: {e}");
-                            }
+                            let _permit = self.permit().await;
+                            self.handle_message(&msg).await;
                         }
                         Some(Err(e)) => {
                             error!("Kafka error: {e}");
@@ -73,43 +437,376 @@ impl<S: OrderService + Send + Sync + 'static> KafkaConsumer<S> {
                         }
                     }
                 }
-                _ = shutdown.notified() => {
+                _ = stop.notified() => {
                     info!("Kafka consumer received shutdown signal.");
                     break;
                 }
             }
         }
-        Ok(())
     }
 
-    /// Handles a single message from Kafka: parses JSON, saves to DB, and caches.
-    async fn handle_message(&self, msg: &BorrowedMessage<'_>) -> Result<()> {
-        let payload = msg
-            .payload()
-            .ok_or_else(|| anyhow::anyhow!("Empty Kafka message payload"))?;
+    /// Collects messages into batches and persists each batch in one transaction.
+    ///
+    /// A batch is flushed when it reaches `max_messages` or `max_wait` has passed
+    /// since its first message. A partially filled batch is flushed on shutdown.
+    async fn consume_batched<'a, St>(&self, config: BatchConfig, mut stream: St, stop: &Notify)
+    where
+        St: Stream<Item = KafkaResult<BorrowedMessage<'a>>> + Unpin,
+    {
+        let max_messages = config.max_messages.max(1);
 
-        let order: Order = match from_slice(payload) {
-            Ok(order) => order,
-            Err(e) => {
-                error!("Failed to deserialize order JSON: {e}");
-                return Ok(()); // Skip bad message, don't crash
+        loop {
+            // Wait for the first message of the next batch.
+            let first = tokio::select! {
+                maybe_msg = stream.next() => match maybe_msg {
+                    Some(Ok(msg)) => self.decode(&msg).await,
+                    Some(Err(e)) => {
+                        error!("Kafka error: {e}");
+                        continue;
+                    }
+                    None => {
+                        debug!("Kafka stream ended.");
+                        break;
+                    }
+                },
+                _ = stop.notified() => {
+                    info!("Kafka consumer received shutdown signal.");
+                    break;
+                }
+            };
+
+            let started = Instant::now();
+            let deadline = tokio::time::Instant::from_std(started + config.max_wait);
+            let mut batch = vec![first];
+            let mut done = false;
+
+            while batch.len() < max_messages {
+                tokio::select! {
+                    maybe_msg = stream.next() => match maybe_msg {
+                        Some(Ok(msg)) => batch.push(self.decode(&msg).await),
+                        Some(Err(e)) => error!("Kafka error: {e}"),
+                        None => {
+                            debug!("Kafka stream ended.");
+                            done = true;
+                            break;
+                        }
+                    },
+                    _ = tokio::time::sleep_until(deadline) => break,
+                    _ = stop.notified() => {
+                        info!("Kafka consumer received shutdown signal, flushing current batch.");
+                        done = true;
+                        break;
+                    }
+                }
+            }
+
+            let _permit = self.permit().await;
+            // The last commit of a stopping worker must land before its partition is revoked
+            let mode = if done {
+                CommitMode::Sync
+            } else {
+                CommitMode::Async
+            };
+            self.process_batch(batch, started, mode).await;
+            if done {
+                break;
+            }
+        }
+    }
+
+    /// Persists a batch, updates the cache and commits the batch's offsets.
+    async fn process_batch(&self, batch: Vec<DecodedMessage>, started: Instant, mode: CommitMode) {
+        // One span for the whole batch, linked to the trace of every message in it.
+        let span = info_span!("kafka.consume_batch", batch_size = batch.len());
+        for msg in &batch {
+            telemetry::link_trace(&span, msg.trace_headers());
+        }
+        self.process_batch_inner(batch, started, mode)
+            .instrument(span)
+            .await;
+    }
+
+    /// Body of [`Self::process_batch`], run inside the batch span.
+    async fn process_batch_inner(
+        &self,
+        batch: Vec<DecodedMessage>,
+        started: Instant,
+        mode: CommitMode,
+    ) {
+        self.metrics.batch_size.observe(batch.len() as f64);
+
+        let mut saved_msgs = Vec::new();
+        let mut orders = Vec::new();
+        for msg in &batch {
+            self.metrics.consumed(&msg.topic);
+            if let Ok(order) = &msg.order {
+                saved_msgs.push(msg);
+                orders.push(order.clone());
+            }
+        }
+
+        // Saved before anything is failed or committed: if the database is down,
+        // the whole batch is consumed again instead of being skipped.
+        let results = if orders.is_empty() {
+            Vec::new()
+        } else {
+            match self.save_batch_with_retry(&orders).await {
+                Some(results) => results,
+                None => {
+                    error!(
+                        "Failed to save batch of {} messages after {BATCH_SAVE_ATTEMPTS} attempts, \
+                         rewinding to retry it",
+                        batch.len()
+                    );
+                    self.rewind(&batch);
+                    tokio::time::sleep(BATCH_RETRY_BACKOFF).await;
+                    return;
+                }
             }
         };
 
-        // Save to DB via OrderService
-        match self.order_service.save_order(&order).await {
-            Ok(()) => {
-                // Only cache the order if it was successfully saved to the database
-                self.order_cache.set(order).await;
-                info!("Order processed and cached: {}", msg.offset());
+        for msg in &batch {
+            if let Err((reason, e)) = &msg.order {
+                self.fail(msg, *reason, e).await;
             }
-            Err(e) => {
-                error!("Failed to save order to DB: {e}");
-                // Skip caching if DB save failed
+        }
+        if !orders.is_empty() {
+            let mut saved = 0;
+            for ((msg, order), result) in saved_msgs.into_iter().zip(orders).zip(results) {
+                match result {
+                    Ok(()) => {
+                        self.cache_saved(order).await;
+                        self.succeed(msg);
+                        saved += 1;
+                    }
+                    Err(e) => {
+                        error!(
+                            request_id = msg.request_id(),
+                            "Failed to save order {} to DB: {e}", order.order_uid
+                        );
+                        self.fail(msg, FailureReason::Db, &e.to_string()).await;
+                    }
+                }
             }
+            info!(
+                "Batch of {} messages processed, {saved} orders saved and cached",
+                batch.len()
+            );
         }
 
-        Ok(())
+        if let Err(e) = self.commit_offsets(&batch, mode) {
+            error!("Failed to commit batch offsets: {e}");
+        }
+        self.metrics
+            .batch_duration_seconds
+            .observe(started.elapsed().as_secs_f64());
+    }
+
+    /// Saves a batch, retrying when the batch as a whole fails (e.g. the DB is unavailable).
+    ///
+    /// Returns per-order results, or `None` if every attempt failed.
+    async fn save_batch_with_retry(
+        &self,
+        orders: &[Order],
+    ) -> Option<Vec<Result<(), service::ServiceError>>> {
+        for attempt in 1..=BATCH_SAVE_ATTEMPTS {
+            match self.order_service.save_order_batch(orders).await {
+                Ok(results) => return Some(results),
+                Err(e) => {
+                    warn!("Failed to save batch (attempt {attempt}/{BATCH_SAVE_ATTEMPTS}): {e}");
+                    if attempt < BATCH_SAVE_ATTEMPTS {
+                        tokio::time::sleep(Duration::from_secs(u64::from(attempt))).await;
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Seeks every partition in the batch back to its first message in the batch,
+    /// so the batch is consumed again; nothing is committed for it.
+    fn rewind(&self, batch: &[DecodedMessage]) {
+        let mut first_offsets: HashMap<(&str, i32), i64> = HashMap::new();
+        for msg in batch {
+            let first = first_offsets
+                .entry((msg.topic.as_str(), msg.partition))
+                .or_insert(msg.offset);
+            *first = (*first).min(msg.offset);
+        }
+
+        for ((topic, partition), offset) in first_offsets {
+            if let Err(e) =
+                self.consumer
+                    .seek(topic, partition, Offset::Offset(offset), SEEK_TIMEOUT)
+            {
+                error!("Failed to seek {topic}/{partition} back to {offset}: {e}");
+            }
+        }
+    }
+
+    /// Commits the offset following the last message of each partition in the batch.
+    fn commit_offsets(&self, batch: &[DecodedMessage], mode: CommitMode) -> Result<(), KafkaError> {
+        let mut next_offsets: HashMap<(&str, i32), i64> = HashMap::new();
+        for msg in batch {
+            let next = next_offsets
+                .entry((msg.topic.as_str(), msg.partition))
+                .or_default();
+            *next = (*next).max(msg.offset + 1);
+        }
+
+        let mut tpl = TopicPartitionList::new();
+        for ((topic, partition), offset) in next_offsets {
+            tpl.add_partition_offset(topic, partition, Offset::Offset(offset))?;
+        }
+        self.consumer.commit(&tpl, mode)
+    }
+
+    /// Decodes and validates a message according to the subscription matching its topic.
+    async fn decode(&self, msg: &BorrowedMessage<'_>) -> DecodedMessage {
+        match self.subscriptions.iter().find(|s| s.matches(msg.topic())) {
+            Some(sub) => decode_message(msg, sub.decoder.as_ref(), sub.validation).await,
+            None => {
+                let error = format!("No subscription matches topic {}", msg.topic());
+                error!("{error}");
+                let mut decoded =
+                    decode_message(msg, &JsonDecoder, ValidationProfile::Default).await;
+                decoded.order = Err((FailureReason::NoRoute, error));
+                decoded
+            }
+        }
+    }
+
+    /// Handles a single message from Kafka: decodes it, saves to DB, and caches.
+    ///
+    /// Processing runs in a span continuing the trace from the message's `traceparent`
+    /// header and carrying its `x-request-id`, if any.
+    async fn handle_message(&self, msg: &BorrowedMessage<'_>) {
+        let started = Instant::now();
+        let decoded = self.decode(msg).await;
+        let span = info_span!(
+            "kafka.consume",
+            topic = %decoded.topic,
+            partition = decoded.partition,
+            offset = decoded.offset,
+            request_id = decoded.request_id(),
+        );
+        telemetry::continue_trace(&span, decoded.trace_headers());
+        self.process_message(decoded, started)
+            .instrument(span)
+            .await;
+    }
+
+    /// Saves and caches a decoded message's order, or records why it failed.
+    async fn process_message(&self, decoded: DecodedMessage, started: Instant) {
+        self.metrics.consumed(&decoded.topic);
+
+        match &decoded.order {
+            // Save to DB via OrderService
+            Ok(order) => match self.order_service.save_order(order).await {
+                Ok(()) => {
+                    // Only cache the order if it was successfully saved to the database
+                    self.cache_saved(order.clone()).await;
+                    self.succeed(&decoded);
+                    info!("Order processed and cached: {}", decoded.offset);
+                }
+                Err(e) => {
+                    error!("Failed to save order to DB: {e}");
+                    // Skip caching if DB save failed
+                    self.fail(&decoded, FailureReason::Db, &e.to_string()).await;
+                }
+            },
+            // Skip bad message, don't crash
+            Err((reason, e)) => self.fail(&decoded, *reason, e).await,
+        }
+
+        self.metrics
+            .processing_duration_seconds
+            .observe(started.elapsed().as_secs_f64());
+    }
+
+    /// Records a message whose order was persisted and cached.
+    fn succeed(&self, msg: &DecodedMessage) {
+        self.metrics
+            .succeeded_total
+            .with_label_values(&[msg.topic.as_str()])
+            .inc();
+        if let Some(timestamp_ms) = msg.timestamp_ms {
+            let now_ms = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as i64);
+            let age_ms = (now_ms - timestamp_ms).max(0);
+            self.metrics
+                .message_age_seconds
+                .observe(age_ms as f64 / 1000.0);
+        }
+    }
+
+    /// Records a failed message and forwards it to the dead-letter topic, if one is configured.
+    async fn fail(&self, msg: &DecodedMessage, reason: FailureReason, error: &str) {
+        self.metrics
+            .failed_total
+            .with_label_values(&[msg.topic.as_str(), reason.as_str()])
+            .inc();
+
+        let Some((producer, dlq_topic)) = &self.dlq else {
+            return;
+        };
+        let partition = msg.partition.to_string();
+        let offset = msg.offset.to_string();
+        let mut headers = OwnedHeaders::new()
+            .insert(Header {
+                key: "dlq-reason",
+                value: Some(reason.as_str()),
+            })
+            .insert(Header {
+                key: "dlq-error",
+                value: Some(error),
+            })
+            .insert(Header {
+                key: "source-topic",
+                value: Some(&msg.topic),
+            })
+            .insert(Header {
+                key: "source-partition",
+                value: Some(&partition),
+            })
+            .insert(Header {
+                key: "source-offset",
+                value: Some(&offset),
+            });
+        if let Some(request_id) = msg.request_id() {
+            headers = headers.insert(Header {
+                key: REQUEST_ID_HEADER,
+                value: Some(request_id),
+            });
+        }
+        let mut record: FutureRecord<'_, [u8], [u8]> = FutureRecord::to(dlq_topic).headers(headers);
+        if let Some(key) = &msg.key {
+            record = record.key(key.as_slice());
+        }
+        if let Some(payload) = &msg.payload {
+            record = record.payload(payload.as_slice());
+        }
+
+        match producer.send(record, DLQ_SEND_TIMEOUT).await {
+            Ok(_) => {
+                self.metrics
+                    .dlq_total
+                    .with_label_values(&[msg.topic.as_str(), reason.as_str()])
+                    .inc();
+                warn!(
+                    request_id = msg.request_id(),
+                    "Message {}/{}@{} sent to dead-letter topic {dlq_topic}: {error}",
+                    msg.topic,
+                    msg.partition,
+                    msg.offset
+                );
+            }
+            Err((e, _)) => error!(
+                "Failed to send message {}/{}@{} to dead-letter topic {dlq_topic}: {e}",
+                msg.topic, msg.partition, msg.offset
+            ),
+        }
     }
 
     /// Close the consumer, flushing resources.
@@ -119,3 +816,506 @@ impl<S: OrderService + Send + Sync + 'static> KafkaConsumer<S> {
         info!("Kafka consumer closed.");
     }
 }
+
+/// The running per-partition workers, started and stopped from the rebalance callback.
+struct PartitionWorkerSet<S: OrderService + Send + Sync + 'static> {
+    consumer: Arc<KafkaConsumer<S>>,
+    runtime: Handle,
+    workers: Mutex<HashMap<TopicPartition, PartitionWorker>>,
+}
+
+impl<S: OrderService + Send + Sync + 'static> PartitionWorkerSet<S> {
+    /// Spawns a worker consuming the split-off queue of one partition.
+    fn spawn(&self, topic: &str, partition: i32) -> Option<PartitionWorker> {
+        let queue = self
+            .consumer
+            .consumer
+            .split_partition_queue(topic, partition)?;
+        let stop = Arc::new(Notify::new());
+        let (stopped_tx, stopped) = mpsc::channel();
+        let this = self.consumer.clone();
+        let worker_stop = stop.clone();
+        let label = format!("{topic}/{partition}");
+        self.runtime.spawn(async move {
+            // Dropped when the task ends, even by panicking, which wakes `stopped`
+            let _stopped = stopped_tx;
+            info!("Partition worker started for {label}");
+            this.consume(queue.stream(), &worker_stop).await;
+            info!("Partition worker stopped for {label}");
+        });
+        Some(PartitionWorker { stop, stopped })
+    }
+
+    /// Stops all workers and waits for them.
+    fn stop_all(&self) {
+        let workers: Vec<_> = self
+            .workers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .drain()
+            .map(|(_, worker)| worker)
+            .collect();
+        stop_workers(workers);
+    }
+}
+
+impl<S: OrderService + Send + Sync + 'static> PartitionWorkers for PartitionWorkerSet<S> {
+    fn assigned(&self, partitions: Vec<TopicPartition>) {
+        let mut workers = self.workers.lock().unwrap_or_else(PoisonError::into_inner);
+        for (topic, partition) in partitions {
+            if workers.contains_key(&(topic.clone(), partition)) {
+                continue;
+            }
+            match self.spawn(&topic, partition) {
+                Some(worker) => {
+                    workers.insert((topic, partition), worker);
+                }
+                None => {
+                    // Paused, so its messages are not delivered on the main queue instead
+                    error!("Failed to split queue for {topic}/{partition}, pausing it");
+                    let mut tpl = TopicPartitionList::new();
+                    tpl.add_partition(&topic, partition);
+                    if let Err(e) = self.consumer.consumer.pause(&tpl) {
+                        error!("Failed to pause {topic}/{partition}: {e}");
+                    }
+                }
+            }
+        }
+    }
+
+    fn revoked(&self, partitions: Vec<TopicPartition>) {
+        let revoked: Vec<_> = {
+            let mut workers = self.workers.lock().unwrap_or_else(PoisonError::into_inner);
+            partitions
+                .iter()
+                .filter_map(|tp| workers.remove(tp))
+                .collect()
+        };
+        stop_workers(revoked);
+    }
+}
+
+/// Signals partition workers to stop and blocks until their in-flight work is
+/// finished and committed.
+fn stop_workers(workers: Vec<PartitionWorker>) {
+    for worker in &workers {
+        worker.stop.notify_one();
+    }
+    for worker in workers {
+        // Only ever fails, once the sender is dropped at the end of the task
+        let _ = worker.stopped.recv();
+    }
+}
+
+/// Returns the decoder selected by the message's content type header, if any.
+fn header_decoder(msg: &BorrowedMessage<'_>) -> Option<&'static dyn OrderDecoder> {
+    let content_type = msg
+        .headers()?
+        .iter()
+        .find(|h| h.key.eq_ignore_ascii_case(CONTENT_TYPE_HEADER))?
+        .value?;
+    decoder_for_content_type(std::str::from_utf8(content_type).ok()?)
+}
+
+/// Decodes and validates a message's payload, keeping its position for the offset commit.
+///
+/// A [`CONTENT_TYPE_HEADER`] naming a known format overrides the subscription's decoder.
+/// Undecodable or invalid messages are kept (with the failure in `order`) so their
+/// offsets are still committed and they can be forwarded to the dead-letter topic.
+/// Decoded orders get the message's topic as their `source_topic`.
+async fn decode_message(
+    msg: &BorrowedMessage<'_>,
+    decoder: &dyn OrderDecoder,
+    validation: ValidationProfile,
+) -> DecodedMessage {
+    let decoder = header_decoder(msg).unwrap_or(decoder);
+    let order = match msg.payload() {
+        Some(payload) => match decoder.decode(payload).await {
+            Ok(order) => validation
+                .validate(&order)
+                .map(|()| Order {
+                    source_topic: Some(msg.topic().to_string()),
+                    ..order
+                })
+                .map_err(|e| {
+                    error!("Order failed {validation} validation: {e}");
+                    (FailureReason::Validation, e.to_string())
+                }),
+            Err(e) => {
+                error!("Failed to decode {} order payload: {e}", decoder.name());
+                Err((FailureReason::Deserialize, e.to_string()))
+            }
+        },
+        None => {
+            error!("Empty Kafka message payload");
+            Err((
+                FailureReason::EmptyPayload,
+                "Empty Kafka message payload".to_string(),
+            ))
+        }
+    };
+    DecodedMessage {
+        topic: msg.topic().to_string(),
+        partition: msg.partition(),
+        offset: msg.offset(),
+        timestamp_ms: msg.timestamp().to_millis(),
+        key: msg.key().map(<[u8]>::to_vec),
+        payload: msg.payload().map(<[u8]>::to_vec),
+        headers: msg
+            .headers()
+            .map(|headers| {
+                headers
+                    .iter()
+                    .filter_map(|h| Some((h.key.to_string(), h.value?.to_vec())))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        order,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use rdkafka::consumer::BaseConsumer;
+    use rdkafka::mocking::MockCluster;
+    use rdkafka::producer::{BaseProducer, BaseRecord, Producer};
+    use service::{OrderPredicate, SaveOptions, SaveOutcome, ServiceError};
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    const TOPIC: &str = "orders";
+    const GROUP: &str = "orders-test";
+
+    /// Order uids in the order they were processed, with when.
+    type Processed = Arc<Mutex<Vec<(String, Instant)>>>;
+
+    /// Records saved orders; saving waits while the test holds `gate`.
+    #[derive(Default)]
+    struct RecordingService {
+        saved: Processed,
+        gate: tokio::sync::RwLock<()>,
+    }
+
+    fn unused() -> ServiceError {
+        ServiceError::Unexpected("not used by the consumer in batch mode".into())
+    }
+
+    #[async_trait]
+    impl OrderService for RecordingService {
+        async fn save_order(&self, _order: &Order) -> Result<(), ServiceError> {
+            Err(unused())
+        }
+
+        async fn save_order_with(
+            &self,
+            _order: &Order,
+            _options: &SaveOptions,
+        ) -> Result<SaveOutcome, ServiceError> {
+            Err(unused())
+        }
+
+        async fn update_order_if(
+            &self,
+            _order: &Order,
+            _is_current: &OrderPredicate<'_>,
+        ) -> Result<bool, ServiceError> {
+            Err(unused())
+        }
+
+        async fn save_order_batch(
+            &self,
+            orders: &[Order],
+        ) -> Result<Vec<Result<(), ServiceError>>, ServiceError> {
+            let _open = self.gate.read().await;
+            let now = Instant::now();
+            let mut saved = self.saved.lock().unwrap();
+            saved.extend(orders.iter().map(|o| (o.order_uid.clone(), now)));
+            Ok(orders.iter().map(|_| Ok(())).collect())
+        }
+
+        async fn get_order_by_id(&self, _order_uid: &str) -> Result<Order, ServiceError> {
+            Err(unused())
+        }
+    }
+
+    fn uid(partition: i32, i: usize) -> String {
+        format!("p{partition}-{i:04}")
+    }
+
+    fn produce(producer: &BaseProducer, partition: i32, range: std::ops::Range<usize>) {
+        for i in range {
+            let payload = serde_json::to_vec(&Order {
+                order_uid: uid(partition, i),
+                ..Default::default()
+            })
+            .unwrap();
+            producer
+                .send(
+                    BaseRecord::<(), _>::to(TOPIC)
+                        .partition(partition)
+                        .payload(&payload),
+                )
+                .unwrap();
+        }
+        producer.flush(Duration::from_secs(5)).unwrap();
+    }
+
+    /// Uids of `processed` from `partition`, in processing order, optionally only after `since`.
+    fn of_partition(processed: &Processed, partition: i32, since: Option<Instant>) -> Vec<String> {
+        let prefix = uid(partition, 0)[..3].to_string();
+        processed
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(uid, at)| uid.starts_with(&prefix) && since.is_none_or(|since| *at > since))
+            .map(|(uid, _)| uid.clone())
+            .collect()
+    }
+
+    fn assert_in_order(uids: &[String], what: &str) {
+        assert!(
+            uids.is_sorted(),
+            "{what} were processed out of order: {uids:?}"
+        );
+    }
+
+    /// Polls until `check` holds, failing after `timeout`.
+    async fn eventually(timeout: Duration, what: &str, check: impl Fn() -> bool) {
+        let deadline = Instant::now() + timeout;
+        while !check() {
+            assert!(Instant::now() < deadline, "timed out waiting for {what}");
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    /// Stops the consumer and the second member when the test ends, even by
+    /// panicking, so their blocking poll loops do not keep the runtime alive.
+    struct StopOnDrop {
+        shutdown: Arc<Notify>,
+        stop: Arc<AtomicBool>,
+    }
+
+    impl Drop for StopOnDrop {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::SeqCst);
+            self.shutdown.notify_one();
+        }
+    }
+
+    /// Runs a plain group member on another thread, recording what it receives.
+    fn spawn_member(
+        brokers: String,
+        received: Processed,
+        stop: Arc<AtomicBool>,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::task::spawn_blocking(move || {
+            let consumer: BaseConsumer = ClientConfig::new()
+                .set("bootstrap.servers", &brokers)
+                .set("group.id", GROUP)
+                .set("enable.auto.commit", "false")
+                .set("auto.offset.reset", "earliest")
+                .create()
+                .unwrap();
+            consumer.subscribe(&[TOPIC]).unwrap();
+            while !stop.load(Ordering::SeqCst) {
+                if let Some(Ok(msg)) = consumer.poll(Duration::from_millis(100)) {
+                    let order: Order = serde_json::from_slice(msg.payload().unwrap()).unwrap();
+                    received
+                        .lock()
+                        .unwrap()
+                        .push((order.order_uid, Instant::now()));
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn test_subscription_matches_topic_or_pattern() {
+        let exact =
+            Subscription::new(TOPIC, Arc::new(JsonDecoder), ValidationProfile::Default).unwrap();
+        assert!(exact.matches("orders"));
+        assert!(!exact.matches("orders-eu"));
+
+        let pattern = Subscription::new(
+            "^orders-.*",
+            Arc::new(JsonDecoder),
+            ValidationProfile::Default,
+        )
+        .unwrap();
+        assert!(pattern.matches("orders-eu"));
+        assert!(!pattern.matches("orders"));
+
+        assert!(
+            Subscription::new(
+                "^orders-(",
+                Arc::new(JsonDecoder),
+                ValidationProfile::Default
+            )
+            .is_err()
+        );
+    }
+
+    /// A second member joining the group takes a partition away from the consumer.
+    ///
+    /// The mock cluster only completes the rebalance after most of the consumer's
+    /// session timeout, which makes this test slow. Unlike a real broker, it also
+    /// rejects offset commits while a rebalance is in progress, so orders processed
+    /// right before the handover are delivered again. The checks therefore look at
+    /// when and in which order orders were processed, not at how often.
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_partition_workers_hand_over_on_rebalance() {
+        let cluster = MockCluster::new(1).unwrap();
+        cluster.create_topic(TOPIC, 2, 1).unwrap();
+        let brokers = cluster.bootstrap_servers();
+        let producer: BaseProducer = ClientConfig::new()
+            .set("bootstrap.servers", &brokers)
+            .create()
+            .unwrap();
+
+        let service = Arc::new(RecordingService::default());
+        let config = ConsumerConfig {
+            brokers: vec![brokers.clone()],
+            subscriptions: vec![
+                Subscription::new(TOPIC, Arc::new(JsonDecoder), ValidationProfile::Default)
+                    .unwrap(),
+            ],
+            group_id: GROUP.into(),
+            batch: Some(BatchConfig {
+                max_messages: 20,
+                max_wait: Duration::from_millis(50),
+            }),
+            partition_workers: Some(2),
+            dlq_topic: None,
+            statistics_interval: Duration::ZERO,
+        };
+        let consumer = Arc::new(
+            KafkaConsumer::new(
+                &config,
+                service.clone(),
+                Arc::new(OrderCache::new()),
+                &Registry::new(),
+            )
+            .unwrap(),
+        );
+        let shutdown = Arc::new(Notify::new());
+        let stop = Arc::new(AtomicBool::new(false));
+        let guard = StopOnDrop {
+            shutdown: shutdown.clone(),
+            stop: stop.clone(),
+        };
+        let running = tokio::spawn(consumer.clone().run(shutdown.clone()));
+
+        // Assign: both partitions get a worker, each keeping its partition's order
+        produce(&producer, 0, 0..20);
+        produce(&producer, 1, 0..20);
+        eventually(Duration::from_secs(30), "the first orders", || {
+            service.saved.lock().unwrap().len() == 40
+        })
+        .await;
+        for partition in [0, 1] {
+            assert_in_order(&of_partition(&service.saved, partition, None), "orders");
+        }
+
+        // Revoke: a second member joins while batches are held in flight and orders keep coming
+        let assignments = Arc::new(Mutex::new(Vec::new()));
+        let monitor = {
+            let (consumer, assignments, stop) =
+                (consumer.clone(), assignments.clone(), stop.clone());
+            tokio::spawn(async move {
+                let mut last = None;
+                while !stop.load(Ordering::SeqCst) {
+                    if let Ok(tpl) = consumer.consumer.assignment()
+                        && last != Some(tpl.count())
+                    {
+                        assignments
+                            .lock()
+                            .unwrap()
+                            .push((Instant::now(), tpl.count()));
+                        last = Some(tpl.count());
+                    }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+        };
+        let received = Processed::default();
+        let mut hold = Some(service.gate.write().await);
+        let mut released_at = None;
+        let member = spawn_member(brokers, received.clone(), stop.clone());
+        let joined = Instant::now();
+        let deadline = joined + Duration::from_secs(90);
+        let mut produced = 20;
+        let mut received_at = None;
+        while received_at.is_none_or(|at| produced < at + 20) {
+            // Long enough for the consumer to learn about the rebalance from a heartbeat
+            if hold.is_some() && joined.elapsed() > Duration::from_secs(5) {
+                drop(hold.take());
+                released_at = Some(Instant::now());
+            }
+            assert!(
+                Instant::now() < deadline,
+                "the second member never received an order"
+            );
+            produce(&producer, 0, produced..produced + 1);
+            produce(&producer, 1, produced..produced + 1);
+            produced += 1;
+            if received_at.is_none() && !received.lock().unwrap().is_empty() {
+                received_at = Some(produced);
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        let all: HashSet<String> = (0..produced).flat_map(|i| [uid(0, i), uid(1, i)]).collect();
+        let processed = || {
+            let mut processed: HashSet<String> = service
+                .saved
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(u, _)| u.clone())
+                .collect();
+            processed.extend(received.lock().unwrap().iter().map(|(u, _)| u.clone()));
+            processed
+        };
+        eventually(Duration::from_secs(60), "all orders", || processed() == all).await;
+        drop(guard);
+        member.await.unwrap();
+        monitor.await.unwrap();
+        running.await.unwrap().unwrap();
+
+        // The revocation waited for the batches held in flight
+        let revoked_at = assignments
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(_, count)| *count == 0)
+            .expect("the partitions were revoked")
+            .0;
+        assert!(
+            revoked_at > released_at.unwrap(),
+            "the revocation completed while batches were still being saved"
+        );
+
+        let (moved, kept) = if received.lock().unwrap()[0].0.starts_with("p0-") {
+            (0, 1)
+        } else {
+            (1, 0)
+        };
+        let first_received = received.lock().unwrap()[0].1;
+        assert_eq!(
+            of_partition(&received, kept, None),
+            Vec::<String>::new(),
+            "the second member only got partition {moved}"
+        );
+        assert_eq!(
+            of_partition(&service.saved, moved, Some(first_received)),
+            Vec::<String>::new(),
+            "the revoked partition was no longer processed once its new owner started"
+        );
+        assert_in_order(
+            &of_partition(&service.saved, kept, Some(first_received)),
+            "orders of the partition assigned again",
+        );
+    }
+}
@@ -0,0 +1,191 @@
+//! Prometheus metrics exported by the Kafka consumer.
+
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{Gauge, Histogram, HistogramOpts, IntCounterVec, IntGaugeVec, Opts, Registry};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Instant;
+
+/// Metrics describing the consumer's message processing.
+pub(crate) struct ConsumerMetrics {
+    /// Messages received from Kafka, by topic.
+    pub consumed_total: IntCounterVec,
+    /// Messages persisted and cached, by topic.
+    pub succeeded_total: IntCounterVec,
+    /// Messages that could not be processed, by topic and failure reason.
+    pub failed_total: IntCounterVec,
+    /// Messages forwarded to the dead-letter topic, by source topic and failure reason.
+    pub dlq_total: IntCounterVec,
+    /// Time spent processing a single message (single-message mode).
+    pub processing_duration_seconds: Histogram,
+    /// Time from a message being produced to it being persisted.
+    pub message_age_seconds: Histogram,
+    /// Number of messages per processed batch.
+    pub batch_size: Histogram,
+    /// Time from the first message of a batch arriving to its offsets being committed.
+    pub batch_duration_seconds: Histogram,
+    /// Consumer lag per partition, as reported by librdkafka statistics.
+    pub partition_lag: IntGaugeVec,
+    /// Time of the last message received from Kafka.
+    last_message: LastMessage,
+}
+
+impl ConsumerMetrics {
+    /// Creates the consumer metrics and registers them in `registry`.
+    pub fn new(registry: &Registry) -> prometheus::Result<Self> {
+        let consumed_total = IntCounterVec::new(
+            Opts::new(
+                "kafka_consumer_messages_consumed_total",
+                "Total number of messages received from Kafka",
+            ),
+            &["topic"],
+        )?;
+        let succeeded_total = IntCounterVec::new(
+            Opts::new(
+                "kafka_consumer_messages_succeeded_total",
+                "Total number of messages persisted and cached",
+            ),
+            &["topic"],
+        )?;
+        let failed_total = IntCounterVec::new(
+            Opts::new(
+                "kafka_consumer_messages_failed_total",
+                "Total number of messages that could not be processed",
+            ),
+            &["topic", "reason"],
+        )?;
+        let dlq_total = IntCounterVec::new(
+            Opts::new(
+                "kafka_consumer_messages_dlq_total",
+                "Total number of messages forwarded to the dead-letter topic",
+            ),
+            &["topic", "reason"],
+        )?;
+        let processing_duration_seconds = Histogram::with_opts(HistogramOpts::new(
+            "kafka_consumer_processing_duration_seconds",
+            "Time spent processing a single message, in seconds",
+        ))?;
+        let message_age_seconds = Histogram::with_opts(
+            HistogramOpts::new(
+                "kafka_consumer_message_age_seconds",
+                "Time from a message being produced to it being persisted, in seconds",
+            )
+            .buckets(vec![
+                0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0, 900.0, 3600.0,
+            ]),
+        )?;
+        let batch_size = Histogram::with_opts(
+            HistogramOpts::new(
+                "kafka_consumer_batch_size",
+                "Number of messages per consumed batch",
+            )
+            .buckets(vec![
+                1.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0,
+            ]),
+        )?;
+        let batch_duration_seconds = Histogram::with_opts(HistogramOpts::new(
+            "kafka_consumer_batch_duration_seconds",
+            "Time from the first message of a batch to its commit, in seconds",
+        ))?;
+        let partition_lag = IntGaugeVec::new(
+            Opts::new(
+                "kafka_consumer_partition_lag",
+                "Consumer lag per partition, in messages",
+            ),
+            &["topic", "partition"],
+        )?;
+        let last_message = LastMessage::new()?;
+
+        registry.register(Box::new(consumed_total.clone()))?;
+        registry.register(Box::new(succeeded_total.clone()))?;
+        registry.register(Box::new(failed_total.clone()))?;
+        registry.register(Box::new(dlq_total.clone()))?;
+        registry.register(Box::new(processing_duration_seconds.clone()))?;
+        registry.register(Box::new(message_age_seconds.clone()))?;
+        registry.register(Box::new(batch_size.clone()))?;
+        registry.register(Box::new(batch_duration_seconds.clone()))?;
+        registry.register(Box::new(partition_lag.clone()))?;
+        registry.register(Box::new(last_message.clone()))?;
+
+        Ok(Self {
+            consumed_total,
+            succeeded_total,
+            failed_total,
+            dlq_total,
+            processing_duration_seconds,
+            message_age_seconds,
+            batch_size,
+            batch_duration_seconds,
+            partition_lag,
+            last_message,
+        })
+    }
+
+    /// Records a message received from `topic`.
+    pub fn consumed(&self, topic: &str) {
+        self.consumed_total.with_label_values(&[topic]).inc();
+        self.last_message.touch();
+    }
+}
+
+/// Exposes `kafka_consumer_seconds_since_last_message`, computed at scrape time.
+///
+/// The gauge is absent until the first message arrives.
+#[derive(Clone)]
+struct LastMessage {
+    at: Arc<Mutex<Option<Instant>>>,
+    gauge: Gauge,
+}
+
+impl LastMessage {
+    fn new() -> prometheus::Result<Self> {
+        Ok(Self {
+            at: Arc::new(Mutex::new(None)),
+            gauge: Gauge::new(
+                "kafka_consumer_seconds_since_last_message",
+                "Seconds since the last message was received from Kafka",
+            )?,
+        })
+    }
+
+    fn touch(&self) {
+        *self.at.lock().unwrap_or_else(PoisonError::into_inner) = Some(Instant::now());
+    }
+}
+
+impl Collector for LastMessage {
+    fn desc(&self) -> Vec<&Desc> {
+        self.gauge.desc()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        match *self.at.lock().unwrap_or_else(PoisonError::into_inner) {
+            Some(at) => {
+                self.gauge.set(at.elapsed().as_secs_f64());
+                self.gauge.collect()
+            }
+            None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seconds_since_last_message_appears_after_first_message() {
+        let registry = Registry::new();
+        let metrics = ConsumerMetrics::new(&registry).unwrap();
+        let name = "kafka_consumer_seconds_since_last_message";
+        let has_gauge = |registry: &Registry| registry.gather().iter().any(|f| f.name() == name);
+
+        assert!(!has_gauge(&registry));
+        metrics.consumed("orders");
+        assert!(has_gauge(&registry));
+        assert_eq!(
+            metrics.consumed_total.with_label_values(&["orders"]).get(),
+            1
+        );
+    }
+}
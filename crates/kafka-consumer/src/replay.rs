@@ -0,0 +1,291 @@
+//! Replay of historical order messages.
+//!
+//! Re-processes a bounded range of the orders topic, e.g. after a bug in order
+//! persistence has been fixed. The range starts at a timestamp or at explicit
+//! per-partition offsets and ends at a timestamp, explicit offsets or the end of
+//! each partition as seen when the replay starts.
+//!
+//! Partitions are assigned manually under a dedicated consumer group and no
+//! offsets are committed, so the production group's position is never touched.
+//! Every order goes through [`OrderService::save_order_with`] with the configured
+//! [`DuplicatePolicy`](service::DuplicatePolicy).
+
+use crate::{OrderDecoder, decode_message};
+use anyhow::{Context, Result, bail};
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::message::Message;
+use rdkafka::{Offset, TopicPartitionList};
+use serde::Serialize;
+use service::{OrderService, SaveOptions, SaveOutcome, ValidationProfile};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio_stream::StreamExt;
+use tracing::{error, info, warn};
+
+/// Timeout for metadata, watermark and offset lookups.
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Number of processed messages between progress log lines.
+const PROGRESS_EVERY: u64 = 1000;
+
+/// One end of a replay range.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayBound {
+    /// Milliseconds since the Unix epoch; resolved per partition to the first
+    /// message with an equal or later timestamp.
+    Timestamp(i64),
+    /// Explicit offset per partition.
+    Offsets(HashMap<i32, i64>),
+}
+
+/// Settings for [`replay`].
+#[derive(Clone)]
+pub struct ReplayConfig {
+    /// Kafka bootstrap brokers.
+    pub brokers: Vec<String>,
+    /// Topic to replay.
+    pub topic: String,
+    /// Consumer group used by the replay; must differ from the production group.
+    pub group_id: String,
+    /// Decoder for the topic's payload format.
+    pub decoder: Arc<dyn OrderDecoder>,
+    /// Extra checks for orders from the topic.
+    pub validation: ValidationProfile,
+    /// First message to replay (inclusive). With explicit offsets, only the
+    /// listed partitions are replayed.
+    pub start: ReplayBound,
+    /// Where to stop (exclusive); `None` stops at the end of each partition as
+    /// seen when the replay starts.
+    pub end: Option<ReplayBound>,
+    /// Save options, including the duplicate policy, applied to every order.
+    pub options: SaveOptions,
+    /// Give up if no message arrives for this long before the range is exhausted.
+    pub idle_timeout: Duration,
+}
+
+/// Counts collected by a replay run.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ReplayReport {
+    /// Number of partitions with messages in the range.
+    pub partitions: usize,
+    /// Messages read within the range.
+    pub consumed: u64,
+    /// Orders that did not exist and were inserted.
+    pub inserted: u64,
+    /// Orders that already existed and were left untouched.
+    pub skipped: u64,
+    /// Orders that already existed and were replaced.
+    pub overwritten: u64,
+    /// Messages whose payload was empty, could not be decoded or failed validation.
+    pub invalid: u64,
+    /// Orders that could not be saved.
+    pub failed: u64,
+    /// Whether the replay stopped before reaching the end of the range.
+    pub incomplete: bool,
+}
+
+impl fmt::Display for ReplayReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} partitions, {} consumed: {} inserted, {} skipped, {} overwritten, {} invalid, {} failed{}",
+            self.partitions,
+            self.consumed,
+            self.inserted,
+            self.skipped,
+            self.overwritten,
+            self.invalid,
+            self.failed,
+            if self.incomplete { " (incomplete)" } else { "" }
+        )
+    }
+}
+
+/// Replays the configured range through `order_service` and reports what happened.
+///
+/// Stops early, with [`ReplayReport::incomplete`] set, when `shutdown` is notified
+/// or no message arrives within the idle timeout.
+///
+/// # Errors
+/// Returns an error if the consumer cannot be created, the topic does not exist,
+/// or the range bounds cannot be resolved to offsets.
+pub async fn replay<S: OrderService + ?Sized>(
+    config: &ReplayConfig,
+    order_service: &S,
+    shutdown: &Notify,
+) -> Result<ReplayReport> {
+    let consumer: StreamConsumer = ClientConfig::new()
+        .set("bootstrap.servers", config.brokers.join(","))
+        .set("group.id", &config.group_id)
+        .set("enable.partition.eof", "false")
+        .set("enable.auto.commit", "false")
+        .set("auto.offset.reset", "earliest")
+        .create()
+        .context("Failed to create replay consumer")?;
+
+    let ranges = resolve_ranges(&consumer, config)?;
+    let mut report = ReplayReport {
+        partitions: ranges.len(),
+        ..Default::default()
+    };
+    if ranges.is_empty() {
+        info!("Nothing to replay on topic {}", config.topic);
+        return Ok(report);
+    }
+
+    let mut tpl = TopicPartitionList::new();
+    for (partition, (start, end)) in &ranges {
+        info!(
+            "Replaying {}/{partition} from offset {start} to {end}",
+            config.topic
+        );
+        tpl.add_partition_offset(&config.topic, *partition, Offset::Offset(*start))?;
+    }
+    consumer.assign(&tpl)?;
+
+    let mut remaining: HashMap<i32, i64> = ranges
+        .into_iter()
+        .map(|(partition, (_, end))| (partition, end))
+        .collect();
+    let mut stream = consumer.stream();
+
+    while !remaining.is_empty() {
+        let msg = tokio::select! {
+            next = tokio::time::timeout(config.idle_timeout, stream.next()) => match next {
+                Ok(Some(Ok(msg))) => msg,
+                Ok(Some(Err(e))) => {
+                    error!("Kafka error during replay: {e}");
+                    continue;
+                }
+                Ok(None) => break,
+                Err(_) => {
+                    warn!(
+                        "No message for {:?}, stopping replay with {} partitions unfinished",
+                        config.idle_timeout,
+                        remaining.len()
+                    );
+                    break;
+                }
+            },
+            _ = shutdown.notified() => {
+                info!("Replay received shutdown signal.");
+                break;
+            }
+        };
+
+        let Some(&end) = remaining.get(&msg.partition()) else {
+            continue;
+        };
+        if msg.offset() >= end {
+            remaining.remove(&msg.partition());
+            continue;
+        }
+
+        report.consumed += 1;
+        match decode_message(&msg, config.decoder.as_ref(), config.validation)
+            .await
+            .order
+        {
+            Ok(order) => match order_service.save_order_with(&order, &config.options).await {
+                Ok(SaveOutcome::Inserted) => report.inserted += 1,
+                Ok(SaveOutcome::Skipped) => report.skipped += 1,
+                Ok(SaveOutcome::Overwritten) => report.overwritten += 1,
+                Err(e) => {
+                    error!("Failed to replay order {}: {e}", order.order_uid);
+                    report.failed += 1;
+                }
+            },
+            Err(_) => report.invalid += 1,
+        }
+
+        if msg.offset() + 1 >= end {
+            remaining.remove(&msg.partition());
+        }
+        if report.consumed.is_multiple_of(PROGRESS_EVERY) {
+            info!("Replay progress: {report}");
+        }
+    }
+
+    report.incomplete = !remaining.is_empty();
+    Ok(report)
+}
+
+/// Resolves the replay bounds to a `[start, end)` offset range per partition,
+/// leaving out partitions with nothing to replay.
+fn resolve_ranges(
+    consumer: &StreamConsumer,
+    config: &ReplayConfig,
+) -> Result<HashMap<i32, (i64, i64)>> {
+    let metadata = consumer
+        .fetch_metadata(Some(&config.topic), LOOKUP_TIMEOUT)
+        .context("Failed to fetch topic metadata")?;
+    let partitions: Vec<i32> = metadata
+        .topics()
+        .iter()
+        .filter(|t| t.name() == config.topic)
+        .flat_map(|t| t.partitions().iter().map(|p| p.id()))
+        .collect();
+    if partitions.is_empty() {
+        bail!("Topic {} does not exist or has no partitions", config.topic);
+    }
+
+    let mut high_watermarks = HashMap::new();
+    for &partition in &partitions {
+        let (_, high) = consumer.fetch_watermarks(&config.topic, partition, LOOKUP_TIMEOUT)?;
+        high_watermarks.insert(partition, high);
+    }
+
+    let starts = match &config.start {
+        ReplayBound::Timestamp(ts) => offsets_for_time(consumer, config, &partitions, *ts)?,
+        ReplayBound::Offsets(offsets) => {
+            if let Some(p) = offsets.keys().find(|p| !partitions.contains(p)) {
+                bail!("Topic {} has no partition {p}", config.topic);
+            }
+            offsets.clone()
+        }
+    };
+    let ends = match &config.end {
+        None => HashMap::new(),
+        Some(ReplayBound::Timestamp(ts)) => offsets_for_time(consumer, config, &partitions, *ts)?,
+        Some(ReplayBound::Offsets(offsets)) => offsets.clone(),
+    };
+
+    Ok(starts
+        .into_iter()
+        .filter_map(|(partition, start)| {
+            let high = *high_watermarks.get(&partition)?;
+            let end = ends.get(&partition).copied().unwrap_or(high).min(high);
+            (start < end).then_some((partition, (start, end)))
+        })
+        .collect())
+}
+
+/// Looks up, per partition, the offset of the first message at or after `timestamp_ms`.
+///
+/// Partitions without such a message are left out.
+fn offsets_for_time(
+    consumer: &StreamConsumer,
+    config: &ReplayConfig,
+    partitions: &[i32],
+    timestamp_ms: i64,
+) -> Result<HashMap<i32, i64>> {
+    let mut tpl = TopicPartitionList::new();
+    for &partition in partitions {
+        tpl.add_partition_offset(&config.topic, partition, Offset::Offset(timestamp_ms))?;
+    }
+    let resolved = consumer
+        .offsets_for_times(tpl, LOOKUP_TIMEOUT)
+        .context("Failed to look up offsets for timestamp")?;
+    Ok(resolved
+        .elements()
+        .iter()
+        .filter_map(|e| match e.offset() {
+            Offset::Offset(offset) => Some((e.partition(), offset)),
+            _ => None,
+        })
+        .collect())
+}
@@ -1,70 +1,191 @@
 //! Kafka producer module for generating and sending test order messages.
 //!
-//! This module provides functionality to generate random order data
-//! and publish it to a Kafka topic.
+//! This module provides an [`OrderProducer`] that publishes orders, including
+//! randomly generated test orders, to a Kafka topic as JSON, protobuf, or Avro in
+//! the Confluent schema registry wire format.
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use app_config::AppConfig;
 use chrono::Utc;
+use codec::avro::{ORDER_SCHEMA_JSON, encode_order};
+use codec::confluent::frame;
+use codec::registry::SchemaRegistry;
+use codec::{CONTENT_TYPE_HEADER, CONTENT_TYPE_JSON, CONTENT_TYPE_PROTOBUF};
 use fake::{Fake, Faker};
 use model::{Delivery, Item, Order, Payment};
 use rand::seq::SliceRandom;
 use rdkafka::ClientConfig;
+use rdkafka::message::{Header, OwnedHeaders};
 use rdkafka::producer::{FutureProducer, FutureRecord};
 use std::time::{Duration, SystemTime};
-use tracing::{error, info};
+use telemetry::REQUEST_ID_HEADER;
+use tracing::{Span, error, info, instrument};
 use uuid::Uuid;
 
-/// Generates a test order message, serializes it to JSON, and sends it to Kafka.
+/// Encodes orders as Avro in the Confluent schema registry wire format.
 ///
-/// # Returns
-/// - `Result<String>`: The unique identifier (OrderUID) of the order sent to Kafka,
-///   or an error if the message could not be sent.
-pub async fn produce_test_message() -> Result<String> {
-    info!("Starting Kafka producer");
-
-    // Load configuration
-    let config = AppConfig::load().context("Failed to load config")?;
-
-    // Create Kafka producer
-    let producer: FutureProducer = ClientConfig::new()
-        .set("bootstrap.servers", config.kafka_brokers.join(","))
-        .set("message.timeout.ms", "5000")
-        .create()
-        .context("Failed to create Kafka producer")?;
-
-    info!(
-        topic = %config.kafka_topic,
-        "Kafka producer initialized"
-    );
-
-    // Generate and publish message
-    let order = generate_order();
-    let order_uid = order.order_uid.clone();
-
-    // Serialize message to JSON
-    let data = serde_json::to_string(&order).context("Failed to serialize order to JSON")?;
-
-    // Publish message to Kafka
-    let record = FutureRecord::to(&config.kafka_topic)
-        .key(&order_uid)
-        .payload(&data);
-
-    match producer
-        .send(record, Duration::from_secs(5))
-        .await
-        .map_err(|(kafka_err, owned_msg)| {
-            anyhow::anyhow!("Kafka error: {:?}, Message: {:?}", kafka_err, owned_msg)
+/// The order schema checked into the repo is registered under the topic's value
+/// subject (`<topic>-value`) on first use; the registry returns the existing id
+/// if it is already registered and rejects it if it breaks compatibility.
+pub struct AvroOrderEncoder {
+    registry: SchemaRegistry,
+    subject: String,
+}
+
+impl AvroOrderEncoder {
+    /// Creates an encoder for messages published to `topic`.
+    pub fn new(registry: SchemaRegistry, topic: &str) -> Self {
+        Self {
+            registry,
+            subject: format!("{topic}-value"),
+        }
+    }
+
+    /// Encodes an order, prefixed with the registry header for the order schema.
+    ///
+    /// # Errors
+    /// Returns an error if the schema cannot be registered or the order cannot be encoded.
+    pub async fn encode(&self, order: &Order) -> Result<Vec<u8>> {
+        let schema_id = self
+            .registry
+            .register(&self.subject, ORDER_SCHEMA_JSON)
+            .await
+            .context("Failed to register the order schema")?;
+        Ok(frame(schema_id, &encode_order(order)?))
+    }
+}
+
+/// How orders are serialized into message payloads.
+enum Encoding {
+    Json,
+    Protobuf,
+    Avro(AvroOrderEncoder),
+}
+
+/// Publishes orders to the configured Kafka topic.
+///
+/// Holds the Kafka producer and, for Avro output, the schema registry client, so
+/// one instance is created at startup and shared by all requests.
+pub struct OrderProducer {
+    producer: FutureProducer,
+    topic: String,
+    encoding: Encoding,
+}
+
+impl OrderProducer {
+    /// Creates a producer for the brokers, topic and format in `config`.
+    ///
+    /// # Errors
+    /// Returns an error if the format is unknown, Avro output lacks a schema
+    /// registry URL, or the Kafka producer cannot be created.
+    pub fn from_config(config: &AppConfig) -> Result<Self> {
+        let encoding = match config.kafka_producer_format.as_str() {
+            "json" => Encoding::Json,
+            "protobuf" => Encoding::Protobuf,
+            "avro" => {
+                if config.kafka_schema_registry_url.is_empty() {
+                    bail!("Avro output requires KAFKA_SCHEMA_REGISTRY_URL");
+                }
+                let registry = SchemaRegistry::new(&config.kafka_schema_registry_url)?;
+                Encoding::Avro(AvroOrderEncoder::new(registry, &config.kafka_topic))
+            }
+            other => bail!("Unknown producer format '{other}'"),
+        };
+
+        let producer: FutureProducer = ClientConfig::new()
+            .set("bootstrap.servers", config.kafka_brokers.join(","))
+            .set("message.timeout.ms", "5000")
+            .create()
+            .context("Failed to create Kafka producer")?;
+
+        info!(
+            topic = %config.kafka_topic,
+            format = %config.kafka_producer_format,
+            "Kafka producer initialized"
+        );
+        Ok(Self {
+            producer,
+            topic: config.kafka_topic.clone(),
+            encoding,
         })
-        .context("Failed to send message to Kafka")
-    {
-        Ok(_) => {
-            info!(order_uid = %order_uid, "Message published successfully");
-            Ok(order_uid)
+    }
+
+    /// Generates a test order and publishes it with [`OrderProducer::publish`].
+    ///
+    /// # Returns
+    /// - `Result<String>`: The unique identifier (OrderUID) of the order sent to Kafka,
+    ///   or an error if the message could not be sent.
+    pub async fn publish_test_order(&self, request_id: Option<&str>) -> Result<String> {
+        let order = generate_order();
+        self.publish(&order, request_id).await?;
+        Ok(order.order_uid)
+    }
+
+    /// Serializes an order in the configured format and sends it to Kafka.
+    ///
+    /// The message carries the W3C trace context of the current span, so the consumer
+    /// continues the same trace, and the id of the HTTP request that produced it, if any.
+    #[instrument(name = "kafka.produce", skip_all, fields(topic = %self.topic, order_uid = %order.order_uid))]
+    pub async fn publish(&self, order: &Order, request_id: Option<&str>) -> Result<()> {
+        let order_uid = &order.order_uid;
+        let span = Span::current();
+
+        // Serialize message in the configured format; JSON and protobuf messages carry
+        // their content type so consumers can pick the decoder per message.
+        let (data, content_type) = match &self.encoding {
+            Encoding::Json => (
+                serde_json::to_vec(order).context("Failed to serialize order to JSON")?,
+                Some(CONTENT_TYPE_JSON),
+            ),
+            Encoding::Protobuf => (
+                codec::protobuf::encode_order(order),
+                Some(CONTENT_TYPE_PROTOBUF),
+            ),
+            Encoding::Avro(encoder) => (encoder.encode(order).await?, None),
+        };
+
+        // Publish message to Kafka
+        let mut headers = OwnedHeaders::new();
+        if let Some(content_type) = content_type {
+            headers = headers.insert(Header {
+                key: CONTENT_TYPE_HEADER,
+                value: Some(content_type),
+            });
         }
-        Err(e) => {
-            error!(error = ?e, "Failed to publish message to Kafka");
-            Err(e)
+        if let Some(request_id) = request_id {
+            headers = headers.insert(Header {
+                key: REQUEST_ID_HEADER,
+                value: Some(request_id),
+            });
+        }
+        for (key, value) in &telemetry::inject(&span) {
+            headers = headers.insert(Header {
+                key,
+                value: Some(value),
+            });
+        }
+        let record = FutureRecord::to(&self.topic)
+            .key(order_uid)
+            .payload(&data)
+            .headers(headers);
+
+        match self
+            .producer
+            .send(record, Duration::from_secs(5))
+            .await
+            .map_err(|(kafka_err, owned_msg)| {
+                anyhow::anyhow!("Kafka error: {:?}, Message: {:?}", kafka_err, owned_msg)
+            })
+            .context("Failed to send message to Kafka")
+        {
+            Ok(_) => {
+                info!(order_uid = %order_uid, "Message published successfully");
+                Ok(())
+            }
+            Err(e) => {
+                error!(error = ?e, "Failed to publish message to Kafka");
+                Err(e)
+            }
         }
     }
 }
@@ -150,6 +271,7 @@ fn generate_order() -> Order {
         sm_id: (1..100).fake(),
         date_created: Utc::now(),
         oof_shard: Faker.fake::<String>(),
+        source_topic: None,
     }
 }
 
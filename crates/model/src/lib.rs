@@ -1,11 +1,16 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+mod masking;
+
+pub use masking::PiiView;
 
 /// Delivery - Information about order delivery.
 ///
 /// Contains all the necessary details for shipping an order to a customer,
 /// including contact information and address details.
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default, ToSchema)]
 pub struct Delivery {
     /// Recipient's full name
     pub name: String,
@@ -27,7 +32,7 @@ pub struct Delivery {
 ///
 /// Contains all the details related to a payment transaction,
 /// including amounts, transaction IDs, and payment provider information.
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default, ToSchema)]
 pub struct Payment {
     /// Unique transaction identifier
     pub transaction: String,
@@ -60,7 +65,7 @@ pub struct Payment {
 ///
 /// Represents a single product in an order with its details
 /// such as price, size, and tracking information.
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default, ToSchema)]
 pub struct Item {
     /// Chart ID - unique identifier for the item in the chart
     #[serde(rename = "chrt_id")]
@@ -95,7 +100,7 @@ pub struct Item {
 /// The central entity in the shopping cart system that combines all information
 /// about a customer's purchase, including delivery details, payment information,
 /// and the items being ordered.
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default, ToSchema)]
 pub struct Order {
     /// Unique identifier for the order
     #[serde(rename = "order_uid")]
@@ -133,6 +138,9 @@ pub struct Order {
     /// Out-of-stock shard identifier
     #[serde(rename = "oof_shard")]
     pub oof_shard: String,
+    /// Kafka topic the order was consumed from (`None` for orders from other sources)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_topic: Option<String>,
 }
 
 #[cfg(test)]
@@ -0,0 +1,144 @@
+//! Masked views of orders for callers not cleared to see customer PII.
+//!
+//! Masking keeps enough of each value to help identify a customer on the phone
+//! (last digits of the phone number, first letter of the name and e-mail, the
+//! e-mail domain, city and region) and hides the rest.
+
+use crate::{Delivery, Order, Payment};
+use std::borrow::Cow;
+
+/// Replacement for hidden characters.
+const MASK: &str = "***";
+
+/// How much customer PII a view of an order exposes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PiiView {
+    /// `Delivery` contact and address fields and payment identifiers are masked.
+    Masked,
+    /// The order as stored.
+    Full,
+}
+
+impl Order {
+    /// Returns the order as seen through `view`.
+    pub fn view(&self, view: PiiView) -> Cow<'_, Order> {
+        match view {
+            PiiView::Full => Cow::Borrowed(self),
+            PiiView::Masked => Cow::Owned(self.masked()),
+        }
+    }
+
+    /// Returns a copy with `Delivery` and `Payment.transaction`/`request_id` masked.
+    pub fn masked(&self) -> Order {
+        Order {
+            delivery: self.delivery.masked(),
+            payment: self.payment.masked(),
+            ..self.clone()
+        }
+    }
+}
+
+impl Delivery {
+    fn masked(&self) -> Delivery {
+        Delivery {
+            name: keep_prefix(&self.name, 1),
+            phone: keep_suffix(&self.phone, 4),
+            zip: mask_all(&self.zip),
+            city: self.city.clone(),
+            address: mask_all(&self.address),
+            region: self.region.clone(),
+            email: mask_email(&self.email),
+        }
+    }
+}
+
+impl Payment {
+    fn masked(&self) -> Payment {
+        Payment {
+            transaction: keep_suffix(&self.transaction, 4),
+            request_id: keep_suffix(&self.request_id, 4),
+            ..self.clone()
+        }
+    }
+}
+
+/// Masks everything but the first `n` characters; values that short are masked entirely.
+fn keep_prefix(value: &str, n: usize) -> String {
+    if value.chars().count() <= n {
+        return mask_all(value);
+    }
+    value.chars().take(n).collect::<String>() + MASK
+}
+
+/// Masks everything but the last `n` characters; values that short are masked entirely.
+fn keep_suffix(value: &str, n: usize) -> String {
+    let len = value.chars().count();
+    if len <= n {
+        return mask_all(value);
+    }
+    MASK.to_string() + &value.chars().skip(len - n).collect::<String>()
+}
+
+/// Masks a non-empty value entirely; empty values stay empty.
+fn mask_all(value: &str) -> String {
+    if value.is_empty() {
+        String::new()
+    } else {
+        MASK.to_string()
+    }
+}
+
+/// Masks the local part of an e-mail address except its first character.
+fn mask_email(email: &str) -> String {
+    match email.split_once('@') {
+        Some((local, domain)) => format!("{}@{domain}", keep_prefix(local, 1)),
+        None => mask_all(email),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_masked_order() {
+        let order = Order {
+            order_uid: "b563feb7b2b84b6test".into(),
+            delivery: Delivery {
+                name: "Test Testov".into(),
+                phone: "+9720000000".into(),
+                zip: "2639809".into(),
+                city: "Kiryat Mozkin".into(),
+                address: "Ploshad Mira 15".into(),
+                region: "Kraiot".into(),
+                email: "test@gmail.com".into(),
+            },
+            payment: Payment {
+                transaction: "b563feb7b2b84b6test".into(),
+                currency: "USD".into(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let masked = order.view(PiiView::Masked);
+        assert_eq!(
+            masked.delivery,
+            Delivery {
+                name: "T***".into(),
+                phone: "***0000".into(),
+                zip: "***".into(),
+                city: "Kiryat Mozkin".into(),
+                address: "***".into(),
+                region: "Kraiot".into(),
+                email: "t***@gmail.com".into(),
+            }
+        );
+        assert_eq!(masked.payment.transaction, "***test");
+        assert_eq!(masked.payment.request_id, "");
+        assert_eq!(masked.payment.currency, "USD");
+        assert_eq!(masked.order_uid, order.order_uid);
+
+        assert_eq!(*order.view(PiiView::Full), order);
+    }
+}
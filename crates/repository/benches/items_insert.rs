@@ -0,0 +1,156 @@
+//! Compares the single-statement `UNNEST` item insert with the previous
+//! one-`INSERT`-per-item loop, and bulk order inserts with per-order inserts.
+//!
+//! Requires a migrated database in `DATABASE_URL` (see `test_db`), e.g.
+//! `DATABASE_URL="host=localhost user=orders_user password=securepassword dbname=orders_db" cargo bench -p repository`.
+//! Every iteration runs inside a transaction that is rolled back.
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use model::{Delivery, Item, Order, Payment};
+use repository::{
+    DeliveriesRepository, ItemsRepository, OrdersRepository, PaymentsRepository,
+    PgDeliveriesRepository, PgItemsRepository, PgOrdersRepository, PgPaymentsRepository,
+};
+use test_db::{connect, database_url, order};
+use tokio::runtime::Runtime;
+use tokio_postgres::Transaction;
+
+/// The item insert as it was before the switch to `UNNEST`: one statement per item.
+async fn insert_items_loop(tx: &Transaction<'_>, items: &[Item], order_uid: &str) {
+    let query = r#"
+        INSERT INTO items (order_uid, chrt_id, track_number, price, rid, name, sale, size, total_price, nm_id, brand, status)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+    "#;
+    for it in items {
+        tx.execute(
+            query,
+            &[
+                &order_uid,
+                &it.chrt_id,
+                &it.track_number,
+                &it.price,
+                &it.rid,
+                &it.name,
+                &it.sale,
+                &it.size,
+                &it.total_price,
+                &it.nm_id,
+                &it.brand,
+                &it.status,
+            ],
+        )
+        .await
+        .unwrap();
+    }
+}
+
+fn bench_inserts(c: &mut Criterion) {
+    let dsn = database_url();
+    let rt = Runtime::new().unwrap();
+    let mut conn = rt.block_on(connect(&dsn));
+    let orders_repo = PgOrdersRepository::new(rt.block_on(connect(&dsn)));
+    let deliveries_repo = PgDeliveriesRepository::new(rt.block_on(connect(&dsn)));
+    let payments_repo = PgPaymentsRepository::new(rt.block_on(connect(&dsn)));
+    let items_repo = PgItemsRepository::new(rt.block_on(connect(&dsn)));
+
+    let mut group = c.benchmark_group("items_insert");
+    for item_count in [1, 10, 100, 1000] {
+        let order = order("bench-items", item_count);
+
+        group.bench_with_input(BenchmarkId::new("loop", item_count), &order, |b, order| {
+            b.iter(|| {
+                rt.block_on(async {
+                    let tx = conn.transaction().await.unwrap();
+                    orders_repo.insert_tx(&tx, order).await.unwrap();
+                    insert_items_loop(&tx, &order.items, &order.order_uid).await;
+                    tx.rollback().await.unwrap();
+                })
+            })
+        });
+        group.bench_with_input(
+            BenchmarkId::new("unnest", item_count),
+            &order,
+            |b, order| {
+                b.iter(|| {
+                    rt.block_on(async {
+                        let tx = conn.transaction().await.unwrap();
+                        orders_repo.insert_tx(&tx, order).await.unwrap();
+                        items_repo
+                            .insert_tx(&tx, &order.items, &order.order_uid)
+                            .await
+                            .unwrap();
+                        tx.rollback().await.unwrap();
+                    })
+                })
+            },
+        );
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("orders_insert");
+    for order_count in [10, 100] {
+        let orders: Vec<Order> = (0..order_count)
+            .map(|i| order(&format!("bench-order-{i}"), 5))
+            .collect();
+
+        group.bench_with_input(
+            BenchmarkId::new("per_order", order_count),
+            &orders,
+            |b, orders| {
+                b.iter(|| {
+                    rt.block_on(async {
+                        let tx = conn.transaction().await.unwrap();
+                        for order in orders {
+                            orders_repo.insert_tx(&tx, order).await.unwrap();
+                            deliveries_repo
+                                .insert_tx(&tx, &order.delivery, &order.order_uid)
+                                .await
+                                .unwrap();
+                            payments_repo
+                                .insert_tx(&tx, &order.payment, &order.order_uid)
+                                .await
+                                .unwrap();
+                            insert_items_loop(&tx, &order.items, &order.order_uid).await;
+                        }
+                        tx.rollback().await.unwrap();
+                    })
+                })
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("bulk", order_count),
+            &orders,
+            |b, orders| {
+                let deliveries: Vec<(&str, &Delivery)> = orders
+                    .iter()
+                    .map(|o| (o.order_uid.as_str(), &o.delivery))
+                    .collect();
+                let payments: Vec<(&str, &Payment)> = orders
+                    .iter()
+                    .map(|o| (o.order_uid.as_str(), &o.payment))
+                    .collect();
+                let items: Vec<(&str, &[Item])> = orders
+                    .iter()
+                    .map(|o| (o.order_uid.as_str(), o.items.as_slice()))
+                    .collect();
+                b.iter(|| {
+                    rt.block_on(async {
+                        let tx = conn.transaction().await.unwrap();
+                        orders_repo.insert_many_tx(&tx, orders).await.unwrap();
+                        deliveries_repo
+                            .insert_many_tx(&tx, &deliveries)
+                            .await
+                            .unwrap();
+                        payments_repo.insert_many_tx(&tx, &payments).await.unwrap();
+                        items_repo.insert_many_tx(&tx, &items).await.unwrap();
+                        tx.rollback().await.unwrap();
+                    })
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_inserts);
+criterion_main!(benches);
@@ -4,11 +4,20 @@
 //! for all entities: orders, deliveries, payments, items.
 //! Each repository supports both regular and transactional operations
 //! for integration with service/business logic.
+//!
+//! Multi-row writes (all items of an order, or many orders at once) are issued as
+//! a single `INSERT ... SELECT * FROM UNNEST(...)` statement with one array
+//! parameter per column, instead of one `INSERT` per row.
+//!
+//! Reads and inserts run in `<table>.<method>` spans, so they show up in the
+//! trace of the request or message that caused them.
 
 use async_trait::async_trait;
 use model::{Delivery, Item, Order, Payment};
 use thiserror::Error;
+use tokio_postgres::types::ToSql;
 use tokio_postgres::{Client, Transaction};
+use tracing::instrument;
 
 /// # RepositoryError
 ///
@@ -47,6 +56,20 @@ pub trait DeliveriesRepository: Send + Sync {
         order_uid: &str,
     ) -> Result<(), RepositoryError>;
 
+    /// Insert delivery records of many orders in a transaction, using a single statement.
+    async fn insert_many_tx(
+        &self,
+        tx: &Transaction<'_>,
+        deliveries: &[(&str, &Delivery)],
+    ) -> Result<(), RepositoryError>;
+
+    /// Delete the delivery record of an order in a transaction, returning the number of deleted rows.
+    async fn delete_by_order_id_tx(
+        &self,
+        tx: &Transaction<'_>,
+        order_uid: &str,
+    ) -> Result<u64, RepositoryError>;
+
     /// Get delivery info by order ID.
     async fn get_by_order_id(&self, order_uid: &str) -> Result<Delivery, RepositoryError>;
 }
@@ -68,6 +91,7 @@ impl PgDeliveriesRepository {
 
 #[async_trait]
 impl DeliveriesRepository for PgDeliveriesRepository {
+    #[instrument(name = "deliveries.insert", skip_all)]
     async fn insert(&self, delivery: &Delivery, order_uid: &str) -> Result<(), RepositoryError> {
         let query = r#"
             INSERT INTO deliveries (order_uid, name, phone, zip, city, address, region, email)
@@ -91,6 +115,7 @@ impl DeliveriesRepository for PgDeliveriesRepository {
         Ok(())
     }
 
+    #[instrument(name = "deliveries.insert_tx", skip_all)]
     async fn insert_tx(
         &self,
         tx: &Transaction<'_>,
@@ -118,6 +143,55 @@ impl DeliveriesRepository for PgDeliveriesRepository {
         Ok(())
     }
 
+    #[instrument(name = "deliveries.insert_many_tx", skip_all)]
+    async fn insert_many_tx(
+        &self,
+        tx: &Transaction<'_>,
+        deliveries: &[(&str, &Delivery)],
+    ) -> Result<(), RepositoryError> {
+        if deliveries.is_empty() {
+            return Ok(());
+        }
+        let query = r#"
+            INSERT INTO deliveries (order_uid, name, phone, zip, city, address, region, email)
+            SELECT * FROM UNNEST(
+                $1::text[], $2::text[], $3::text[], $4::text[],
+                $5::text[], $6::text[], $7::text[], $8::text[]
+            )
+        "#;
+        let column = |f: fn(&Delivery) -> &str| -> Vec<&str> {
+            deliveries.iter().map(|(_, d)| f(d)).collect()
+        };
+        let order_uids: Vec<&str> = deliveries.iter().map(|(uid, _)| *uid).collect();
+        tx.execute(
+            query,
+            &[
+                &order_uids,
+                &column(|d| &d.name),
+                &column(|d| &d.phone),
+                &column(|d| &d.zip),
+                &column(|d| &d.city),
+                &column(|d| &d.address),
+                &column(|d| &d.region),
+                &column(|d| &d.email),
+            ],
+        )
+        .await?;
+        Ok(())
+    }
+
+    #[instrument(name = "deliveries.delete_by_order_id_tx", skip_all)]
+    async fn delete_by_order_id_tx(
+        &self,
+        tx: &Transaction<'_>,
+        order_uid: &str,
+    ) -> Result<u64, RepositoryError> {
+        Ok(tx
+            .execute("DELETE FROM deliveries WHERE order_uid = $1", &[&order_uid])
+            .await?)
+    }
+
+    #[instrument(name = "deliveries.get_by_order_id", skip_all)]
     async fn get_by_order_id(&self, order_uid: &str) -> Result<Delivery, RepositoryError> {
         let query = r#"
             SELECT name, phone, zip, city, address, region, email
@@ -152,16 +226,96 @@ impl DeliveriesRepository for PgDeliveriesRepository {
 
 #[async_trait]
 pub trait ItemsRepository: Send + Sync {
+    /// Insert all items of an order (outside of transaction), using a single statement.
     async fn insert(&self, items: &[Item], order_uid: &str) -> Result<(), RepositoryError>;
+    /// Insert all items of an order in a transaction, using a single statement.
     async fn insert_tx(
         &self,
         tx: &Transaction<'_>,
         items: &[Item],
         order_uid: &str,
     ) -> Result<(), RepositoryError>;
+    /// Insert the items of many orders in a transaction, using a single statement.
+    async fn insert_many_tx(
+        &self,
+        tx: &Transaction<'_>,
+        items: &[(&str, &[Item])],
+    ) -> Result<(), RepositoryError>;
+    /// Delete the items of an order in a transaction, returning the number of deleted rows.
+    async fn delete_by_order_id_tx(
+        &self,
+        tx: &Transaction<'_>,
+        order_uid: &str,
+    ) -> Result<u64, RepositoryError>;
     async fn get_by_order_id(&self, order_uid: &str) -> Result<Vec<Item>, RepositoryError>;
 }
 
+/// Multi-row `INSERT` for items, fed by one array parameter per column.
+const INSERT_ITEMS_UNNEST: &str = r#"
+    INSERT INTO items (order_uid, chrt_id, track_number, price, rid, name, sale, size, total_price, nm_id, brand, status)
+    SELECT * FROM UNNEST(
+        $1::text[], $2::int4[], $3::text[], $4::int4[], $5::text[], $6::text[],
+        $7::int4[], $8::text[], $9::int4[], $10::int4[], $11::text[], $12::int4[]
+    )
+"#;
+
+/// Column-wise view of item rows, bound as arrays to [`INSERT_ITEMS_UNNEST`].
+#[derive(Default)]
+struct ItemColumns<'a> {
+    order_uid: Vec<&'a str>,
+    chrt_id: Vec<i32>,
+    track_number: Vec<&'a str>,
+    price: Vec<i32>,
+    rid: Vec<&'a str>,
+    name: Vec<&'a str>,
+    sale: Vec<i32>,
+    size: Vec<&'a str>,
+    total_price: Vec<i32>,
+    nm_id: Vec<i32>,
+    brand: Vec<&'a str>,
+    status: Vec<i32>,
+}
+
+impl<'a> ItemColumns<'a> {
+    fn push(&mut self, order_uid: &'a str, items: &'a [Item]) {
+        for it in items {
+            self.order_uid.push(order_uid);
+            self.chrt_id.push(it.chrt_id);
+            self.track_number.push(&it.track_number);
+            self.price.push(it.price);
+            self.rid.push(&it.rid);
+            self.name.push(&it.name);
+            self.sale.push(it.sale);
+            self.size.push(&it.size);
+            self.total_price.push(it.total_price);
+            self.nm_id.push(it.nm_id);
+            self.brand.push(&it.brand);
+            self.status.push(it.status);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.order_uid.is_empty()
+    }
+
+    fn params(&self) -> [&(dyn ToSql + Sync); 12] {
+        [
+            &self.order_uid,
+            &self.chrt_id,
+            &self.track_number,
+            &self.price,
+            &self.rid,
+            &self.name,
+            &self.sale,
+            &self.size,
+            &self.total_price,
+            &self.nm_id,
+            &self.brand,
+            &self.status,
+        ]
+    }
+}
+
 /// PostgreSQL implementation of the ItemsRepository trait.
 ///
 /// This struct provides methods to store and retrieve order items
@@ -179,72 +333,69 @@ impl PgItemsRepository {
 
 #[async_trait]
 impl ItemsRepository for PgItemsRepository {
+    #[instrument(name = "items.insert", skip_all)]
     async fn insert(&self, items: &[Item], order_uid: &str) -> Result<(), RepositoryError> {
-        let query = r#"
-            INSERT INTO items (order_uid, chrt_id, track_number, price, rid, name, sale, size, total_price, nm_id, brand, status)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
-        "#;
-        for it in items {
-            self.db
-                .execute(
-                    query,
-                    &[
-                        &order_uid,
-                        &it.chrt_id,
-                        &it.track_number,
-                        &it.price,
-                        &it.rid,
-                        &it.name,
-                        &it.sale,
-                        &it.size,
-                        &it.total_price,
-                        &it.nm_id,
-                        &it.brand,
-                        &it.status,
-                    ],
-                )
-                .await?;
+        let mut columns = ItemColumns::default();
+        columns.push(order_uid, items);
+        if columns.is_empty() {
+            return Ok(());
         }
+        self.db
+            .execute(INSERT_ITEMS_UNNEST, &columns.params())
+            .await?;
         Ok(())
     }
 
+    #[instrument(name = "items.insert_tx", skip_all)]
     async fn insert_tx(
         &self,
         tx: &Transaction<'_>,
         items: &[Item],
         order_uid: &str,
     ) -> Result<(), RepositoryError> {
-        let query = r#"
-            INSERT INTO items (order_uid, chrt_id, track_number, price, rid, name, sale, size, total_price, nm_id, brand, status)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
-        "#;
-        for it in items {
-            tx.execute(
-                query,
-                &[
-                    &order_uid,
-                    &it.chrt_id,
-                    &it.track_number,
-                    &it.price,
-                    &it.rid,
-                    &it.name,
-                    &it.sale,
-                    &it.size,
-                    &it.total_price,
-                    &it.nm_id,
-                    &it.brand,
-                    &it.status,
-                ],
-            )
-            .await?;
+        let mut columns = ItemColumns::default();
+        columns.push(order_uid, items);
+        if columns.is_empty() {
+            return Ok(());
+        }
+        tx.execute(INSERT_ITEMS_UNNEST, &columns.params()).await?;
+        Ok(())
+    }
+
+    #[instrument(name = "items.insert_many_tx", skip_all)]
+    async fn insert_many_tx(
+        &self,
+        tx: &Transaction<'_>,
+        items: &[(&str, &[Item])],
+    ) -> Result<(), RepositoryError> {
+        let mut columns = ItemColumns::default();
+        for (order_uid, order_items) in items {
+            columns.push(order_uid, order_items);
         }
+        if columns.is_empty() {
+            return Ok(());
+        }
+        tx.execute(INSERT_ITEMS_UNNEST, &columns.params()).await?;
         Ok(())
     }
 
+    #[instrument(name = "items.delete_by_order_id_tx", skip_all)]
+    async fn delete_by_order_id_tx(
+        &self,
+        tx: &Transaction<'_>,
+        order_uid: &str,
+    ) -> Result<u64, RepositoryError> {
+        Ok(tx
+            .execute("DELETE FROM items WHERE order_uid = $1", &[&order_uid])
+            .await?)
+    }
+
+    #[instrument(name = "items.get_by_order_id", skip_all)]
     async fn get_by_order_id(&self, order_uid: &str) -> Result<Vec<Item>, RepositoryError> {
         let query = r#"
             SELECT chrt_id, track_number, price, rid, name, sale, size, total_price, nm_id, brand, status
             FROM items WHERE order_uid = $1
+            ORDER BY id
         "#;
         let rows = self.db.query(query, &[&order_uid]).await?;
         let mut items = Vec::new();
@@ -282,6 +433,32 @@ impl ItemsRepository for PgItemsRepository {
 pub trait OrdersRepository: Send + Sync {
     async fn insert(&self, order: &Order) -> Result<(), RepositoryError>;
     async fn insert_tx(&self, tx: &Transaction<'_>, order: &Order) -> Result<(), RepositoryError>;
+    /// Insert many order rows in a transaction, using a single statement.
+    ///
+    /// Only the `orders` table is written; related entities are inserted by their own repositories.
+    async fn insert_many_tx(
+        &self,
+        tx: &Transaction<'_>,
+        orders: &[Order],
+    ) -> Result<(), RepositoryError>;
+    /// Check in a transaction whether an order exists, locking its row until the transaction ends.
+    async fn exists_tx(
+        &self,
+        tx: &Transaction<'_>,
+        order_uid: &str,
+    ) -> Result<bool, RepositoryError>;
+    /// Update an order row in place in a transaction, returning the number of updated rows.
+    ///
+    /// Only the `orders` table is written; related entities are replaced by their own repositories.
+    async fn update_tx(&self, tx: &Transaction<'_>, order: &Order) -> Result<u64, RepositoryError>;
+    /// Delete an order row in a transaction, returning the number of deleted rows.
+    ///
+    /// Related entities reference the order and must be deleted first by their own repositories.
+    async fn delete_tx(
+        &self,
+        tx: &Transaction<'_>,
+        order_uid: &str,
+    ) -> Result<u64, RepositoryError>;
     async fn get_by_id(&self, order_uid: &str) -> Result<Order, RepositoryError>;
 }
 
@@ -303,12 +480,14 @@ impl PgOrdersRepository {
 
 #[async_trait]
 impl OrdersRepository for PgOrdersRepository {
+    #[instrument(name = "orders.insert", skip_all)]
     async fn insert(&self, order: &Order) -> Result<(), RepositoryError> {
         let query = r#"
             INSERT INTO orders (
                 order_uid, track_number, entry, locale, internal_signature,
-                customer_id, delivery_service, shardkey, sm_id, date_created, oof_shard
-            ) VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11)
+                customer_id, delivery_service, shardkey, sm_id, date_created, oof_shard,
+                source_topic
+            ) VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11,$12)
         "#;
         self.db
             .execute(
@@ -325,18 +504,21 @@ impl OrdersRepository for PgOrdersRepository {
                     &order.sm_id,
                     &order.date_created,
                     &order.oof_shard,
+                    &order.source_topic,
                 ],
             )
             .await?;
         Ok(())
     }
 
+    #[instrument(name = "orders.insert_tx", skip_all)]
     async fn insert_tx(&self, tx: &Transaction<'_>, order: &Order) -> Result<(), RepositoryError> {
         let query = r#"
             INSERT INTO orders (
                 order_uid, track_number, entry, locale, internal_signature,
-                customer_id, delivery_service, shardkey, sm_id, date_created, oof_shard
-            ) VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11)
+                customer_id, delivery_service, shardkey, sm_id, date_created, oof_shard,
+                source_topic
+            ) VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11,$12)
         "#;
         tx.execute(
             query,
@@ -352,16 +534,121 @@ impl OrdersRepository for PgOrdersRepository {
                 &order.sm_id,
                 &order.date_created,
                 &order.oof_shard,
+                &order.source_topic,
+            ],
+        )
+        .await?;
+        Ok(())
+    }
+
+    #[instrument(name = "orders.insert_many_tx", skip_all)]
+    async fn insert_many_tx(
+        &self,
+        tx: &Transaction<'_>,
+        orders: &[Order],
+    ) -> Result<(), RepositoryError> {
+        if orders.is_empty() {
+            return Ok(());
+        }
+        let query = r#"
+            INSERT INTO orders (
+                order_uid, track_number, entry, locale, internal_signature,
+                customer_id, delivery_service, shardkey, sm_id, date_created, oof_shard,
+                source_topic
+            )
+            SELECT * FROM UNNEST(
+                $1::text[], $2::text[], $3::text[], $4::text[], $5::text[], $6::text[],
+                $7::text[], $8::text[], $9::int4[], $10::timestamptz[], $11::text[], $12::text[]
+            )
+        "#;
+        let column = |f: fn(&Order) -> &str| -> Vec<&str> { orders.iter().map(f).collect() };
+        let sm_ids: Vec<i32> = orders.iter().map(|o| o.sm_id).collect();
+        let dates_created: Vec<_> = orders.iter().map(|o| o.date_created).collect();
+        let source_topics: Vec<Option<&str>> =
+            orders.iter().map(|o| o.source_topic.as_deref()).collect();
+        tx.execute(
+            query,
+            &[
+                &column(|o| &o.order_uid),
+                &column(|o| &o.track_number),
+                &column(|o| &o.entry),
+                &column(|o| &o.locale),
+                &column(|o| &o.internal_signature),
+                &column(|o| &o.customer_id),
+                &column(|o| &o.delivery_service),
+                &column(|o| &o.shardkey),
+                &sm_ids,
+                &dates_created,
+                &column(|o| &o.oof_shard),
+                &source_topics,
             ],
         )
         .await?;
         Ok(())
     }
 
+    #[instrument(name = "orders.exists_tx", skip_all)]
+    async fn exists_tx(
+        &self,
+        tx: &Transaction<'_>,
+        order_uid: &str,
+    ) -> Result<bool, RepositoryError> {
+        let row = tx
+            .query_opt(
+                "SELECT 1 FROM orders WHERE order_uid = $1 FOR UPDATE",
+                &[&order_uid],
+            )
+            .await?;
+        Ok(row.is_some())
+    }
+
+    #[instrument(name = "orders.update_tx", skip_all)]
+    async fn update_tx(&self, tx: &Transaction<'_>, order: &Order) -> Result<u64, RepositoryError> {
+        let query = r#"
+            UPDATE orders SET
+                track_number = $2, entry = $3, locale = $4, internal_signature = $5,
+                customer_id = $6, delivery_service = $7, shardkey = $8, sm_id = $9,
+                date_created = $10, oof_shard = $11, source_topic = $12
+            WHERE order_uid = $1
+        "#;
+        Ok(tx
+            .execute(
+                query,
+                &[
+                    &order.order_uid,
+                    &order.track_number,
+                    &order.entry,
+                    &order.locale,
+                    &order.internal_signature,
+                    &order.customer_id,
+                    &order.delivery_service,
+                    &order.shardkey,
+                    &order.sm_id,
+                    &order.date_created,
+                    &order.oof_shard,
+                    &order.source_topic,
+                ],
+            )
+            .await?)
+    }
+
+    #[instrument(name = "orders.delete_tx", skip_all)]
+    async fn delete_tx(
+        &self,
+        tx: &Transaction<'_>,
+        order_uid: &str,
+    ) -> Result<u64, RepositoryError> {
+        Ok(tx
+            .execute("DELETE FROM orders WHERE order_uid = $1", &[&order_uid])
+            .await?)
+    }
+
+    #[instrument(name = "orders.get_by_id", skip_all)]
     async fn get_by_id(&self, order_uid: &str) -> Result<Order, RepositoryError> {
         let query = r#"
             SELECT order_uid, track_number, entry, locale, internal_signature,
-                   customer_id, delivery_service, shardkey, sm_id, date_created, oof_shard
+                   customer_id, delivery_service, shardkey, sm_id, date_created, oof_shard,
+                   source_topic
             FROM orders WHERE order_uid = $1
         "#;
         let row = self.db.query_opt(query, &[&order_uid]).await?;
@@ -382,6 +669,7 @@ impl OrdersRepository for PgOrdersRepository {
                     sm_id: row.get("sm_id"),
                     date_created: row.get("date_created"),
                     oof_shard: row.get("oof_shard"),
+                    source_topic: row.get("source_topic"),
                 })
             }
             None => Err(RepositoryError::NotFound),
@@ -409,6 +697,18 @@ pub trait PaymentsRepository: Send + Sync {
         payment: &Payment,
         order_uid: &str,
     ) -> Result<(), RepositoryError>;
+    /// Insert payment records of many orders in a transaction, using a single statement.
+    async fn insert_many_tx(
+        &self,
+        tx: &Transaction<'_>,
+        payments: &[(&str, &Payment)],
+    ) -> Result<(), RepositoryError>;
+    /// Delete the payment record of an order in a transaction, returning the number of deleted rows.
+    async fn delete_by_order_id_tx(
+        &self,
+        tx: &Transaction<'_>,
+        order_uid: &str,
+    ) -> Result<u64, RepositoryError>;
     async fn get_by_order_id(&self, order_uid: &str) -> Result<Payment, RepositoryError>;
 }
 
@@ -430,6 +730,7 @@ impl PgPaymentsRepository {
 
 #[async_trait]
 impl PaymentsRepository for PgPaymentsRepository {
+    #[instrument(name = "payments.insert", skip_all)]
     async fn insert(&self, payment: &Payment, order_uid: &str) -> Result<(), RepositoryError> {
         let query = r#"
             INSERT INTO payments (
@@ -458,6 +759,7 @@ impl PaymentsRepository for PgPaymentsRepository {
         Ok(())
     }
 
+    #[instrument(name = "payments.insert_tx", skip_all)]
     async fn insert_tx(
         &self,
         tx: &Transaction<'_>,
@@ -490,6 +792,63 @@ impl PaymentsRepository for PgPaymentsRepository {
         Ok(())
     }
 
+    #[instrument(name = "payments.insert_many_tx", skip_all)]
+    async fn insert_many_tx(
+        &self,
+        tx: &Transaction<'_>,
+        payments: &[(&str, &Payment)],
+    ) -> Result<(), RepositoryError> {
+        if payments.is_empty() {
+            return Ok(());
+        }
+        let query = r#"
+            INSERT INTO payments (
+                order_uid, transaction, request_id, currency, provider, amount, payment_dt,
+                bank, delivery_cost, goods_total, custom_fee
+            )
+            SELECT * FROM UNNEST(
+                $1::text[], $2::text[], $3::text[], $4::text[], $5::text[], $6::int4[],
+                $7::int8[], $8::text[], $9::int4[], $10::int4[], $11::int4[]
+            )
+        "#;
+        let text =
+            |f: fn(&Payment) -> &str| -> Vec<&str> { payments.iter().map(|(_, p)| f(p)).collect() };
+        let int =
+            |f: fn(&Payment) -> i32| -> Vec<i32> { payments.iter().map(|(_, p)| f(p)).collect() };
+        let order_uids: Vec<&str> = payments.iter().map(|(uid, _)| *uid).collect();
+        let payment_dts: Vec<i64> = payments.iter().map(|(_, p)| p.payment_dt).collect();
+        tx.execute(
+            query,
+            &[
+                &order_uids,
+                &text(|p| &p.transaction),
+                &text(|p| &p.request_id),
+                &text(|p| &p.currency),
+                &text(|p| &p.provider),
+                &int(|p| p.amount),
+                &payment_dts,
+                &text(|p| &p.bank),
+                &int(|p| p.delivery_cost),
+                &int(|p| p.goods_total),
+                &int(|p| p.custom_fee),
+            ],
+        )
+        .await?;
+        Ok(())
+    }
+
+    #[instrument(name = "payments.delete_by_order_id_tx", skip_all)]
+    async fn delete_by_order_id_tx(
+        &self,
+        tx: &Transaction<'_>,
+        order_uid: &str,
+    ) -> Result<u64, RepositoryError> {
+        Ok(tx
+            .execute("DELETE FROM payments WHERE order_uid = $1", &[&order_uid])
+            .await?)
+    }
+
+    #[instrument(name = "payments.get_by_order_id", skip_all)]
     async fn get_by_order_id(&self, order_uid: &str) -> Result<Payment, RepositoryError> {
         let query = r#"
             SELECT transaction, request_id, currency, provider, amount, payment_dt,
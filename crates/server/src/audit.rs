@@ -0,0 +1,25 @@
+//! Audit log of access to unmasked customer PII.
+//!
+//! Events are logged under the [`AUDIT_TARGET`] target, so they can be filtered
+//! and shipped separately from the application log, e.g. with
+//! `LOG_LEVEL=warn,audit=info`.
+
+use tracing::info;
+
+/// Log target of audit events.
+pub const AUDIT_TARGET: &str = "audit";
+
+/// Records that `actor` received `order_count` unmasked orders from `resource`.
+///
+/// `actor` is the API key name or JWT subject, or `anonymous` when authentication
+/// is disabled.
+pub fn unmasked_access(actor: &str, request_id: Option<&str>, resource: &str, order_count: usize) {
+    info!(
+        target: AUDIT_TARGET,
+        actor,
+        request_id,
+        resource,
+        order_count,
+        "Unmasked order access"
+    );
+}
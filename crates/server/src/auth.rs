@@ -0,0 +1,435 @@
+//! Authentication and per-route authorization for the HTTP API.
+//!
+//! Callers authenticate with a static API key (`X-Api-Key: <key>` or
+//! `Authorization: Bearer <key>`) or a JWT (`Authorization: Bearer <jwt>`) signed
+//! with HS256 or RS256. API keys are configured as SHA-256 digests, so the keys
+//! themselves never appear in configuration.
+//!
+//! Every route declares the [`Access`] it needs in [`route_access`]; routes missing
+//! from that table require the `admin` scope.
+
+use anyhow::{Context, Result, bail};
+use app_config::AppConfig;
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::{HeaderMap, Method, StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use model::PiiView;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+/// Header carrying a static API key.
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Permission to use a group of routes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scope {
+    /// Read orders.
+    OrdersRead,
+    /// Create orders and send test orders.
+    OrdersWrite,
+    /// See customer PII unmasked in order responses.
+    PiiRead,
+    /// Administrative endpoints; implies every other scope.
+    Admin,
+}
+
+impl FromStr for Scope {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "orders:read" => Ok(Self::OrdersRead),
+            "orders:write" => Ok(Self::OrdersWrite),
+            "pii:read" => Ok(Self::PiiRead),
+            "admin" => Ok(Self::Admin),
+            other => bail!(
+                "Unknown scope '{other}' (expected orders:read, orders:write, pii:read or admin)"
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::OrdersRead => "orders:read",
+            Self::OrdersWrite => "orders:write",
+            Self::PiiRead => "pii:read",
+            Self::Admin => "admin",
+        })
+    }
+}
+
+/// What a route requires of its caller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// No credentials needed.
+    Public,
+    /// Any authenticated caller.
+    Authenticated,
+    /// An authenticated caller with the given scope.
+    Scope(Scope),
+}
+
+/// Returns the access required for a matched route.
+pub fn route_access(method: &Method, path: &str, public_health_metrics: bool) -> Access {
+    match (method.as_str(), path) {
+        ("GET", "/api/openapi.json" | "/api/docs" | "/api/docs/redoc.standalone.js") => {
+            Access::Public
+        }
+        ("GET", "/health" | "/metrics") if public_health_metrics => Access::Public,
+        ("GET", "/health" | "/metrics") => Access::Authenticated,
+        (
+            "GET",
+            "/order/{id}"
+            | "/api/orders"
+            | "/api/orders/stream"
+            | "/api/orders/ws"
+            | "/api/exports/orders",
+        ) => Access::Scope(Scope::OrdersRead),
+        ("POST", "/api/orders" | "/api/send-test-order") => Access::Scope(Scope::OrdersWrite),
+        ("PUT", "/order/{id}") => Access::Scope(Scope::OrdersWrite),
+        _ => Access::Scope(Scope::Admin),
+    }
+}
+
+/// The authenticated caller, available to handlers as an extension.
+#[derive(Debug, Clone)]
+pub struct Principal {
+    /// API key name or JWT subject.
+    pub name: String,
+    scopes: HashSet<Scope>,
+}
+
+impl Principal {
+    /// Whether the caller was granted `scope`, directly or through `admin`.
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope) || self.scopes.contains(&Scope::Admin)
+    }
+
+    /// How the caller sees customer PII: support roles (without `pii:read`) get
+    /// masked orders.
+    pub fn pii_view(&self) -> PiiView {
+        if self.has_scope(Scope::PiiRead) {
+            PiiView::Full
+        } else {
+            PiiView::Masked
+        }
+    }
+}
+
+/// Why a request was not authenticated.
+#[derive(Debug, thiserror::Error)]
+pub enum AuthError {
+    #[error("missing credentials")]
+    Missing,
+    #[error("invalid API key")]
+    InvalidApiKey,
+    #[error("invalid token: {0}")]
+    InvalidToken(String),
+}
+
+/// JWT claims read by the authenticator.
+#[derive(Debug, Deserialize)]
+struct Claims {
+    sub: String,
+    /// Space-separated scopes, as in OAuth 2.0.
+    #[serde(default)]
+    scope: String,
+}
+
+/// Verifies API keys and JWTs.
+pub struct Authenticator {
+    /// API key principals by lowercase hex SHA-256 digest of the key.
+    api_keys: HashMap<String, Principal>,
+    /// Decoding key and validation per accepted JWT algorithm.
+    jwt: Vec<(Algorithm, DecodingKey, Validation)>,
+    public_health_metrics: bool,
+}
+
+impl Authenticator {
+    /// Builds the authenticator from the `auth_*` settings.
+    ///
+    /// # Errors
+    /// Returns an error for unknown scopes or an unreadable RS256 public key.
+    pub fn from_config(config: &AppConfig) -> Result<Self> {
+        let mut api_keys = HashMap::new();
+        for key in &config.auth_api_keys {
+            let scopes = key
+                .scopes
+                .iter()
+                .map(|scope| scope.parse())
+                .collect::<Result<_>>()
+                .with_context(|| format!("Invalid scopes for API key '{}'", key.name))?;
+            api_keys.insert(
+                key.key_sha256.clone(),
+                Principal {
+                    name: key.name.clone(),
+                    scopes,
+                },
+            );
+        }
+
+        let mut jwt = Vec::new();
+        if !config.auth_jwt_hs256_secret.is_empty() {
+            jwt.push((
+                Algorithm::HS256,
+                DecodingKey::from_secret(config.auth_jwt_hs256_secret.as_bytes()),
+            ));
+        }
+        if !config.auth_jwt_rs256_public_key_path.is_empty() {
+            let path = &config.auth_jwt_rs256_public_key_path;
+            let pem = std::fs::read(path)
+                .with_context(|| format!("Failed to read RS256 public key {path}"))?;
+            jwt.push((
+                Algorithm::RS256,
+                DecodingKey::from_rsa_pem(&pem).context("Invalid RS256 public key")?,
+            ));
+        }
+        let jwt = jwt
+            .into_iter()
+            .map(|(alg, key)| {
+                let mut validation = Validation::new(alg);
+                if config.auth_jwt_issuer.is_empty() {
+                    validation.iss = None;
+                } else {
+                    validation.set_issuer(&[&config.auth_jwt_issuer]);
+                }
+                if config.auth_jwt_audience.is_empty() {
+                    validation.validate_aud = false;
+                } else {
+                    validation.set_audience(&[&config.auth_jwt_audience]);
+                }
+                (alg, key, validation)
+            })
+            .collect();
+
+        Ok(Self {
+            api_keys,
+            jwt,
+            public_health_metrics: config.auth_public_health_metrics,
+        })
+    }
+
+    /// Identifies the caller from the request headers.
+    pub fn authenticate(&self, headers: &HeaderMap) -> Result<Principal, AuthError> {
+        if let Some(key) = headers.get(API_KEY_HEADER) {
+            let key = key.to_str().map_err(|_| AuthError::InvalidApiKey)?;
+            return self.authenticate_api_key(key);
+        }
+        let credentials = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(str::trim)
+            .ok_or(AuthError::Missing)?;
+        // JWTs have three dot-separated parts; anything else is taken as an API key.
+        if credentials.split('.').count() == 3 {
+            self.authenticate_jwt(credentials)
+        } else {
+            self.authenticate_api_key(credentials)
+        }
+    }
+
+    fn authenticate_api_key(&self, key: &str) -> Result<Principal, AuthError> {
+        let digest = hex::encode(Sha256::digest(key.as_bytes()));
+        self.api_keys
+            .get(&digest)
+            .cloned()
+            .ok_or(AuthError::InvalidApiKey)
+    }
+
+    fn authenticate_jwt(&self, token: &str) -> Result<Principal, AuthError> {
+        let header = jsonwebtoken::decode_header(token)
+            .map_err(|e| AuthError::InvalidToken(e.to_string()))?;
+        let (_, key, validation) = self
+            .jwt
+            .iter()
+            .find(|(alg, _, _)| *alg == header.alg)
+            .ok_or_else(|| AuthError::InvalidToken(format!("{:?} is not accepted", header.alg)))?;
+        let claims = jsonwebtoken::decode::<Claims>(token, key, validation)
+            .map_err(|e| AuthError::InvalidToken(e.to_string()))?
+            .claims;
+        Ok(Principal {
+            name: claims.sub,
+            // Scopes this service does not know are ignored.
+            scopes: claims
+                .scope
+                .split_whitespace()
+                .filter_map(|scope| scope.parse().ok())
+                .collect(),
+        })
+    }
+}
+
+/// Authenticates the request and checks it against the matched route's [`Access`].
+///
+/// Responds `401` with `WWW-Authenticate` for missing or invalid credentials and
+/// `403` if the caller lacks the route's scope.
+pub(crate) async fn middleware(
+    State(auth): State<Arc<Authenticator>>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let path = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("", MatchedPath::as_str);
+    let access = route_access(req.method(), path, auth.public_health_metrics);
+    if access == Access::Public {
+        return next.run(req).await;
+    }
+
+    let principal = match auth.authenticate(req.headers()) {
+        Ok(principal) => principal,
+        Err(e) => {
+            warn!(
+                "Rejected unauthenticated request to {} {path}: {e}",
+                req.method()
+            );
+            return (
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, "Bearer")],
+                e.to_string(),
+            )
+                .into_response();
+        }
+    };
+    if let Access::Scope(scope) = access
+        && !principal.has_scope(scope)
+    {
+        warn!(
+            "Rejected {} {path} for {}: missing scope {scope}",
+            req.method(),
+            principal.name
+        );
+        return (StatusCode::FORBIDDEN, format!("missing scope {scope}")).into_response();
+    }
+
+    debug!(
+        "Authenticated {} for {} {path}",
+        principal.name,
+        req.method()
+    );
+    req.extensions_mut().insert(principal);
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use app_config::ApiKeyConfig;
+    use jsonwebtoken::{EncodingKey, Header, encode};
+    use serde_json::json;
+
+    const SECRET: &str = "test-secret";
+
+    fn authenticator() -> Authenticator {
+        let mut config = AppConfig::load().unwrap();
+        config.auth_api_keys = vec![ApiKeyConfig {
+            name: "support".into(),
+            key_sha256: hex::encode(Sha256::digest(b"support-key")),
+            scopes: vec!["orders:read".into()],
+        }];
+        config.auth_jwt_hs256_secret = SECRET.into();
+        config.auth_jwt_issuer = "https://issuer.test".into();
+        config.auth_jwt_audience = "shoppingcart".into();
+        Authenticator::from_config(&config).unwrap()
+    }
+
+    fn bearer(credentials: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            format!("Bearer {credentials}").parse().unwrap(),
+        );
+        headers
+    }
+
+    fn token(issuer: &str, scope: &str) -> String {
+        let claims = json!({
+            "sub": "ops-bot",
+            "iss": issuer,
+            "aud": "shoppingcart",
+            "scope": scope,
+            "exp": chrono::Utc::now().timestamp() + 60,
+        });
+        encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(SECRET.as_bytes()),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_api_key_authentication() {
+        let auth = authenticator();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(API_KEY_HEADER, "support-key".parse().unwrap());
+        let principal = auth.authenticate(&headers).unwrap();
+        assert_eq!(principal.name, "support");
+        assert!(principal.has_scope(Scope::OrdersRead));
+        assert!(!principal.has_scope(Scope::OrdersWrite));
+
+        assert!(auth.authenticate(&bearer("support-key")).is_ok());
+        assert!(matches!(
+            auth.authenticate(&bearer("wrong-key")),
+            Err(AuthError::InvalidApiKey)
+        ));
+        assert!(matches!(
+            auth.authenticate(&HeaderMap::new()),
+            Err(AuthError::Missing)
+        ));
+    }
+
+    #[test]
+    fn test_jwt_authentication() {
+        let auth = authenticator();
+
+        let principal = auth
+            .authenticate(&bearer(&token(
+                "https://issuer.test",
+                "orders:write unknown",
+            )))
+            .unwrap();
+        assert_eq!(principal.name, "ops-bot");
+        assert!(principal.has_scope(Scope::OrdersWrite));
+        assert!(!principal.has_scope(Scope::OrdersRead));
+
+        let admin = auth
+            .authenticate(&bearer(&token("https://issuer.test", "admin")))
+            .unwrap();
+        assert!(admin.has_scope(Scope::OrdersRead));
+
+        assert!(matches!(
+            auth.authenticate(&bearer(&token("https://other.test", "admin"))),
+            Err(AuthError::InvalidToken(_))
+        ));
+    }
+
+    #[test]
+    fn test_route_access() {
+        assert_eq!(route_access(&Method::GET, "/metrics", true), Access::Public);
+        assert_eq!(
+            route_access(&Method::GET, "/metrics", false),
+            Access::Authenticated
+        );
+        assert_eq!(
+            route_access(&Method::POST, "/api/orders", true),
+            Access::Scope(Scope::OrdersWrite)
+        );
+        assert_eq!(
+            route_access(&Method::POST, "/admin/consistency-check", true),
+            Access::Scope(Scope::Admin)
+        );
+    }
+}
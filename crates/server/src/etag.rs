@@ -0,0 +1,144 @@
+//! Entity tags of order resources, for conditional requests.
+//!
+//! An order's version is the cache's [`content_hash`] of the order, so it changes
+//! whenever any field does and is the same for a received order and its stored copy. The ETag of a response is that version qualified by the
+//! representation: masked views and non-JSON formats get a suffix, keeping tags
+//! strong, i.e. distinct for every distinct body. `If-Match` compares versions
+//! only, so any tag a client received for the current order allows updating it.
+
+use crate::ResponseFormat;
+use axum::http::{HeaderMap, HeaderValue, header};
+use cache::content_hash;
+use model::{Order, PiiView};
+
+/// Hex-encoded content hash of the stored order, shortened to 128 bits.
+pub fn order_version(order: &Order) -> String {
+    let mut version = content_hash(order);
+    version.truncate(32);
+    version
+}
+
+/// The quoted ETag of `order` as served through `view` in `format`.
+pub fn order_etag(order: &Order, view: PiiView, format: ResponseFormat) -> HeaderValue {
+    let view = match view {
+        PiiView::Full => "",
+        PiiView::Masked => "-masked",
+    };
+    let format = match format {
+        ResponseFormat::Json => "",
+        ResponseFormat::NdJson => "-ndjson",
+        ResponseFormat::MsgPack => "-msgpack",
+    };
+    let tag = format!("\"{}{view}{format}\"", order_version(order));
+    HeaderValue::from_str(&tag).expect("hex digits and ASCII suffixes are valid")
+}
+
+/// Entity tags listed in the header `name`, if the request has it.
+fn listed_tags(headers: &HeaderMap, name: header::HeaderName) -> Option<Vec<&str>> {
+    let values: Vec<&str> = headers
+        .get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .collect();
+    if values.is_empty() {
+        return None;
+    }
+    Some(
+        values
+            .into_iter()
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+            .collect(),
+    )
+}
+
+/// Whether `If-None-Match` lists `etag` (by weak comparison) or `*`.
+pub fn none_match_hits(headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    let Ok(etag) = etag.to_str() else {
+        return false;
+    };
+    listed_tags(headers, header::IF_NONE_MATCH).is_some_and(|tags| {
+        tags.iter()
+            .any(|tag| *tag == "*" || tag.trim_start_matches("W/") == etag)
+    })
+}
+
+/// The outcome of checking `If-Match` against an order's current version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IfMatch {
+    /// The request has no `If-Match` header.
+    Missing,
+    /// A listed tag (or `*`) matches the current version.
+    Matches,
+    /// No listed tag matches; the client's copy is out of date.
+    Stale,
+}
+
+/// Whether the request has an `If-Match` header.
+pub fn has_if_match(headers: &HeaderMap) -> bool {
+    listed_tags(headers, header::IF_MATCH).is_some()
+}
+
+/// Checks `If-Match` against `version`, ignoring weak tags as RFC 9110 requires.
+pub fn check_if_match(headers: &HeaderMap, version: &str) -> IfMatch {
+    let Some(tags) = listed_tags(headers, header::IF_MATCH) else {
+        return IfMatch::Missing;
+    };
+    let matches = tags.iter().any(|tag| {
+        *tag == "*"
+            || tag
+                .strip_prefix('"')
+                .and_then(|t| t.strip_suffix('"'))
+                .and_then(|t| t.split('-').next())
+                == Some(version)
+    });
+    if matches {
+        IfMatch::Matches
+    } else {
+        IfMatch::Stale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tags_follow_content_and_representation() {
+        let mut order = Order {
+            order_uid: "b563feb7b2b84b6test".into(),
+            ..Default::default()
+        };
+        let version = order_version(&order);
+        let full = order_etag(&order, PiiView::Full, ResponseFormat::Json);
+        let masked = order_etag(&order, PiiView::Masked, ResponseFormat::MsgPack);
+        assert_eq!(full, format!("\"{version}\"").as_str());
+        assert_eq!(masked, format!("\"{version}-masked-msgpack\"").as_str());
+
+        order.track_number = "WBILMTESTTRACK".into();
+        assert_ne!(order_version(&order), version);
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(check_if_match(&headers, &version), IfMatch::Missing);
+        headers.insert(header::IF_MATCH, masked.clone());
+        assert_eq!(check_if_match(&headers, &version), IfMatch::Matches);
+        assert_eq!(
+            check_if_match(&headers, &order_version(&order)),
+            IfMatch::Stale
+        );
+        headers.insert(
+            header::IF_MATCH,
+            format!("W/{}", full.to_str().unwrap()).parse().unwrap(),
+        );
+        assert_eq!(check_if_match(&headers, &version), IfMatch::Stale);
+
+        headers.insert(header::IF_NONE_MATCH, "\"other\", W/".parse().unwrap());
+        assert!(!none_match_hits(&headers, &full));
+        headers.append(
+            header::IF_NONE_MATCH,
+            format!("W/{}", full.to_str().unwrap()).parse().unwrap(),
+        );
+        assert!(none_match_hits(&headers, &full));
+    }
+}
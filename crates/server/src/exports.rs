@@ -0,0 +1,222 @@
+//! Bulk export of stored orders over HTTP.
+//!
+//! `GET /api/exports/orders` streams the orders matching its query as CSV,
+//! NDJSON or Parquet, read from Postgres page by page while the response is
+//! sent. Customer PII is masked as on the other read endpoints, and filtering by
+//! customer needs the `pii:read` scope. Unmasked exports are audited once they
+//! end, with the number of orders exported.
+
+use crate::auth::Scope;
+use crate::request_id::ErrorBody;
+use crate::{AppState, Principal, RequestId, audit};
+use axum::Extension;
+use axum::body::{Body, Bytes};
+use axum::extract::{Query, State};
+use axum::http::{HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
+use export::{Encoder, ExportError, ExportFilter, ExportFormat, OrderPages, RowLayout};
+use futures_util::stream;
+use model::{Order, PiiView};
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+use utoipa::IntoParams;
+
+/// Route of the export, also the resource named in audit records.
+const RESOURCE: &str = "/api/exports/orders";
+
+/// Which orders to export, and how.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct ExportParams {
+    /// `csv` (default), `ndjson` or `parquet`
+    format: Option<String>,
+    /// Rows of CSV and Parquet exports: `order` (default) or `item`
+    rows: Option<String>,
+    /// Only orders created at or after this RFC 3339 time
+    from: Option<DateTime<Utc>>,
+    /// Only orders created before this RFC 3339 time
+    to: Option<DateTime<Utc>>,
+    /// Comma-separated delivery services, e.g. `meest,dhl`; all if absent
+    delivery_service: Option<String>,
+    /// Comma-separated currency codes, e.g. `USD,EUR`; all if absent
+    currency: Option<String>,
+    /// Only orders of this customer; needs the `pii:read` scope
+    customer_id: Option<String>,
+}
+
+impl ExportParams {
+    fn filter(&self) -> ExportFilter {
+        let list = |list: &Option<String>| -> Vec<String> {
+            list.as_deref()
+                .into_iter()
+                .flat_map(|list| list.split(','))
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_string)
+                .collect()
+        };
+        ExportFilter {
+            from: self.from,
+            to: self.to,
+            delivery_services: list(&self.delivery_service),
+            currencies: list(&self.currency),
+            customer_id: self.customer_id.clone(),
+        }
+    }
+}
+
+/// Export stored orders as a file.
+///
+/// Orders are read page by page and streamed oldest first, as CSV or Parquet
+/// rows (one per order or per item, with delivery and payment fields
+/// flattened) or as NDJSON of full orders. Customer PII is masked unless the
+/// caller has the `pii:read` scope, which filtering by customer also needs.
+#[utoipa::path(
+    get,
+    path = "/api/exports/orders",
+    tag = "orders",
+    params(ExportParams),
+    responses(
+        (status = 200, description = "The exported orders", content(
+            (String = "text/csv"),
+            (String = "application/x-ndjson"),
+            (String = "application/vnd.apache.parquet"),
+        )),
+        (status = 400, description = "Invalid format, row layout or filter", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Missing scope", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded", body = ErrorBody),
+        (status = 500, description = "The orders could not be read", body = ErrorBody),
+    ),
+    security(("api_key" = ["orders:read"]), ("bearer" = ["orders:read"]))
+)]
+pub(crate) async fn handle_export_orders(
+    State(state): State<AppState>,
+    principal: Option<Extension<Principal>>,
+    Extension(request_id): Extension<RequestId>,
+    Query(params): Query<ExportParams>,
+) -> Response {
+    let format = match params.format.as_deref().map(str::parse).transpose() {
+        Ok(format) => format.unwrap_or(ExportFormat::Csv),
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let layout = match params.rows.as_deref().map(str::parse).transpose() {
+        Ok(layout) => layout.unwrap_or(RowLayout::Order),
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    if params.customer_id.is_some()
+        && let Some(Extension(principal)) = &principal
+        && !principal.has_scope(Scope::PiiRead)
+    {
+        warn!(
+            "Rejected export by customer for {}: missing scope",
+            principal.name
+        );
+        return (
+            StatusCode::FORBIDDEN,
+            format!("filtering by customer requires scope {}", Scope::PiiRead),
+        )
+            .into_response();
+    }
+
+    // Without authentication there is no caller to restrict, as on the other routes
+    let view = principal
+        .as_deref()
+        .map_or(PiiView::Full, Principal::pii_view);
+    let encoder = match Encoder::new(format, layout, view) {
+        Ok(encoder) => encoder,
+        Err(e) => {
+            error!("Failed to start export: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "failed to start export").into_response();
+        }
+    };
+    let mut pages = OrderPages::new(
+        state.db_pool.clone(),
+        params.filter(),
+        state.export_page_size,
+    );
+    // Failures before the first byte still get a proper error response
+    let first = match pages.next_page().await {
+        Ok(first) => first,
+        Err(e) => {
+            error!("Failed to read orders to export: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "failed to read orders").into_response();
+        }
+    };
+    info!("Exporting orders as {format} ({layout} rows)");
+
+    // A small channel keeps the reader at most a couple of pages ahead of the client
+    let (sender, receiver) = mpsc::channel(2);
+    let actor = principal.map(|Extension(p)| p.name);
+    tokio::spawn(async move {
+        let (exported, result) = produce(pages, first, encoder, &sender).await;
+        match result {
+            Ok(()) => info!("Exported {exported} orders"),
+            Err(e) => {
+                error!("Export failed after {exported} orders: {}", e);
+                // Aborts the response, so the client sees an incomplete transfer
+                let _ = sender.send(Err(e)).await;
+            }
+        }
+        if view == PiiView::Full {
+            let actor = actor.as_deref().unwrap_or("anonymous");
+            audit::unmasked_access(actor, Some(&request_id.0), RESOURCE, exported);
+        }
+    });
+
+    let body = Body::from_stream(stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    }));
+    let disposition = format!("attachment; filename=\"orders.{}\"", format.extension());
+    (
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(format.content_type()),
+            ),
+            (
+                header::CONTENT_DISPOSITION,
+                HeaderValue::from_str(&disposition).expect("the file name is ASCII"),
+            ),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+/// Encodes the pages into `sender`, returning the number of orders sent.
+///
+/// Stops early without an error once the client has gone.
+async fn produce(
+    mut pages: OrderPages,
+    first: Option<Vec<Order>>,
+    mut encoder: Encoder,
+    sender: &mpsc::Sender<Result<Bytes, ExportError>>,
+) -> (usize, Result<(), ExportError>) {
+    let mut exported = 0;
+    let mut page = first;
+    while let Some(orders) = page {
+        let chunk = match encoder.encode(&orders) {
+            Ok(chunk) => chunk,
+            Err(e) => return (exported, Err(e)),
+        };
+        exported += orders.len();
+        if !chunk.is_empty() && sender.send(Ok(chunk.into())).await.is_err() {
+            warn!("Export client disconnected");
+            return (exported, Ok(()));
+        }
+        page = match pages.next_page().await {
+            Ok(page) => page,
+            Err(e) => return (exported, Err(e)),
+        };
+    }
+    match encoder.finish() {
+        Ok(chunk) => {
+            let _ = sender.send(Ok(chunk.into())).await;
+            (exported, Ok(()))
+        }
+        Err(e) => (exported, Err(e)),
+    }
+}
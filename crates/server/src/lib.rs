@@ -3,47 +3,103 @@
 //! This module implements an HTTP server for handling order-related requests,
 //! including retrieving orders, sending test orders, and serving static content.
 
-use std::path::Path;
+pub mod audit;
+pub mod auth;
+mod etag;
+mod exports;
+mod live;
+mod negotiate;
+pub mod openapi;
+mod rate_limit;
+mod request_id;
+mod static_files;
+mod ws;
+
+pub use auth::{Authenticator, Principal};
+pub use negotiate::ResponseFormat;
+pub use rate_limit::RateLimiter;
+pub use request_id::RequestId;
+pub use static_files::StaticFiles;
+
+use std::net::SocketAddr;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::task::{Context, Poll};
 use std::time::Duration;
 
-use anyhow::{Context, Result};
+use anyhow::{Context as _, Result};
 use axum::{
-    Router,
-    extract::{Path as AxumPath, State},
-    http::StatusCode,
+    Extension, Json, Router,
+    body::{Body, Bytes, HttpBody},
+    extract::{MatchedPath, Path as AxumPath, Query, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
     response::{IntoResponse, Response},
-    routing::{get, post},
+    routing::get,
 };
-use cache::OrderCache;
+use cache::{ConsistencyChecker, OrderCache, OrderFeed, ScanMode};
+use chrono::SubsecRound;
 use deadpool_postgres::Pool;
-use prometheus::{CounterVec, HistogramOpts, HistogramVec, Opts, Registry};
+use http_body::{Frame, SizeHint};
+use kafka_producer::OrderProducer;
+use live::LiveOrders;
+use model::{Order, PiiView};
+use prometheus::{Counter, CounterVec, HistogramOpts, HistogramVec, Opts, Registry};
+use repository::RepositoryError;
+use request_id::ErrorBody;
+use serde::{Deserialize, Serialize};
+use service::{OrderChanges, OrderService, ServiceError};
 use tokio::net::TcpListener;
 use tokio::signal;
-use tracing::{error, info, warn};
+use tokio::sync::Semaphore;
+use tower_http::compression::CompressionLayer;
+use tower_http::compression::predicate::{DefaultPredicate, Predicate, SizeAbove};
+use tracing::{error, info, instrument, warn};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use utoipa_axum::{router::OpenApiRouter, routes};
+use ws::OrderSubscriptions;
 
 /// Server represents an HTTP server for working with orders.
 pub struct Server {
     cache: Arc<OrderCache>,
     static_dir: String,
+    /// Replaces serving from `static_dir` when set.
+    static_files: Option<Arc<StaticFiles>>,
     port: String,
     metrics: Arc<Metrics>,
     db_pool: Pool,
+    order_service: Arc<dyn OrderService>,
+    consistency_checker: Arc<ConsistencyChecker>,
+    auth: Option<Arc<Authenticator>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    concurrency_limit: Option<Arc<Semaphore>>,
+    compression_min_size: Option<u16>,
+    live_orders: Option<Arc<LiveOrders>>,
+    order_subscriptions: Option<Arc<OrderSubscriptions>>,
+    order_producer: Option<Arc<OrderProducer>>,
+    export_page_size: usize,
 }
 
+/// Endpoint label of requests that matched no route (static files and 404s).
+const UNMATCHED_ENDPOINT: &str = "unmatched";
+
 /// Metrics collects and exposes HTTP server metrics.
+///
+/// Requests are labelled with the route template they matched (e.g. `/order/{id}`),
+/// never with the raw path, so the number of series stays bounded.
 struct Metrics {
     registry: Registry,
     http_requests_total: CounterVec,
     http_request_duration_seconds: HistogramVec,
     errors_total: CounterVec,
     network_traffic_bytes: CounterVec,
+    rejected_requests_total: CounterVec,
 }
 
 impl Metrics {
-    fn new() -> Self {
-        let registry = Registry::new();
-
+    /// Creates the HTTP metrics and registers them in the shared `registry`.
+    ///
+    /// `duration_buckets` are the upper bounds of the request duration histogram.
+    fn new(registry: Registry, duration_buckets: Vec<f64>) -> Self {
         let http_requests_total = CounterVec::new(
             Opts::new("http_requests_total", "Total number of HTTP requests"),
             &["method", "endpoint", "status"],
@@ -54,7 +110,8 @@ impl Metrics {
             HistogramOpts::new(
                 "http_request_duration_seconds",
                 "HTTP request duration in seconds",
-            ),
+            )
+            .buckets(duration_buckets),
             &["method", "endpoint"],
         )
         .expect("Failed to create http_request_duration_seconds metric");
@@ -66,11 +123,23 @@ impl Metrics {
         .expect("Failed to create errors_total metric");
 
         let network_traffic_bytes = CounterVec::new(
-            Opts::new("network_traffic_bytes", "Network traffic in bytes"),
+            Opts::new(
+                "network_traffic_bytes",
+                "HTTP request and response body bytes",
+            ),
             &["direction"],
         )
         .expect("Failed to create network_traffic_bytes metric");
 
+        let rejected_requests_total = CounterVec::new(
+            Opts::new(
+                "http_rejected_requests_total",
+                "Requests rejected by rate or concurrency limits",
+            ),
+            &["reason"],
+        )
+        .expect("Failed to create http_rejected_requests_total metric");
+
         registry
             .register(Box::new(http_requests_total.clone()))
             .expect("Failed to register http_requests_total metric");
@@ -83,6 +152,9 @@ impl Metrics {
         registry
             .register(Box::new(network_traffic_bytes.clone()))
             .expect("Failed to register network_traffic_bytes metric");
+        registry
+            .register(Box::new(rejected_requests_total.clone()))
+            .expect("Failed to register http_rejected_requests_total metric");
 
         Self {
             registry,
@@ -90,6 +162,7 @@ impl Metrics {
             http_request_duration_seconds,
             errors_total,
             network_traffic_bytes,
+            rejected_requests_total,
         }
     }
 
@@ -108,11 +181,66 @@ impl Metrics {
             .inc();
     }
 
-    fn record_network_traffic(&self, direction: &str, bytes: usize) {
-        self.network_traffic_bytes
-            .with_label_values(&[direction])
-            .inc_by(bytes as f64);
+    /// Counts a request rejected before reaching its handler, by reason.
+    fn record_rejection(&self, reason: &str) {
+        self.rejected_requests_total
+            .with_label_values(&[reason])
+            .inc();
+    }
+
+    /// Wraps `body` so that every data frame read from it is counted as `direction` traffic.
+    fn count_traffic(&self, direction: &str, body: Body) -> Body {
+        Body::new(CountingBody {
+            inner: body,
+            bytes: self.network_traffic_bytes.with_label_values(&[direction]),
+        })
+    }
+}
+
+/// Body that adds the size of each data frame to a counter as it is polled.
+///
+/// Unlike the `Content-Length` header this also covers chunked bodies, and only
+/// counts what was actually transferred.
+struct CountingBody {
+    inner: Body,
+    bytes: Counter,
+}
+
+impl HttpBody for CountingBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<std::result::Result<Frame<Bytes>, axum::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &poll
+            && let Some(data) = frame.data_ref()
+        {
+            self.bytes.inc_by(data.len() as f64);
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
     }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Settings of the HTTP server, as opposed to the components it is wired to.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// The port on which the server will listen.
+    pub port: String,
+    /// The directory for static files (e.g., index.html).
+    pub static_dir: String,
+    /// Bucket upper bounds of the request duration histogram, in seconds.
+    pub duration_buckets: Vec<f64>,
 }
 
 impl Server {
@@ -120,25 +248,127 @@ impl Server {
     ///
     /// # Arguments
     ///
-    /// * `port` - The port on which the server will listen
+    /// * `config` - The port, static directory and metric buckets of the server
     /// * `cache` - The order cache for accessing orders
-    /// * `static_dir` - The directory for static files (e.g., index.html)
+    /// * `db_pool` - The database connection pool
+    /// * `order_service` - The order service used to load orders missing from the cache
+    /// * `registry` - The shared Prometheus registry served at `/metrics`
+    /// * `consistency_checker` - The cache consistency checker triggered via the admin endpoint
     ///
     /// # Returns
     ///
     /// A new Server instance
-    pub fn new(port: String, cache: Arc<OrderCache>, static_dir: String, db_pool: Pool) -> Self {
+    pub fn new(
+        config: ServerConfig,
+        cache: Arc<OrderCache>,
+        db_pool: Pool,
+        order_service: Arc<dyn OrderService>,
+        registry: Registry,
+        consistency_checker: Arc<ConsistencyChecker>,
+    ) -> Self {
+        let ServerConfig {
+            port,
+            static_dir,
+            duration_buckets,
+        } = config;
         info!("Initializing HTTP server on port {}", port);
 
         Self {
             cache,
             static_dir,
+            static_files: None,
             port,
-            metrics: Arc::new(Metrics::new()),
+            metrics: Arc::new(Metrics::new(registry, duration_buckets)),
             db_pool,
+            order_service,
+            consistency_checker,
+            auth: None,
+            rate_limiter: None,
+            concurrency_limit: None,
+            compression_min_size: None,
+            live_orders: None,
+            order_subscriptions: None,
+            order_producer: None,
+            export_page_size: export::DEFAULT_PAGE_SIZE,
         }
     }
 
+    /// Requires callers of the API routes to authenticate with `auth`.
+    ///
+    /// Without it every route is public.
+    pub fn with_auth(mut self, auth: Authenticator) -> Self {
+        self.auth = Some(Arc::new(auth));
+        self
+    }
+
+    /// Limits the request rate of each client per route with `limiter`.
+    pub fn with_rate_limiter(mut self, limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(Arc::new(limiter));
+        self
+    }
+
+    /// Sheds requests with `503` while `max` requests are being handled (0 means unlimited).
+    pub fn with_max_concurrent_requests(mut self, max: usize) -> Self {
+        self.concurrency_limit = (max > 0).then(|| Arc::new(Semaphore::new(max)));
+        self
+    }
+
+    /// Serves the static files compiled into the binary instead of `static_dir`.
+    #[cfg(feature = "embed-static")]
+    pub fn with_embedded_static(mut self) -> Self {
+        self.static_files = Some(Arc::new(StaticFiles::embedded()));
+        self
+    }
+
+    /// Compresses response bodies of at least `min_size` bytes with gzip, brotli or
+    /// zstd, as negotiated via `Accept-Encoding` (0 disables compression).
+    pub fn with_compression(mut self, min_size: u16) -> Self {
+        self.compression_min_size = (min_size > 0).then_some(min_size);
+        self
+    }
+
+    /// Serves the orders published to `feed` at `/api/orders/stream`, with a
+    /// heartbeat every `heartbeat` while no orders arrive.
+    ///
+    /// Without it the stream responds `404`.
+    pub fn with_order_stream(mut self, feed: Arc<OrderFeed>, heartbeat: Duration) -> Self {
+        self.live_orders = Some(Arc::new(LiveOrders::new(feed, heartbeat)));
+        self
+    }
+
+    /// Lets WebSocket clients of `/api/orders/ws` subscribe to `changes` of orders,
+    /// holding at most `max_subscriptions` subscriptions per connection and
+    /// answering a ping every `ping_interval`.
+    ///
+    /// Without it the WebSocket responds `404`.
+    pub fn with_order_subscriptions(
+        mut self,
+        changes: OrderChanges,
+        max_subscriptions: usize,
+        ping_interval: Duration,
+    ) -> Self {
+        self.order_subscriptions = Some(Arc::new(OrderSubscriptions::new(
+            changes,
+            max_subscriptions,
+            ping_interval,
+        )));
+        self
+    }
+
+    /// Publishes created and generated test orders to Kafka with `producer`.
+    ///
+    /// Without it order creation and test orders respond `503`.
+    pub fn with_order_producer(mut self, producer: Arc<OrderProducer>) -> Self {
+        self.order_producer = Some(producer);
+        self
+    }
+
+    /// Reads `page_size` orders per database query when exporting orders.
+    pub fn with_export_page_size(mut self, page_size: usize) -> Self {
+        self.export_page_size = page_size.max(1);
+        self
+    }
+
     /// Starts the server and blocks until it's shut down.
     ///
     /// # Returns
@@ -156,10 +386,27 @@ impl Server {
 
         info!("HTTP server listening on port {}", port);
 
-        axum::serve(listener, app)
-            .with_graceful_shutdown(shutdown_signal())
-            .await
-            .context("Server error")?;
+        // Peer addresses identify clients without credentials for rate limiting
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .with_graceful_shutdown({
+            let live_orders = self.live_orders.clone();
+            let order_subscriptions = self.order_subscriptions.clone();
+            async move {
+                shutdown_signal().await;
+                // Live streams and WebSockets never end on their own
+                if let Some(live_orders) = live_orders {
+                    live_orders.close();
+                }
+                if let Some(order_subscriptions) = order_subscriptions {
+                    order_subscriptions.close();
+                }
+            }
+        })
+        .await
+        .context("Server error")?;
 
         info!("HTTP server shut down gracefully");
         Ok(())
@@ -168,48 +415,91 @@ impl Server {
     fn create_router(&self) -> Router {
         let metrics = self.metrics.clone();
         let cache = self.cache.clone();
-        let static_dir = self.static_dir.clone();
+        let static_files = self
+            .static_files
+            .clone()
+            .unwrap_or_else(|| Arc::new(StaticFiles::disk(&self.static_dir)));
         let db_pool = self.db_pool.clone();
+        let order_service = self.order_service.clone();
+        let consistency_checker = self.consistency_checker.clone();
+
+        let (router, _) = api_routes().split_for_parts();
+        let mut router = router.route(openapi::REDOC_JS_PATH, get(openapi::handle_redoc_js));
+        // Inside authentication, so only verified principals get a bucket of their own
+        if let (Some(limiter), Some(_)) = (&self.rate_limiter, &self.auth) {
+            router = router.route_layer(axum::middleware::from_fn_with_state(
+                (limiter.clone(), metrics.clone()),
+                rate_limit::principal_middleware,
+            ));
+        }
+        // Static files stay public; only the routes above are checked
+        if let Some(auth) = &self.auth {
+            router = router.route_layer(axum::middleware::from_fn_with_state(
+                auth.clone(),
+                auth::middleware,
+            ));
+        }
+        // Outside authentication, so floods of invalid credentials are limited too;
+        // keyed by IP address, since the credentials are not verified yet
+        if let Some(limiter) = &self.rate_limiter {
+            router = router.route_layer(axum::middleware::from_fn_with_state(
+                (limiter.clone(), metrics.clone()),
+                rate_limit::middleware,
+            ));
+        }
 
-        Router::new()
-            .route("/order/{id}", get(Self::handle_get_order_by_id))
-            .route("/api/orders", get(Self::handle_get_orders))
-            .route("/api/send-test-order", post(Self::handle_send_test_order))
-            .route("/health", get(Self::handle_health))
-            .route("/metrics", get(Self::handle_metrics))
-            .fallback(Self::handle_static)
-            .layer(axum::middleware::from_fn_with_state(
-                metrics.clone(),
-                Self::metrics_middleware,
-            ))
+        router = router.fallback(handle_static);
+        // Inside the metrics layer, so sent bytes are counted compressed
+        if let Some(min_size) = self.compression_min_size {
+            router = router.layer(
+                CompressionLayer::new()
+                    .compress_when(DefaultPredicate::new().and(SizeAbove::new(min_size))),
+            );
+        }
+        router = router.layer(axum::middleware::from_fn_with_state(
+            metrics.clone(),
+            Self::metrics_middleware,
+        ));
+        if let Some(permits) = &self.concurrency_limit {
+            router = router.layer(axum::middleware::from_fn_with_state(
+                (permits.clone(), metrics.clone()),
+                rate_limit::concurrency_middleware,
+            ));
+        }
+        router
+            .layer(axum::middleware::from_fn(request_id::middleware))
             .with_state(AppState {
                 cache,
-                static_dir,
+                static_files,
                 metrics,
                 db_pool,
+                order_service,
+                consistency_checker,
+                live_orders: self.live_orders.clone(),
+                order_subscriptions: self.order_subscriptions.clone(),
+                order_producer: self.order_producer.clone(),
+                export_page_size: self.export_page_size,
             })
     }
 
-    /// Middleware for collecting metrics on HTTP requests
+    /// Middleware for collecting metrics on HTTP requests.
+    ///
+    /// Requests are labelled with their matched route template, or
+    /// [`UNMATCHED_ENDPOINT`] if no route matched.
     async fn metrics_middleware(
         State(metrics): State<Arc<Metrics>>,
         req: axum::extract::Request,
         next: axum::middleware::Next,
     ) -> Response {
         let method = req.method().to_string();
-        let path = req.uri().path().to_string();
+        let endpoint = req
+            .extensions()
+            .get::<MatchedPath>()
+            .map_or(UNMATCHED_ENDPOINT, MatchedPath::as_str)
+            .to_string();
 
-        // Estimate request size for incoming traffic metrics
-        let content_length = req
-            .headers()
-            .get(axum::http::header::CONTENT_LENGTH)
-            .and_then(|v| v.to_str().ok())
-            .and_then(|v| v.parse::<usize>().ok())
-            .unwrap_or(0);
-
-        if content_length > 0 {
-            metrics.record_network_traffic("in", content_length);
-        }
+        // Count the body bytes the handler actually reads
+        let req = req.map(|body| metrics.count_traffic("in", body));
 
         // Record start time
         let start = std::time::Instant::now();
@@ -224,168 +514,518 @@ impl Server {
         let status = response.status().as_u16();
 
         // Record metrics
-        metrics.record_request(&method, &path, status, duration);
+        metrics.record_request(&method, &endpoint, status, duration);
 
         // If error status, record error
         if status >= 400 {
-            metrics.record_error("http", &path);
-        }
-
-        // Estimate response size for outgoing traffic metrics
-        let response_size = response
-            .headers()
-            .get(axum::http::header::CONTENT_LENGTH)
-            .and_then(|v| v.to_str().ok())
-            .and_then(|v| v.parse::<usize>().ok())
-            .unwrap_or(0);
-
-        if response_size > 0 {
-            metrics.record_network_traffic("out", response_size);
+            metrics.record_error("http", &endpoint);
         }
 
-        response
+        // Count the response body bytes as they are sent
+        response.map(|body| metrics.count_traffic("out", body))
     }
+}
 
-    async fn handle_get_order_by_id(
-        State(state): State<AppState>,
-        axum::extract::Path(order_id): AxumPath<String>,
-    ) -> Response {
-        info!("Received order request for ID: {}", order_id);
-
-        if order_id.is_empty() {
-            warn!("Order ID is missing in request");
-            return (StatusCode::BAD_REQUEST, "order id is required").into_response();
-        }
+/// Get an order by its uid.
+///
+/// Customer PII is masked unless the caller has the `pii:read` scope. The
+/// response carries an ETag; send it in `If-None-Match` to get `304` while the
+/// order is unchanged.
+#[utoipa::path(
+    get,
+    path = "/order/{id}",
+    tag = "orders",
+    params(
+        ("id" = String, Path, description = "Order uid"),
+        ("If-None-Match" = Option<String>, Header, description = "ETag of a cached copy"),
+    ),
+    responses(
+        (status = 200, description = "The order", headers(("ETag" = String)), content(
+            (Order = "application/json"),
+            (Order = "application/x-ndjson"),
+            (Order = "application/msgpack"),
+        )),
+        (status = 304, description = "The cached copy is current"),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Missing scope", body = ErrorBody),
+        (status = 404, description = "No such order", body = ErrorBody),
+        (status = 406, description = "No acceptable response format", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded", body = ErrorBody),
+        (status = 500, description = "The order could not be loaded", body = ErrorBody),
+    ),
+    security(("api_key" = ["orders:read"]), ("bearer" = ["orders:read"]))
+)]
+async fn handle_get_order_by_id(
+    State(state): State<AppState>,
+    principal: Option<Extension<Principal>>,
+    Extension(request_id): Extension<RequestId>,
+    format: ResponseFormat,
+    headers: HeaderMap,
+    axum::extract::Path(order_id): AxumPath<String>,
+) -> Response {
+    info!("Received order request for ID: {}", order_id);
+
+    if order_id.is_empty() {
+        warn!("Order ID is missing in request");
+        return (StatusCode::BAD_REQUEST, "order id is required").into_response();
+    }
 
-        match state.cache.get(&order_id).await {
-            Some(order) => {
-                let json = serde_json::to_string(&order).unwrap_or_else(|e| {
-                    error!("Failed to serialize order: {}", e);
-                    "{}".to_string()
-                });
-                (StatusCode::OK, json).into_response()
-            }
-            None => {
-                warn!("Order not found: {}", order_id);
-                (StatusCode::NOT_FOUND, "order not found").into_response()
+    match state
+        .cache
+        .get_or_load(&order_id, state.order_service.as_ref())
+        .await
+    {
+        Ok(Some(order)) => {
+            let view = pii_view(principal.as_deref(), &request_id, "/order/{id}", 1);
+            let etag = etag::order_etag(&order, view, format);
+            if etag::none_match_hits(&headers, &etag) {
+                return (
+                    StatusCode::NOT_MODIFIED,
+                    [
+                        (header::ETAG, etag),
+                        (header::VARY, HeaderValue::from_static("accept")),
+                    ],
+                )
+                    .into_response();
             }
+            let mut response = format.one(&order.view(view));
+            response.headers_mut().insert(header::ETAG, etag);
+            response
+        }
+        Ok(None) => {
+            warn!("Order not found: {}", order_id);
+            (StatusCode::NOT_FOUND, "order not found").into_response()
+        }
+        Err(e) => {
+            error!("Failed to load order {}: {}", order_id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "failed to load order").into_response()
         }
     }
+}
 
-    async fn handle_get_orders(State(state): State<AppState>) -> Response {
-        info!("Received request to fetch all orders");
+/// Replace an order.
+///
+/// `If-Match` must carry an ETag of the current order, in any representation.
+/// It is checked against the stored order while the update holds its row lock,
+/// so of two updates based on the same version only the first succeeds.
+#[utoipa::path(
+    put,
+    path = "/order/{id}",
+    tag = "orders",
+    params(
+        ("id" = String, Path, description = "Order uid; must match `order_uid` of the body"),
+        ("If-Match" = String, Header, description = "ETag of the order being replaced"),
+    ),
+    request_body = Order,
+    responses(
+        (status = 200, description = "The updated order", headers(("ETag" = String)), content(
+            (Order = "application/json"),
+            (Order = "application/x-ndjson"),
+            (Order = "application/msgpack"),
+        )),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Missing scope", body = ErrorBody),
+        (status = 404, description = "No such order", body = ErrorBody),
+        (status = 412, description = "The order has changed since the ETag was issued", body = ErrorBody),
+        (status = 422, description = "Invalid order", body = ErrorBody),
+        (status = 428, description = "`If-Match` is missing", body = ErrorBody),
+        (status = 500, description = "The order could not be saved", body = ErrorBody),
+    ),
+    security(("api_key" = ["orders:write"]), ("bearer" = ["orders:write"]))
+)]
+#[instrument(name = "http.update_order", skip_all, fields(order_uid = %order_id))]
+async fn handle_update_order(
+    State(state): State<AppState>,
+    principal: Option<Extension<Principal>>,
+    Extension(request_id): Extension<RequestId>,
+    format: ResponseFormat,
+    headers: HeaderMap,
+    axum::extract::Path(order_id): AxumPath<String>,
+    Json(order): Json<Order>,
+) -> Response {
+    info!("Received request to update order");
+
+    if order.order_uid != order_id {
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "order_uid does not match the order id in the path",
+        )
+            .into_response();
+    }
 
-        // Access the get_all method on the inner OrderCache by dereferencing the Arc
-        let orders = state.cache.as_ref().get_all().await;
-        if orders.is_empty() {
-            warn!("No orders found in cache");
-            return (StatusCode::NOT_FOUND, "no orders available").into_response();
-        }
+    if !etag::has_if_match(&headers) {
+        return (
+            StatusCode::PRECONDITION_REQUIRED,
+            "If-Match with the order's ETag is required",
+        )
+            .into_response();
+    }
+    if let Err(e) = service::validate_order(&order) {
+        warn!("Rejected invalid order: {}", e);
+        return (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()).into_response();
+    }
+    // Postgres keeps microseconds; cache and return the order as it is stored
+    let order = Order {
+        date_created: order.date_created.trunc_subsecs(6),
+        ..order
+    };
 
-        match serde_json::to_string(&orders) {
-            Ok(json) => (StatusCode::OK, json).into_response(),
-            Err(e) => {
-                error!("Failed to encode orders response: {}", e);
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "failed to encode response",
-                )
-                    .into_response()
-            }
+    // Compared against the stored order while its row is locked for the update
+    let is_current = |current: &Order| {
+        etag::check_if_match(&headers, &etag::order_version(current)) == etag::IfMatch::Matches
+    };
+    match state
+        .order_service
+        .update_order_if(&order, &is_current)
+        .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            warn!("Rejected update of order {}: stale If-Match", order_id);
+            return (
+                StatusCode::PRECONDITION_FAILED,
+                "order has changed; fetch it again",
+            )
+                .into_response();
         }
+        Err(ServiceError::Db(RepositoryError::NotFound)) => {
+            return (StatusCode::NOT_FOUND, "order not found").into_response();
+        }
+        Err(ServiceError::InvalidOrder(e)) => {
+            return (StatusCode::UNPROCESSABLE_ENTITY, e).into_response();
+        }
+        Err(e) => {
+            error!("Failed to update order {}: {}", order_id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "failed to update order").into_response();
+        }
+    }
+    state.cache.set(order.clone()).await;
+
+    let view = pii_view(principal.as_deref(), &request_id, "/order/{id}", 1);
+    let mut response = format.one(&order.view(view));
+    response
+        .headers_mut()
+        .insert(header::ETAG, etag::order_etag(&order, view, format));
+    response
+}
+
+/// List all cached orders.
+///
+/// Customer PII is masked unless the caller has the `pii:read` scope.
+#[utoipa::path(
+    get,
+    path = "/api/orders",
+    tag = "orders",
+    responses(
+        (status = 200, description = "The orders; one per line as NDJSON", content(
+            (Vec<Order> = "application/json"),
+            (Order = "application/x-ndjson"),
+            (Vec<Order> = "application/msgpack"),
+        )),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Missing scope", body = ErrorBody),
+        (status = 404, description = "No orders are cached", body = ErrorBody),
+        (status = 406, description = "No acceptable response format", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded", body = ErrorBody),
+    ),
+    security(("api_key" = ["orders:read"]), ("bearer" = ["orders:read"]))
+)]
+async fn handle_get_orders(
+    State(state): State<AppState>,
+    principal: Option<Extension<Principal>>,
+    Extension(request_id): Extension<RequestId>,
+    format: ResponseFormat,
+) -> Response {
+    info!("Received request to fetch all orders");
+
+    // Access the get_all method on the inner OrderCache by dereferencing the Arc
+    let orders = state.cache.as_ref().get_all().await;
+    if orders.is_empty() {
+        warn!("No orders found in cache");
+        return (StatusCode::NOT_FOUND, "no orders available").into_response();
     }
 
-    async fn handle_send_test_order(State(_state): State<AppState>) -> Response {
-        info!("Received request to send test order");
+    let view = pii_view(
+        principal.as_deref(),
+        &request_id,
+        "/api/orders",
+        orders.len(),
+    );
+    let orders: Vec<_> = orders.iter().map(|order| order.view(view)).collect();
+    format.many(&orders)
+}
 
-        match kafka_producer::produce_test_message().await {
-            Ok(order_uid) => (
-                StatusCode::OK,
-                format!("Test order sent successfully! Order UID: {order_uid}"),
+/// Publish a generated test order to Kafka.
+#[utoipa::path(
+    post,
+    path = "/api/send-test-order",
+    tag = "orders",
+    responses(
+        (status = 200, description = "The uid of the published order", body = String, content_type = "text/plain"),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Missing scope", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded", body = ErrorBody),
+        (status = 500, description = "The order could not be published", body = ErrorBody),
+        (status = 503, description = "Publishing orders is not configured", body = ErrorBody),
+    ),
+    security(("api_key" = ["orders:write"]), ("bearer" = ["orders:write"]))
+)]
+#[instrument(name = "http.send_test_order", skip_all)]
+async fn handle_send_test_order(
+    State(state): State<AppState>,
+    Extension(request_id): Extension<RequestId>,
+) -> Response {
+    info!("Received request to send test order");
+
+    let Some(producer) = state.order_producer else {
+        return publishing_disabled();
+    };
+    match producer.publish_test_order(Some(&request_id.0)).await {
+        Ok(order_uid) => (
+            StatusCode::OK,
+            format!("Test order sent successfully! Order UID: {order_uid}"),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("Failed to send test order: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to send test order",
             )
-                .into_response(),
-            Err(e) => {
-                error!("Failed to send test order: {}", e);
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "failed to send test order",
-                )
-                    .into_response()
-            }
+                .into_response()
         }
     }
+}
 
-    async fn handle_health() -> &'static str {
-        info!("Health check requested");
-        "OK"
+/// Validate an order and publish it to Kafka.
+///
+/// The order is stored once the consumer processes the message.
+#[utoipa::path(
+    post,
+    path = "/api/orders",
+    tag = "orders",
+    request_body = Order,
+    responses(
+        (status = 202, description = "The order was published", body = OrderAccepted),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Missing scope", body = ErrorBody),
+        (status = 422, description = "Invalid order", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded", body = ErrorBody),
+        (status = 500, description = "The order could not be published", body = ErrorBody),
+        (status = 503, description = "Publishing orders is not configured", body = ErrorBody),
+    ),
+    security(("api_key" = ["orders:write"]), ("bearer" = ["orders:write"]))
+)]
+#[instrument(name = "http.create_order", skip_all, fields(order_uid = %order.order_uid))]
+async fn handle_create_order(
+    State(state): State<AppState>,
+    Extension(request_id): Extension<RequestId>,
+    Json(order): Json<Order>,
+) -> Response {
+    info!("Received request to create order");
+
+    if let Err(e) = service::validate_order(&order) {
+        warn!("Rejected invalid order: {}", e);
+        return (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()).into_response();
     }
 
-    async fn handle_metrics(State(state): State<AppState>) -> Response {
-        use prometheus::Encoder;
-        let encoder = prometheus::TextEncoder::new();
+    let Some(producer) = state.order_producer else {
+        return publishing_disabled();
+    };
+    match producer.publish(&order, Some(&request_id.0)).await {
+        Ok(()) => (
+            StatusCode::ACCEPTED,
+            Json(OrderAccepted {
+                order_uid: order.order_uid,
+                request_id: request_id.0,
+            }),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("Failed to publish order: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "failed to publish order").into_response()
+        }
+    }
+}
 
-        let mut buffer = Vec::new();
-        if let Err(e) = encoder.encode(&state.metrics.registry.gather(), &mut buffer) {
-            error!("Failed to encode metrics: {}", e);
-            return (
+/// Health check.
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "operations",
+    responses((status = 200, description = "`OK`", body = String, content_type = "text/plain"))
+)]
+async fn handle_health() -> &'static str {
+    info!("Health check requested");
+    "OK"
+}
+
+/// Compare the cache with the database, optionally repairing differences.
+#[utoipa::path(
+    post,
+    path = "/admin/consistency-check",
+    tag = "operations",
+    params(ConsistencyCheckParams),
+    responses(
+        (status = 200, description = "Report of missing, stale and orphaned entries", body = Object),
+        (status = 400, description = "Invalid mode", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Missing scope", body = ErrorBody),
+        (status = 500, description = "The check failed", body = ErrorBody),
+    ),
+    security(("api_key" = ["admin"]), ("bearer" = ["admin"]))
+)]
+async fn handle_consistency_check(
+    State(state): State<AppState>,
+    Query(params): Query<ConsistencyCheckParams>,
+) -> Response {
+    let mode = match params.mode.as_deref() {
+        Some("full") => ScanMode::Full,
+        None | Some("sample") => ScanMode::Sample(params.sample_size.unwrap_or(1000)),
+        Some(other) => {
+            warn!("Unknown consistency check mode: {}", other);
+            return (StatusCode::BAD_REQUEST, "mode must be 'full' or 'sample'").into_response();
+        }
+    };
+    let repair = params.repair.unwrap_or(false);
+    info!(
+        "Received consistency check request: {:?}, repair={}",
+        mode, repair
+    );
+
+    match state.consistency_checker.run(mode, repair).await {
+        Ok(report) => axum::Json(report).into_response(),
+        Err(e) => {
+            error!("Consistency check failed: {}", e);
+            (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to encode metrics",
+                "consistency check failed",
             )
-                .into_response();
+                .into_response()
         }
+    }
+}
 
-        match String::from_utf8(buffer) {
-            Ok(metrics_text) => (StatusCode::OK, metrics_text).into_response(),
-            Err(e) => {
-                error!("Failed to convert metrics to UTF-8: {}", e);
-                (StatusCode::INTERNAL_SERVER_ERROR, "Invalid metrics data").into_response()
-            }
-        }
+/// Prometheus metrics.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "operations",
+    responses((status = 200, description = "Metrics in the Prometheus text format", body = String, content_type = "text/plain"))
+)]
+async fn handle_metrics(State(state): State<AppState>) -> Response {
+    use prometheus::Encoder;
+    let encoder = prometheus::TextEncoder::new();
+
+    let mut buffer = Vec::new();
+    if let Err(e) = encoder.encode(&state.metrics.registry.gather(), &mut buffer) {
+        error!("Failed to encode metrics: {}", e);
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to encode metrics",
+        )
+            .into_response();
     }
 
-    async fn handle_static(State(state): State<AppState>, uri: axum::http::Uri) -> Response {
-        let path = uri.path().trim_start_matches('/');
-        let path = if path.is_empty() { "index.html" } else { path };
-
-        let file_path = Path::new(&state.static_dir).join(path);
-        info!("Serving static file: {:?}", file_path);
-
-        match tokio::fs::read_to_string(file_path).await {
-            Ok(content) => {
-                let content_type = if path.ends_with(".html") {
-                    "text/html"
-                } else if path.ends_with(".css") {
-                    "text/css"
-                } else if path.ends_with(".js") {
-                    "application/javascript"
-                } else {
-                    "text/plain"
-                };
-
-                Response::builder()
-                    .header("Content-Type", content_type)
-                    .body(content.into())
-                    .unwrap_or_else(|_| {
-                        (
-                            StatusCode::INTERNAL_SERVER_ERROR,
-                            "Failed to create response",
-                        )
-                            .into_response()
-                    })
-            }
-            Err(_) => (StatusCode::NOT_FOUND, "File not found").into_response(),
+    match String::from_utf8(buffer) {
+        Ok(metrics_text) => (StatusCode::OK, metrics_text).into_response(),
+        Err(e) => {
+            error!("Failed to convert metrics to UTF-8: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Invalid metrics data").into_response()
         }
     }
 }
 
+async fn handle_static(
+    State(state): State<AppState>,
+    method: axum::http::Method,
+    headers: axum::http::HeaderMap,
+    uri: axum::http::Uri,
+) -> Response {
+    state
+        .static_files
+        .serve(&method, uri.path(), &headers)
+        .await
+}
+
+/// The API routes, each documented by the `#[utoipa::path]` of its handler.
+pub(crate) fn api_routes() -> OpenApiRouter<AppState> {
+    OpenApiRouter::with_openapi(openapi::ApiDoc::openapi())
+        .routes(routes!(handle_get_order_by_id, handle_update_order))
+        .routes(routes!(handle_get_orders, handle_create_order))
+        .routes(routes!(live::handle_order_stream))
+        .routes(routes!(ws::handle_order_ws))
+        .routes(routes!(exports::handle_export_orders))
+        .routes(routes!(handle_send_test_order))
+        .routes(routes!(openapi::handle_openapi_json))
+        .routes(routes!(openapi::handle_api_docs))
+        .routes(routes!(handle_health))
+        .routes(routes!(handle_metrics))
+        .routes(routes!(handle_consistency_check))
+}
+
+/// Decides how the caller sees customer PII and audits unmasked access.
+///
+/// Without authentication there is no caller to restrict, so orders are served
+/// unmasked, as before authentication existed.
+fn pii_view(
+    principal: Option<&Principal>,
+    request_id: &RequestId,
+    resource: &str,
+    order_count: usize,
+) -> PiiView {
+    let view = principal.map_or(PiiView::Full, Principal::pii_view);
+    if view == PiiView::Full {
+        let actor = principal.map_or("anonymous", |p| p.name.as_str());
+        audit::unmasked_access(actor, Some(&request_id.0), resource, order_count);
+    }
+    view
+}
+
+/// Query parameters of the consistency check admin endpoint.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ConsistencyCheckParams {
+    /// `full` or `sample` (default)
+    mode: Option<String>,
+    /// Number of orders compared in `sample` mode (default 1000)
+    sample_size: Option<usize>,
+    /// Whether to fix the differences found (default `false`)
+    repair: Option<bool>,
+}
+
+/// Response to an accepted order.
+#[derive(Debug, Serialize, ToSchema)]
+struct OrderAccepted {
+    /// Uid of the published order
+    order_uid: String,
+    /// Id of the request, carried in the Kafka message headers
+    request_id: String,
+}
+
 /// Application state shared between request handlers
 #[derive(Clone)]
 struct AppState {
     cache: Arc<OrderCache>,
-    static_dir: String,
+    static_files: Arc<StaticFiles>,
     metrics: Arc<Metrics>,
-    #[allow(dead_code)]
     db_pool: Pool,
+    order_service: Arc<dyn OrderService>,
+    consistency_checker: Arc<ConsistencyChecker>,
+    live_orders: Option<Arc<LiveOrders>>,
+    order_subscriptions: Option<Arc<OrderSubscriptions>>,
+    order_producer: Option<Arc<OrderProducer>>,
+    /// Orders read per query by exports.
+    export_page_size: usize,
+}
+
+/// Response to requests publishing orders when no producer is configured.
+fn publishing_disabled() -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        "publishing orders is not configured",
+    )
+        .into_response()
 }
 
 /// Waits for a shutdown signal (Ctrl+C)
@@ -418,10 +1058,84 @@ async fn shutdown_signal() {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use async_trait::async_trait;
+    use axum::body::to_bytes;
+    use axum::http::Request;
     use deadpool_postgres::tokio_postgres;
+    use service::{OrderPredicate, SaveOptions, SaveOutcome, ServiceError};
+    use sha2::Digest;
+    use std::collections::HashMap;
+    use telemetry::REQUEST_ID_HEADER;
+    use tower::ServiceExt;
+
+    /// Order service stub; the tests below never reach the database.
+    ///
+    /// Conditional updates apply to the orders in `stored`, which hold what Postgres
+    /// would (see [`as_stored`]).
+    #[derive(Default)]
+    struct NoopOrderService {
+        stored: std::sync::Mutex<HashMap<String, Order>>,
+    }
+
+    #[async_trait]
+    impl OrderService for NoopOrderService {
+        async fn save_order(&self, _order: &Order) -> std::result::Result<(), ServiceError> {
+            Ok(())
+        }
+
+        async fn save_order_with(
+            &self,
+            _order: &Order,
+            _options: &SaveOptions,
+        ) -> std::result::Result<SaveOutcome, ServiceError> {
+            Ok(SaveOutcome::Inserted)
+        }
+
+        async fn update_order_if(
+            &self,
+            order: &Order,
+            is_current: &OrderPredicate<'_>,
+        ) -> std::result::Result<bool, ServiceError> {
+            let mut stored = self.stored.lock().unwrap();
+            let Some(current) = stored.get_mut(&order.order_uid) else {
+                return Err(RepositoryError::NotFound.into());
+            };
+            if !is_current(current) {
+                return Ok(false);
+            }
+            *current = as_stored(order);
+            Ok(true)
+        }
+
+        async fn save_order_batch(
+            &self,
+            orders: &[Order],
+        ) -> std::result::Result<Vec<std::result::Result<(), ServiceError>>, ServiceError> {
+            Ok(orders.iter().map(|_| Ok(())).collect())
+        }
+
+        async fn get_order_by_id(
+            &self,
+            _order_uid: &str,
+        ) -> std::result::Result<Order, ServiceError> {
+            Err(ServiceError::Unexpected("not available in tests".into()))
+        }
+    }
+
+    /// `order` as read back from Postgres, which keeps timestamps in microseconds.
+    fn as_stored(order: &Order) -> Order {
+        Order {
+            date_created: order.date_created.trunc_subsecs(6),
+            ..order.clone()
+        }
+    }
 
     // Helper function to create a test server
     fn create_test_server() -> Server {
+        create_test_server_with(Arc::new(NoopOrderService::default()))
+    }
+
+    fn create_test_server_with(order_service: Arc<NoopOrderService>) -> Server {
         let cache = Arc::new(OrderCache::new());
 
         // For tests, we create a mock database pool that won't be used
@@ -437,7 +1151,30 @@ mod tests {
             .create_pool(None, tokio_postgres::NoTls)
             .expect("Failed to create mock pool");
 
-        Server::new("8080".to_string(), cache, "static".to_string(), mock_pool)
+        let order_service: Arc<dyn OrderService> = order_service;
+        let registry = Registry::new();
+        let consistency_checker = Arc::new(
+            ConsistencyChecker::new(
+                mock_pool.clone(),
+                cache.clone(),
+                order_service.clone(),
+                &registry,
+            )
+            .expect("Failed to create consistency checker"),
+        );
+
+        Server::new(
+            ServerConfig {
+                port: "8080".to_string(),
+                static_dir: "static".to_string(),
+                duration_buckets: vec![0.1, 1.0],
+            },
+            cache,
+            mock_pool,
+            order_service,
+            registry,
+            consistency_checker,
+        )
     }
 
     #[test]
@@ -446,4 +1183,690 @@ mod tests {
         assert_eq!(server.port, "8080");
         assert_eq!(server.static_dir, "static");
     }
+
+    #[tokio::test]
+    async fn test_request_id_in_headers_and_error_body() {
+        let router = create_test_server().create_router();
+
+        let response = router
+            .clone()
+            .oneshot(
+                Request::get("/api/orders")
+                    .header(REQUEST_ID_HEADER, "req-123")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "req-123");
+        let body: serde_json::Value =
+            serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await.unwrap())
+                .unwrap();
+        assert_eq!(body["error"], "no orders available");
+        assert_eq!(body["request_id"], "req-123");
+
+        // Without a usable id from the client, one is generated.
+        let response = router
+            .oneshot(
+                Request::get("/health")
+                    .header(REQUEST_ID_HEADER, "bad id")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let id = response.headers()[REQUEST_ID_HEADER].to_str().unwrap();
+        assert_ne!(id, "bad id");
+        assert!(uuid::Uuid::parse_str(id).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_metrics_use_route_templates() {
+        let server = create_test_server();
+        let router = server.create_router();
+
+        for uri in ["/order/a", "/order/b", "/no/such/file.css"] {
+            router
+                .clone()
+                .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+        }
+        let response = router
+            .oneshot(Request::get("/health").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        let metrics = &server.metrics;
+        let endpoints: Vec<String> = metrics
+            .registry
+            .gather()
+            .iter()
+            .filter(|family| family.name() == "http_requests_total")
+            .flat_map(|family| family.get_metric())
+            .flat_map(|metric| metric.get_label())
+            .filter(|label| label.name() == "endpoint")
+            .map(|label| label.value().to_string())
+            .collect();
+        assert_eq!(endpoints.len(), 3, "{endpoints:?}");
+        for endpoint in ["/order/{id}", UNMATCHED_ENDPOINT, "/health"] {
+            assert!(endpoints.iter().any(|e| e == endpoint), "{endpoints:?}");
+        }
+        assert_eq!(
+            metrics
+                .errors_total
+                .with_label_values(&["http", "/order/{id}"])
+                .get(),
+            2.0
+        );
+        // "OK" from the health check, plus the error bodies
+        assert!(
+            metrics
+                .network_traffic_bytes
+                .with_label_values(&["out"])
+                .get()
+                >= 2.0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_auth_protects_api_routes() {
+        let mut config = app_config::AppConfig::load().unwrap();
+        config.auth_api_keys = vec![app_config::ApiKeyConfig {
+            name: "writer".into(),
+            key_sha256: hex::encode(sha2::Sha256::digest(b"writer-key")),
+            scopes: vec!["orders:write".into()],
+        }];
+        let router = create_test_server()
+            .with_auth(Authenticator::from_config(&config).unwrap())
+            .create_router();
+
+        let status = |uri: &str, key: Option<&str>| {
+            let router = router.clone();
+            let mut req = Request::get(uri);
+            if let Some(key) = key {
+                req = req.header(auth::API_KEY_HEADER, key);
+            }
+            let req = req.body(Body::empty()).unwrap();
+            async move { router.oneshot(req).await.unwrap().status() }
+        };
+        assert_eq!(status("/health", None).await, StatusCode::OK);
+        assert_eq!(status("/api/orders", None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            status("/api/orders", Some("wrong-key")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status("/api/orders", Some("writer-key")).await,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[tokio::test]
+    async fn test_orders_masked_without_pii_scope() {
+        let mut config = app_config::AppConfig::load().unwrap();
+        let key = |name: &str, scopes: &[&str]| app_config::ApiKeyConfig {
+            name: name.into(),
+            key_sha256: hex::encode(sha2::Sha256::digest(name.as_bytes())),
+            scopes: scopes.iter().map(|s| s.to_string()).collect(),
+        };
+        config.auth_api_keys = vec![
+            key("support", &["orders:read"]),
+            key("billing", &["orders:read", "pii:read"]),
+        ];
+        let server = create_test_server().with_auth(Authenticator::from_config(&config).unwrap());
+        let mut order = Order {
+            order_uid: "b563feb7b2b84b6test".into(),
+            ..Default::default()
+        };
+        order.delivery.phone = "+9720000000".into();
+        server.cache.set(order).await;
+        let router = server.create_router();
+
+        for (key, phone) in [("support", "***0000"), ("billing", "+9720000000")] {
+            let response = router
+                .clone()
+                .oneshot(
+                    Request::get("/order/b563feb7b2b84b6test")
+                        .header(auth::API_KEY_HEADER, key)
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let order: Order =
+                serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await.unwrap())
+                    .unwrap();
+            assert_eq!(order.delivery.phone, phone);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_export_rejects_invalid_requests() {
+        let mut config = app_config::AppConfig::load().unwrap();
+        config.auth_api_keys = vec![app_config::ApiKeyConfig {
+            name: "support".into(),
+            key_sha256: hex::encode(sha2::Sha256::digest(b"support-key")),
+            scopes: vec!["orders:read".into()],
+        }];
+        let router = create_test_server()
+            .with_auth(Authenticator::from_config(&config).unwrap())
+            .create_router();
+
+        for (uri, expected) in [
+            ("/api/exports/orders?format=xlsx", StatusCode::BAD_REQUEST),
+            ("/api/exports/orders?rows=payment", StatusCode::BAD_REQUEST),
+            (
+                "/api/exports/orders?from=yesterday",
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                "/api/exports/orders?customer_id=test",
+                StatusCode::FORBIDDEN,
+            ),
+        ] {
+            let response = router
+                .clone()
+                .oneshot(
+                    Request::get(uri)
+                        .header(auth::API_KEY_HEADER, "support-key")
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), expected, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_orders_negotiate_format_and_encoding() {
+        let server = create_test_server().with_compression(1);
+        let order = Order {
+            order_uid: "b563feb7b2b84b6test".into(),
+            ..Default::default()
+        };
+        server.cache.set(order.clone()).await;
+        let router = server.create_router();
+        let get_orders = |accept: &str, encoding: &str| {
+            Request::get("/api/orders")
+                .header(axum::http::header::ACCEPT, accept)
+                .header(axum::http::header::ACCEPT_ENCODING, encoding)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = router
+            .clone()
+            .oneshot(get_orders("application/msgpack", "identity"))
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[axum::http::header::CONTENT_TYPE],
+            "application/msgpack"
+        );
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let orders: Vec<Order> = rmp_serde::from_slice(&body).unwrap();
+        assert_eq!(orders, vec![order]);
+
+        let response = router
+            .clone()
+            .oneshot(get_orders("application/x-ndjson", "br;q=0.5, zstd"))
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[axum::http::header::CONTENT_TYPE],
+            "application/x-ndjson"
+        );
+        assert_eq!(
+            response.headers()[axum::http::header::CONTENT_ENCODING],
+            "zstd"
+        );
+
+        let response = router
+            .oneshot(get_orders("text/html", "gzip"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_ACCEPTABLE);
+    }
+
+    #[tokio::test]
+    async fn test_conditional_get_and_update() {
+        let order_service = Arc::new(NoopOrderService::default());
+        let server = create_test_server_with(order_service.clone());
+        let mut order = Order {
+            order_uid: "b563feb7b2b84b6test".into(),
+            items: vec![Default::default()],
+            ..Default::default()
+        };
+        order.delivery.name = "Test Testov".into();
+        order.delivery.phone = "+9720000000".into();
+        server.cache.set(order.clone()).await;
+        order_service
+            .stored
+            .lock()
+            .unwrap()
+            .insert(order.order_uid.clone(), order.clone());
+        let router = server.create_router();
+
+        let response = router
+            .clone()
+            .oneshot(
+                Request::get("/order/b563feb7b2b84b6test")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let etag = response.headers()[header::ETAG].clone();
+        let response = router
+            .clone()
+            .oneshot(
+                Request::get("/order/b563feb7b2b84b6test")
+                    .header(header::IF_NONE_MATCH, etag.clone())
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        let update = |if_match: Option<&HeaderValue>| {
+            let mut changed = order.clone();
+            changed.track_number = "WBILMTESTTRACK".into();
+            let mut request = Request::put("/order/b563feb7b2b84b6test")
+                .header(header::CONTENT_TYPE, "application/json");
+            if let Some(if_match) = if_match {
+                request = request.header(header::IF_MATCH, if_match);
+            }
+            request
+                .body(Body::from(serde_json::to_vec(&changed).unwrap()))
+                .unwrap()
+        };
+        let response = router.clone().oneshot(update(None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PRECONDITION_REQUIRED);
+        let response = router.clone().oneshot(update(Some(&etag))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_ne!(response.headers()[header::ETAG], etag);
+        let response = router.oneshot(update(Some(&etag))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(
+            server
+                .cache
+                .get("b563feb7b2b84b6test")
+                .await
+                .unwrap()
+                .track_number,
+            "WBILMTESTTRACK"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_order_with_nanosecond_timestamp() {
+        let order_service = Arc::new(NoopOrderService::default());
+        let server = create_test_server_with(order_service.clone());
+        // Received from Kafka: the cached copy keeps the nanoseconds, the stored one does not
+        let mut order = Order {
+            order_uid: "nanos".into(),
+            items: vec![Default::default()],
+            date_created: chrono::DateTime::from_timestamp(1_700_000_000, 123_456_789).unwrap(),
+            ..Default::default()
+        };
+        order.delivery.name = "Test Testov".into();
+        order.delivery.phone = "+9720000000".into();
+        server.cache.set(order.clone()).await;
+        order_service
+            .stored
+            .lock()
+            .unwrap()
+            .insert(order.order_uid.clone(), as_stored(&order));
+        let router = server.create_router();
+
+        let response = router
+            .clone()
+            .oneshot(Request::get("/order/nanos").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let mut etag = response.headers()[header::ETAG].clone();
+
+        for track_number in ["FIRST", "SECOND"] {
+            order.track_number = track_number.into();
+            let response = router
+                .clone()
+                .oneshot(
+                    Request::put("/order/nanos")
+                        .header(header::CONTENT_TYPE, "application/json")
+                        .header(header::IF_MATCH, &etag)
+                        .body(Body::from(serde_json::to_vec(&order).unwrap()))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(
+                response.status(),
+                StatusCode::OK,
+                "update to {track_number}"
+            );
+            etag = response.headers()[header::ETAG].clone();
+        }
+        let cached = server.cache.get("nanos").await.unwrap();
+        assert_eq!(cached, as_stored(&order));
+    }
+
+    #[tokio::test]
+    async fn test_openapi_documents_the_served_routes() {
+        use std::collections::BTreeSet;
+
+        let response = create_test_server()
+            .create_router()
+            .oneshot(
+                Request::get("/api/openapi.json")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let spec: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let mut documented = BTreeSet::new();
+        for (path, item) in spec["paths"].as_object().unwrap() {
+            for method in item.as_object().unwrap().keys() {
+                documented.insert((method.to_uppercase(), path.clone()));
+            }
+        }
+
+        // A method no route uses gets 405 listing the routed methods in `Allow`,
+        // without running any handler
+        let router = create_test_server().create_router();
+        let mut routed = BTreeSet::new();
+        for path in spec["paths"].as_object().unwrap().keys() {
+            let request = Request::builder()
+                .method("TRACE")
+                .uri(path.replace("{id}", "test-id"))
+                .body(Body::empty())
+                .unwrap();
+            let response = router.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+            let allow = response.headers()[header::ALLOW].to_str().unwrap();
+            for method in allow.split(',').map(str::trim) {
+                if method != "HEAD" {
+                    routed.insert((method.to_string(), path.to_string()));
+                }
+            }
+        }
+        assert_eq!(routed, documented);
+        assert!(documented.contains(&("PUT".to_string(), "/order/{id}".to_string())));
+
+        assert_eq!(spec["openapi"], "3.1.0");
+        assert_eq!(
+            spec["components"]["schemas"]["Order"]["properties"]["order_uid"]["description"],
+            "Unique identifier for the order"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_api_docs_are_served_without_a_cdn() {
+        // The documentation is public even with authentication enabled
+        let mut config = app_config::AppConfig::load().unwrap();
+        config.auth_api_keys = vec![app_config::ApiKeyConfig {
+            name: "reader".into(),
+            key_sha256: hex::encode(sha2::Sha256::digest(b"reader-key")),
+            scopes: vec!["orders:read".into()],
+        }];
+        let router = create_test_server()
+            .with_auth(Authenticator::from_config(&config).unwrap())
+            .create_router();
+        let get = |uri: &'static str| {
+            let router = router.clone();
+            async move {
+                let response = router
+                    .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                let status = response.status();
+                let content_type = response.headers()[header::CONTENT_TYPE].clone();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (
+                    status,
+                    content_type,
+                    String::from_utf8(body.to_vec()).unwrap(),
+                )
+            }
+        };
+
+        let (status, _, page) = get("/api/docs").await;
+        assert_eq!(status, StatusCode::OK);
+        let script = format!("<script src=\"{}\">", openapi::REDOC_JS_PATH);
+        assert!(page.contains(&script), "{page}");
+        assert!(!page.contains("https://"), "{page}");
+
+        let (status, content_type, bundle) = get(openapi::REDOC_JS_PATH).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(
+            content_type
+                .to_str()
+                .unwrap()
+                .starts_with("text/javascript")
+        );
+        assert!(bundle.contains("Redoc"));
+    }
+
+    #[tokio::test]
+    async fn test_order_stream_resumes_after_last_event_id() {
+        use futures_util::StreamExt;
+
+        let feed = Arc::new(OrderFeed::new(16));
+        let mut probe = feed.subscribe(None);
+        let order = |uid: &str| Order {
+            order_uid: uid.into(),
+            delivery_service: "meest".into(),
+            ..Default::default()
+        };
+        feed.publish(order("first"));
+        let first_id = probe.receiver.recv().await.unwrap().id;
+        feed.publish(order("second"));
+
+        let router = create_test_server()
+            .with_order_stream(feed.clone(), Duration::from_secs(60))
+            .create_router();
+        let response = router
+            .oneshot(
+                Request::get("/api/orders/stream?delivery_service=meest")
+                    .header("last-event-id", first_id.to_string())
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/event-stream"
+        );
+        let mut body = response.into_body().into_data_stream();
+        let frame = body.next().await.unwrap().unwrap();
+        let frame = std::str::from_utf8(&frame).unwrap();
+        assert!(frame.contains("event: order"));
+        assert!(frame.contains(&format!("id: {}", first_id + 1)));
+        assert!(frame.contains("\"order_uid\":\"second\""));
+    }
+
+    #[tokio::test]
+    async fn test_order_subscriptions_over_websocket() {
+        use futures_util::{SinkExt, StreamExt};
+        use service::ChangeKind;
+        use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest};
+
+        let mut config = app_config::AppConfig::load().unwrap();
+        config.auth_api_keys = vec![app_config::ApiKeyConfig {
+            name: "support".into(),
+            key_sha256: hex::encode(sha2::Sha256::digest(b"support-key")),
+            scopes: vec!["orders:read".into()],
+        }];
+        let changes = OrderChanges::new(16);
+        let server = create_test_server()
+            .with_auth(Authenticator::from_config(&config).unwrap())
+            .with_order_subscriptions(changes.clone(), 2, Duration::from_secs(60));
+        let subscriptions = server.order_subscriptions.clone().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(axum::serve(listener, server.create_router()).into_future());
+
+        let mut request = format!("ws://{addr}/api/orders/ws")
+            .into_client_request()
+            .unwrap();
+        request
+            .headers_mut()
+            .insert(auth::API_KEY_HEADER, "support-key".parse().unwrap());
+        let (mut socket, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+        let mut exchange = async |request: serde_json::Value| {
+            if !request.is_null() {
+                let request = tungstenite::Message::text(request.to_string());
+                socket.send(request).await.unwrap();
+            }
+            match socket.next().await.unwrap().unwrap() {
+                tungstenite::Message::Text(text) => {
+                    serde_json::from_str::<serde_json::Value>(&text).unwrap()
+                }
+                tungstenite::Message::Close(frame) => {
+                    serde_json::json!({ "closed": frame.unwrap().code.to_string() })
+                }
+                other => panic!("unexpected message {other:?}"),
+            }
+        };
+
+        // Following a customer needs pii:read
+        let reply = exchange(serde_json::json!({"action": "subscribe", "customer_id": "c1"})).await;
+        assert_eq!(reply["type"], "error");
+        for uid in ["a", "b"] {
+            let reply =
+                exchange(serde_json::json!({"action": "subscribe", "order_uid": uid})).await;
+            assert_eq!(
+                reply,
+                serde_json::json!({"type": "subscribed", "order_uid": uid})
+            );
+        }
+        let reply = exchange(serde_json::json!({"action": "subscribe", "order_uid": "c"})).await;
+        assert_eq!(reply["message"], "subscription limit of 2 reached");
+
+        let mut order = Order {
+            order_uid: "a".into(),
+            ..Default::default()
+        };
+        order.delivery.phone = "+9720000000".into();
+        changes.publish(ChangeKind::Created, &order);
+        let reply = exchange(serde_json::Value::Null).await;
+        assert_eq!(reply["type"], "change");
+        assert_eq!(reply["change"], "created");
+        assert_eq!(reply["order"]["delivery"]["phone"], "***0000");
+
+        changes.publish(
+            ChangeKind::Created,
+            &Order {
+                order_uid: "z".into(),
+                ..Default::default()
+            },
+        );
+        changes.publish(ChangeKind::Updated, &order);
+        let reply = exchange(serde_json::Value::Null).await;
+        assert_eq!(reply["change"], "updated");
+        assert_eq!(reply["order"]["order_uid"], "a");
+
+        subscriptions.close();
+        let reply = exchange(serde_json::Value::Null).await;
+        assert_eq!(reply["closed"], "1001");
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_requests_get_retry_after() {
+        let quota = app_config::RateQuota::parse("0.1/1").unwrap();
+        let server = create_test_server().with_rate_limiter(RateLimiter::new(Some(quota), vec![]));
+        let router = server.create_router();
+
+        let get_health = || Request::get("/health").body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(get_health()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = router.oneshot(get_health()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[axum::http::header::RETRY_AFTER], "10");
+        assert_eq!(
+            server
+                .metrics
+                .rejected_requests_total
+                .with_label_values(&["rate_limited"])
+                .get(),
+            1.0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_keys_on_ip_until_authenticated() {
+        use axum::extract::ConnectInfo;
+
+        let mut config = app_config::AppConfig::load().unwrap();
+        config.auth_api_keys = vec![app_config::ApiKeyConfig {
+            name: "reader".into(),
+            key_sha256: hex::encode(sha2::Sha256::digest(b"reader-key")),
+            scopes: vec!["orders:read".into()],
+        }];
+        let quota = app_config::RateQuota::parse("0.1/1").unwrap();
+        let router = create_test_server()
+            .with_auth(Authenticator::from_config(&config).unwrap())
+            .with_rate_limiter(RateLimiter::new(Some(quota), vec![]))
+            .create_router();
+
+        let status = |uri: &str, ip: &str, key: &str| {
+            let router = router.clone();
+            let mut req = Request::get(uri)
+                .header(auth::API_KEY_HEADER, key)
+                .body(Body::empty())
+                .unwrap();
+            let peer: std::net::SocketAddr = format!("{ip}:4242").parse().unwrap();
+            req.extensions_mut().insert(ConnectInfo(peer));
+            async move { router.oneshot(req).await.unwrap().status() }
+        };
+        // Made-up credentials do not get a bucket of their own
+        assert_eq!(status("/health", "10.0.0.1", "a").await, StatusCode::OK);
+        assert_eq!(
+            status("/health", "10.0.0.1", "b").await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        // A verified principal is limited across addresses
+        assert_eq!(
+            status("/api/orders", "10.0.0.2", "reader-key").await,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            status("/api/orders", "10.0.0.3", "reader-key").await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+
+    #[tokio::test]
+    async fn test_streaming_response_holds_concurrency_permit() {
+        let router = create_test_server()
+            .with_max_concurrent_requests(1)
+            .with_order_stream(Arc::new(OrderFeed::new(16)), Duration::from_secs(60))
+            .create_router();
+        let get = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+
+        let stream = router
+            .clone()
+            .oneshot(get("/api/orders/stream"))
+            .await
+            .unwrap();
+        assert_eq!(stream.status(), StatusCode::OK);
+        let response = router.clone().oneshot(get("/health")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        drop(stream);
+        let response = router.oneshot(get("/health")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
 }
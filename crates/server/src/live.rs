@@ -0,0 +1,314 @@
+//! Live feed of newly ingested orders over Server-Sent Events.
+//!
+//! `GET /api/orders/stream` sends an `order` event for every order the consumer
+//! saves, optionally filtered by delivery service and currency. Event ids let a
+//! reconnecting client resume with `Last-Event-ID` from the feed's replay buffer.
+//! A client that falls too far behind, or resumes from an id no longer buffered,
+//! gets a `resync` event telling it to reload the orders it missed. Comments are
+//! sent as heartbeats while no orders arrive, and all streams end when the server
+//! shuts down.
+
+use crate::request_id::ErrorBody;
+use crate::{AppState, Principal, RequestId, audit, pii_view};
+use axum::Extension;
+use axum::extract::{Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use cache::{FeedEvent, OrderFeed};
+use futures_util::stream;
+use model::{Order, PiiView};
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+use utoipa::IntoParams;
+
+/// Header with the id of the last event a reconnecting client received.
+const LAST_EVENT_ID_HEADER: &str = "last-event-id";
+
+/// The order feed as served to live clients.
+pub(crate) struct LiveOrders {
+    pub(crate) feed: Arc<OrderFeed>,
+    pub(crate) heartbeat: Duration,
+    closed: watch::Sender<bool>,
+}
+
+impl LiveOrders {
+    pub(crate) fn new(feed: Arc<OrderFeed>, heartbeat: Duration) -> Self {
+        Self {
+            feed,
+            // A zero interval would spin
+            heartbeat: heartbeat.max(Duration::from_secs(1)),
+            closed: watch::channel(false).0,
+        }
+    }
+
+    /// Ends all live streams, so graceful shutdown does not wait for them.
+    pub(crate) fn close(&self) {
+        self.closed.send_replace(true);
+    }
+
+    /// Resolves once [`LiveOrders::close`] was called.
+    pub(crate) fn closed(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut closed = self.closed.subscribe();
+        async move {
+            // Only an explicit close counts; the sender is dropped with the router
+            if closed.wait_for(|closed| *closed).await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+/// Which orders a live client receives.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct OrderFilter {
+    /// Comma-separated delivery services, e.g. `meest,dhl`; all if absent
+    delivery_service: Option<String>,
+    /// Comma-separated currency codes, e.g. `USD,EUR`; all if absent
+    currency: Option<String>,
+}
+
+impl OrderFilter {
+    fn matches(&self, order: &Order) -> bool {
+        let listed = |list: &Option<String>, value: &str| {
+            list.as_deref().is_none_or(|list| {
+                list.split(',')
+                    .map(str::trim)
+                    .any(|item| item.eq_ignore_ascii_case(value))
+            })
+        };
+        listed(&self.delivery_service, &order.delivery_service)
+            && listed(&self.currency, &order.payment.currency)
+    }
+}
+
+/// What to send next on a stream.
+pub(crate) enum Next {
+    Order(FeedEvent),
+    /// The client missed events; the count if known.
+    Resync(Option<u64>),
+}
+
+/// A client's position in the feed.
+pub(crate) struct Cursor {
+    backlog: VecDeque<FeedEvent>,
+    resync: Option<Option<u64>>,
+    receiver: tokio::sync::broadcast::Receiver<FeedEvent>,
+    filter: OrderFilter,
+}
+
+impl Cursor {
+    /// Subscribes to the feed after `last_id`, or from now on without one.
+    pub(crate) fn new(feed: &OrderFeed, last_id: Option<u64>, filter: OrderFilter) -> Self {
+        let subscription = feed.subscribe(last_id);
+        Self {
+            backlog: subscription.backlog.into(),
+            resync: subscription.gap.then_some(None),
+            receiver: subscription.receiver,
+            filter,
+        }
+    }
+
+    /// Number of buffered events about to be replayed.
+    pub(crate) fn backlog_len(&self) -> usize {
+        self.backlog.len()
+    }
+
+    /// Waits for the next event matching the filter; `None` once the feed is gone.
+    ///
+    /// A client reading slower than orders arrive loses the oldest ones instead of
+    /// holding up the feed; it is told so with a resync.
+    pub(crate) async fn next(&mut self) -> Option<Next> {
+        loop {
+            if let Some(missed) = self.resync.take() {
+                return Some(Next::Resync(missed));
+            }
+            let event = match self.backlog.pop_front() {
+                Some(event) => event,
+                None => match self.receiver.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!("Live order client fell behind by {missed} orders");
+                        self.resync = Some(Some(missed));
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return None,
+                },
+            };
+            if self.filter.matches(&event.order) {
+                return Some(Next::Order(event));
+            }
+        }
+    }
+}
+
+/// Stream newly ingested orders as Server-Sent Events.
+///
+/// Each order is sent as an `order` event with an id; reconnect with
+/// `Last-Event-ID` to resume after it. A `resync` event (`{"missed": n}`, `n`
+/// null if unknown) means orders were skipped because the client fell behind
+/// or resumed from an id no longer buffered. Customer PII is masked unless the
+/// caller has the `pii:read` scope.
+#[utoipa::path(
+    get,
+    path = "/api/orders/stream",
+    tag = "orders",
+    params(
+        OrderFilter,
+        ("Last-Event-ID" = Option<String>, Header, description = "Id of the last event received"),
+    ),
+    responses(
+        (status = 200, description = "Event stream; `order` event data is an order as JSON", body = String, content_type = "text/event-stream"),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Missing scope", body = ErrorBody),
+        (status = 404, description = "The stream is not enabled", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded", body = ErrorBody),
+    ),
+    security(("api_key" = ["orders:read"]), ("bearer" = ["orders:read"]))
+)]
+pub(crate) async fn handle_order_stream(
+    State(state): State<AppState>,
+    principal: Option<Extension<Principal>>,
+    Extension(request_id): Extension<RequestId>,
+    Query(filter): Query<OrderFilter>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(live) = state.live_orders else {
+        return (StatusCode::NOT_FOUND, "order stream is not enabled").into_response();
+    };
+    let last_id = headers
+        .get(LAST_EVENT_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok());
+    let cursor = Cursor::new(&live.feed, last_id, filter);
+    info!("Live order stream opened, resuming after {last_id:?}");
+
+    let view = pii_view(
+        principal.as_deref(),
+        &request_id,
+        "/api/orders/stream",
+        cursor.backlog_len(),
+    );
+    let actor = principal.map(|Extension(p)| p.name);
+    let closed = Box::pin(live.closed());
+    let events = stream::unfold(
+        (cursor, closed, actor, request_id, view),
+        |(mut cursor, mut closed, actor, request_id, view)| async move {
+            let next = tokio::select! {
+                next = cursor.next() => next?,
+                () = &mut closed => return None,
+            };
+            let event = match next {
+                Next::Order(event) => {
+                    if view == PiiView::Full {
+                        audit::unmasked_access(
+                            actor.as_deref().unwrap_or("anonymous"),
+                            Some(&request_id.0),
+                            "/api/orders/stream",
+                            1,
+                        );
+                    }
+                    order_event(&event, view)
+                }
+                Next::Resync(missed) => resync_event(missed),
+            };
+            Some((
+                Ok::<_, Infallible>(event),
+                (cursor, closed, actor, request_id, view),
+            ))
+        },
+    );
+    Sse::new(events)
+        .keep_alive(KeepAlive::new().interval(live.heartbeat))
+        .into_response()
+}
+
+fn order_event(event: &FeedEvent, view: PiiView) -> Event {
+    let data = serde_json::to_string(&event.order.view(view)).unwrap_or_else(|e| {
+        error!("Failed to serialize order: {}", e);
+        "{}".to_string()
+    });
+    Event::default()
+        .id(event.id.to_string())
+        .event("order")
+        .data(data)
+}
+
+fn resync_event(missed: Option<u64>) -> Event {
+    Event::default()
+        .event("resync")
+        .data(serde_json::json!({ "missed": missed }).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::Payment;
+
+    #[test]
+    fn test_filter() {
+        let order = Order {
+            delivery_service: "meest".into(),
+            payment: Payment {
+                currency: "USD".into(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        assert!(OrderFilter::default().matches(&order));
+        let filter = OrderFilter {
+            delivery_service: Some("dhl, meest".into()),
+            currency: Some("usd".into()),
+        };
+        assert!(filter.matches(&order));
+        let filter = OrderFilter {
+            delivery_service: None,
+            currency: Some("EUR".into()),
+        };
+        assert!(!filter.matches(&order));
+    }
+
+    #[tokio::test]
+    async fn test_cursor_filters_and_resyncs() {
+        let feed = OrderFeed::new(2);
+        let order = |uid: &str, currency: &str| Order {
+            order_uid: uid.into(),
+            payment: Payment {
+                currency: currency.into(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut cursor = Cursor::new(
+            &feed,
+            None,
+            OrderFilter {
+                delivery_service: None,
+                currency: Some("USD".into()),
+            },
+        );
+        feed.publish(order("a", "EUR"));
+        feed.publish(order("b", "USD"));
+        let Some(Next::Order(event)) = cursor.next().await else {
+            panic!("expected an order");
+        };
+        assert_eq!(event.order.order_uid, "b");
+
+        for uid in ["c", "d", "e"] {
+            feed.publish(order(uid, "USD"));
+        }
+        assert!(matches!(cursor.next().await, Some(Next::Resync(Some(1)))));
+        let Some(Next::Order(event)) = cursor.next().await else {
+            panic!("expected an order");
+        };
+        assert_eq!(event.order.order_uid, "d");
+    }
+}
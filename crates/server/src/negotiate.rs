@@ -0,0 +1,223 @@
+//! Response formats of the order endpoints, negotiated via `Accept`.
+//!
+//! Orders are served as JSON (the default), newline-delimited JSON or MessagePack.
+//! Handlers take a [`ResponseFormat`] extractor and encode their payload with
+//! [`ResponseFormat::one`] or [`ResponseFormat::many`]; requests accepting none of
+//! the formats are rejected with `406 Not Acceptable`.
+
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use tracing::error;
+
+/// Media types of the supported formats, for `406` responses.
+const SUPPORTED: &str = "application/json, application/x-ndjson, application/msgpack";
+
+/// A response body format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseFormat {
+    /// `application/json`; lists are JSON arrays.
+    Json,
+    /// `application/x-ndjson`; one JSON document per line.
+    NdJson,
+    /// `application/msgpack`, with named fields; lists are MessagePack arrays.
+    MsgPack,
+}
+
+impl ResponseFormat {
+    /// All formats, in order of preference when the client rates several equally.
+    const ALL: [ResponseFormat; 3] = [Self::Json, Self::NdJson, Self::MsgPack];
+
+    /// The `Content-Type` of this format.
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::NdJson => "application/x-ndjson",
+            Self::MsgPack => "application/msgpack",
+        }
+    }
+
+    /// Whether `media_type` (lowercase, without parameters) names this format.
+    fn matches(self, media_type: &str) -> bool {
+        match self {
+            Self::Json => media_type == "application/json",
+            Self::NdJson => matches!(media_type, "application/x-ndjson" | "application/ndjson"),
+            Self::MsgPack => matches!(media_type, "application/msgpack" | "application/x-msgpack"),
+        }
+    }
+
+    /// Picks the format the client prefers, or `None` if it accepts none of them.
+    ///
+    /// Without an `Accept` header the client gets JSON. Each format is rated by the
+    /// most specific media range matching it, as in RFC 9110.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let ranges: Vec<(String, f32)> = headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .filter_map(parse_media_range)
+            .collect();
+        if ranges.is_empty() {
+            return Some(Self::Json);
+        }
+
+        let mut best: Option<(Self, f32)> = None;
+        for format in Self::ALL {
+            // (specificity, q) of the most specific matching range
+            let mut rating: Option<(u8, f32)> = None;
+            for (range, q) in &ranges {
+                let specificity = if format.matches(range) {
+                    2
+                } else if range == "application/*" {
+                    1
+                } else if range == "*/*" {
+                    0
+                } else {
+                    continue;
+                };
+                if rating.is_none_or(|(s, _)| specificity > s) {
+                    rating = Some((specificity, *q));
+                }
+            }
+            if let Some((_, q)) = rating
+                && q > 0.0
+                && best.is_none_or(|(_, best_q)| q > best_q)
+            {
+                best = Some((format, q));
+            }
+        }
+        best.map(|(format, _)| format)
+    }
+
+    /// Encodes a single value.
+    pub fn one<T: Serialize>(self, value: &T) -> Response {
+        let body = match self {
+            Self::Json => serde_json::to_vec(value).map_err(|e| e.to_string()),
+            Self::NdJson => serde_json::to_vec(value)
+                .map(|mut line| {
+                    line.push(b'\n');
+                    line
+                })
+                .map_err(|e| e.to_string()),
+            Self::MsgPack => rmp_serde::to_vec_named(value).map_err(|e| e.to_string()),
+        };
+        self.respond(body)
+    }
+
+    /// Encodes a list of values.
+    pub fn many<T: Serialize>(self, values: &[T]) -> Response {
+        let body = match self {
+            Self::NdJson => values
+                .iter()
+                .try_fold(Vec::new(), |mut body, value| {
+                    serde_json::to_writer(&mut body, value)?;
+                    body.push(b'\n');
+                    Ok(body)
+                })
+                .map_err(|e: serde_json::Error| e.to_string()),
+            Self::Json | Self::MsgPack => return self.one(&values),
+        };
+        self.respond(body)
+    }
+
+    fn respond(self, body: Result<Vec<u8>, String>) -> Response {
+        match body {
+            Ok(body) => (
+                StatusCode::OK,
+                [
+                    (
+                        header::CONTENT_TYPE,
+                        HeaderValue::from_static(self.content_type()),
+                    ),
+                    (header::VARY, HeaderValue::from_static("accept")),
+                ],
+                body,
+            )
+                .into_response(),
+            Err(e) => {
+                error!("Failed to encode {} response: {}", self.content_type(), e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "failed to encode response",
+                )
+                    .into_response()
+            }
+        }
+    }
+}
+
+/// Splits one `Accept` element into its lowercase media range and q-value.
+fn parse_media_range(element: &str) -> Option<(String, f32)> {
+    let mut parts = element.split(';').map(str::trim);
+    let range = parts.next().filter(|r| !r.is_empty())?.to_ascii_lowercase();
+    let q = parts
+        .filter_map(|p| p.strip_prefix("q="))
+        .find_map(|q| q.parse::<f32>().ok())
+        .unwrap_or(1.0);
+    Some((range, q))
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for ResponseFormat {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Self::from_headers(&parts.headers).ok_or_else(|| {
+            (
+                StatusCode::NOT_ACCEPTABLE,
+                format!("supported media types: {SUPPORTED}"),
+            )
+                .into_response()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn negotiate(accept: &str) -> Option<ResponseFormat> {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, accept.parse().unwrap());
+        ResponseFormat::from_headers(&headers)
+    }
+
+    #[test]
+    fn test_negotiation() {
+        assert_eq!(
+            ResponseFormat::from_headers(&HeaderMap::new()),
+            Some(ResponseFormat::Json)
+        );
+        assert_eq!(negotiate("*/*"), Some(ResponseFormat::Json));
+        assert_eq!(
+            negotiate("application/x-ndjson"),
+            Some(ResponseFormat::NdJson)
+        );
+        assert_eq!(
+            negotiate("application/json;q=0.5, application/msgpack"),
+            Some(ResponseFormat::MsgPack)
+        );
+        assert_eq!(
+            negotiate("application/*, application/json;q=0"),
+            Some(ResponseFormat::NdJson)
+        );
+        assert_eq!(negotiate("text/html"), None);
+    }
+
+    #[tokio::test]
+    async fn test_ndjson_has_one_document_per_line() {
+        let values = vec![serde_json::json!({"a": 1}), serde_json::json!({"a": 2})];
+
+        let response = ResponseFormat::NdJson.many(&values);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/x-ndjson"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body.as_ref(), b"{\"a\":1}\n{\"a\":2}\n");
+    }
+}
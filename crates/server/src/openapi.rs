@@ -0,0 +1,135 @@
+//! OpenAPI 3.1 description of the HTTP API, served at `/api/openapi.json`.
+//!
+//! Schemas are derived from the `model` types and the server's own request and
+//! response types, so field docs come from their doc comments. Each operation is
+//! declared by `#[utoipa::path]` on its handler, and the routes are registered
+//! through [`OpenApiRouter`](utoipa_axum::router::OpenApiRouter), so the router
+//! and the document are built from the same list.
+//!
+//! The documentation page renders the document with Redoc, whose bundle is
+//! compiled into the binary and served next to the page.
+
+use crate::OrderAccepted;
+use crate::request_id::ErrorBody;
+use axum::http::{HeaderValue, header};
+use axum::response::{IntoResponse, Response};
+use model::{Delivery, Item, Order, Payment};
+use std::sync::LazyLock;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+/// The API description.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Shopping cart orders API",
+        description = "Read, create and update orders of the shopping cart backend."
+    ),
+    components(schemas(Order, Delivery, Payment, Item, OrderAccepted, ErrorBody)),
+    modifiers(&SecuritySchemes),
+    tags(
+        (name = "orders", description = "Order resources"),
+        (name = "operations", description = "Health, metrics and maintenance"),
+        (name = "docs", description = "This description")
+    )
+)]
+pub struct ApiDoc;
+
+/// Registers the authentication schemes accepted unless `AUTH_ENABLED=false`.
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                crate::auth::API_KEY_HEADER,
+                "Static API key",
+            ))),
+        );
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .description(Some(
+                        "HS256 or RS256 JWT with the scopes in its `scope` claim",
+                    ))
+                    .build(),
+            ),
+        );
+    }
+}
+
+/// The document as JSON, built once from the API routes.
+static SPEC: LazyLock<String> = LazyLock::new(|| {
+    let (_, openapi) = crate::api_routes().split_for_parts();
+    openapi
+        .to_pretty_json()
+        .expect("the OpenAPI document serializes")
+});
+
+/// Page rendering the document with Redoc.
+const DOCS_PAGE: &str = include_str!("../assets/api-docs.html");
+
+/// Path of the Redoc bundle loaded by the documentation page.
+pub(crate) const REDOC_JS_PATH: &str = "/api/docs/redoc.standalone.js";
+
+/// The Redoc 2.0.0 standalone bundle (MIT licensed).
+const REDOC_JS: &str = include_str!("../assets/redoc.standalone.js");
+
+/// This OpenAPI document.
+#[utoipa::path(
+    get,
+    path = "/api/openapi.json",
+    tag = "docs",
+    responses((status = 200, description = "OpenAPI 3.1 document", body = Object))
+)]
+pub(crate) async fn handle_openapi_json() -> Response {
+    (
+        [(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        )],
+        SPEC.as_str(),
+    )
+        .into_response()
+}
+
+/// Documentation page rendering this document.
+#[utoipa::path(
+    get,
+    path = "/api/docs",
+    tag = "docs",
+    responses((status = 200, description = "HTML page", body = String, content_type = "text/html"))
+)]
+pub(crate) async fn handle_api_docs() -> Response {
+    (
+        [(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("text/html; charset=utf-8"),
+        )],
+        DOCS_PAGE,
+    )
+        .into_response()
+}
+
+/// Serves the Redoc bundle of the documentation page.
+pub(crate) async fn handle_redoc_js() -> Response {
+    (
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("text/javascript; charset=utf-8"),
+            ),
+            (
+                header::CACHE_CONTROL,
+                HeaderValue::from_static("public, max-age=86400"),
+            ),
+        ],
+        REDOC_JS,
+    )
+        .into_response()
+}
@@ -0,0 +1,283 @@
+//! Per-client rate limiting and a global concurrency limit.
+//!
+//! Each client gets a token bucket per route template. Before authentication,
+//! clients are identified by their IP address only: credentials that have not
+//! been verified yet would let anyone pick a fresh bucket per request. Once
+//! authenticated, a request also takes a token from its principal's bucket, so a
+//! principal spread over many addresses is limited as well. A request finding a
+//! bucket empty is rejected with `429 Too Many Requests` and a `Retry-After` header.
+//!
+//! Independently of the client, at most a configured number of requests are
+//! handled at once; requests beyond that are shed with `503 Service Unavailable`.
+//! A request counts until its response body is finished, so open event streams,
+//! exports and WebSocket connections keep their permit.
+
+use crate::Metrics;
+use crate::auth::Principal;
+use app_config::{RateQuota, RouteQuota};
+use axum::body::{Body, Bytes, HttpBody};
+use axum::extract::{ConnectInfo, MatchedPath, Request, State};
+use axum::http::{StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use http_body::{Frame, SizeHint};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::warn;
+
+/// Number of buckets above which idle, full buckets are dropped.
+const MAX_IDLE_BUCKETS: usize = 10_000;
+
+/// A client's token bucket for one route.
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token bucket rate limiter keyed by client and route.
+pub struct RateLimiter {
+    default: Option<RateQuota>,
+    routes: HashMap<String, RateQuota>,
+    buckets: Mutex<HashMap<(String, String), Bucket>>,
+}
+
+impl RateLimiter {
+    /// Creates a limiter applying `routes` quotas to their routes and `default` to
+    /// all others; routes without a quota are not limited.
+    pub fn new(default: Option<RateQuota>, routes: Vec<RouteQuota>) -> Self {
+        Self {
+            default,
+            routes: routes.into_iter().map(|r| (r.route, r.quota)).collect(),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token from `client`'s bucket for `route`.
+    ///
+    /// Returns how long until a token is available if the bucket is empty.
+    fn acquire(&self, client: &str, route: &str, now: Instant) -> Result<(), Duration> {
+        let Some(quota) = self.routes.get(route).or(self.default.as_ref()) else {
+            return Ok(());
+        };
+        let burst = f64::from(quota.burst);
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        if buckets.len() > MAX_IDLE_BUCKETS {
+            buckets.retain(|(_, route), bucket| {
+                let quota = self.routes.get(route).or(self.default.as_ref());
+                quota.is_some_and(|q| refill(bucket, q, now) < f64::from(q.burst))
+            });
+        }
+
+        let bucket = buckets
+            .entry((client.to_string(), route.to_string()))
+            .or_insert(Bucket {
+                tokens: burst,
+                updated: now,
+            });
+        bucket.tokens = refill(bucket, quota, now);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / quota.per_second,
+            ))
+        }
+    }
+}
+
+/// Tokens in `bucket` at `now`.
+fn refill(bucket: &Bucket, quota: &RateQuota, now: Instant) -> f64 {
+    let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+    (bucket.tokens + elapsed * quota.per_second).min(f64::from(quota.burst))
+}
+
+/// Identifies a client that has not been authenticated by its IP address.
+fn client_key(peer: Option<SocketAddr>) -> String {
+    match peer {
+        Some(peer) => format!("ip:{}", peer.ip()),
+        None => "unknown".to_string(),
+    }
+}
+
+/// Rejects requests exceeding their IP address's quota for the matched route.
+pub(crate) async fn middleware(
+    State((limiter, metrics)): State<(Arc<RateLimiter>, Arc<Metrics>)>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let peer = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| *addr);
+    let client = client_key(peer);
+    limit(&limiter, &metrics, &client, req, next).await
+}
+
+/// Rejects requests exceeding their principal's quota for the matched route.
+///
+/// Runs after authentication; requests without a principal pass, having already
+/// been limited by their IP address.
+pub(crate) async fn principal_middleware(
+    State((limiter, metrics)): State<(Arc<RateLimiter>, Arc<Metrics>)>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let Some(principal) = req.extensions().get::<Principal>() else {
+        return next.run(req).await;
+    };
+    let client = format!("principal:{}", principal.name);
+    limit(&limiter, &metrics, &client, req, next).await
+}
+
+/// Runs `req` if `client` has a token left for the matched route.
+async fn limit(
+    limiter: &RateLimiter,
+    metrics: &Metrics,
+    client: &str,
+    req: Request,
+    next: Next,
+) -> Response {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("", MatchedPath::as_str)
+        .to_string();
+
+    match limiter.acquire(client, &route, Instant::now()) {
+        Ok(()) => next.run(req).await,
+        Err(retry_after) => {
+            warn!("Rate limited {client} on {route}");
+            metrics.record_rejection("rate_limited");
+            let retry_after = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, retry_after.to_string())],
+                "rate limit exceeded",
+            )
+                .into_response()
+        }
+    }
+}
+
+/// A request's share of the concurrency limit, returned once every clone is dropped.
+///
+/// The response body holds one. Handlers whose work outlives the body, such as a
+/// WebSocket connection after the upgrade, take another from the request extensions.
+#[derive(Clone)]
+pub(crate) struct ConcurrencyPermit {
+    _permit: Arc<OwnedSemaphorePermit>,
+}
+
+/// Response body holding the request's concurrency permit until it is dropped.
+struct PermitBody {
+    inner: Body,
+    _permit: ConcurrencyPermit,
+}
+
+impl HttpBody for PermitBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, axum::Error>>> {
+        Pin::new(&mut self.inner).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Sheds requests while all `permits` are taken by requests being handled.
+pub(crate) async fn concurrency_middleware(
+    State((permits, metrics)): State<(Arc<Semaphore>, Arc<Metrics>)>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    match permits.try_acquire_owned() {
+        Ok(permit) => {
+            let permit = ConcurrencyPermit {
+                _permit: Arc::new(permit),
+            };
+            req.extensions_mut().insert(permit.clone());
+            // Streamed bodies are still being produced after the headers are sent
+            next.run(req).await.map(|inner| {
+                Body::new(PermitBody {
+                    inner,
+                    _permit: permit,
+                })
+            })
+        }
+        Err(_) => {
+            warn!(
+                "Shedding request to {}: server overloaded",
+                req.uri().path()
+            );
+            metrics.record_rejection("overloaded");
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(header::RETRY_AFTER, "1")],
+                "server overloaded",
+            )
+                .into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let limiter = RateLimiter::new(
+            Some(RateQuota {
+                per_second: 100.0,
+                burst: 100,
+            }),
+            vec![RouteQuota {
+                route: "/api/send-test-order".into(),
+                quota: RateQuota {
+                    per_second: 0.5,
+                    burst: 2,
+                },
+            }],
+        );
+        let start = Instant::now();
+        let route = "/api/send-test-order";
+
+        assert!(limiter.acquire("ip:10.0.0.1", route, start).is_ok());
+        assert!(limiter.acquire("ip:10.0.0.1", route, start).is_ok());
+        let retry_after = limiter.acquire("ip:10.0.0.1", route, start).unwrap_err();
+        assert_eq!(retry_after, Duration::from_secs(2));
+
+        // Other clients and routes have their own buckets
+        assert!(limiter.acquire("ip:10.0.0.2", route, start).is_ok());
+        assert!(limiter.acquire("ip:10.0.0.1", "/health", start).is_ok());
+
+        // One token is back after two seconds
+        let later = start + Duration::from_secs(2);
+        assert!(limiter.acquire("ip:10.0.0.1", route, later).is_ok());
+        assert!(limiter.acquire("ip:10.0.0.1", route, later).is_err());
+    }
+
+    #[test]
+    fn test_client_key() {
+        let peer: SocketAddr = "10.0.0.1:4242".parse().unwrap();
+        assert_eq!(client_key(Some(peer)), "ip:10.0.0.1");
+        assert_eq!(client_key(None), "unknown");
+    }
+}
@@ -0,0 +1,110 @@
+//! Request ids correlating the logs, traces and Kafka messages of one HTTP request.
+//!
+//! Every request gets an id: the client's `X-Request-Id` if it sent a usable one,
+//! a new UUID otherwise. The id is recorded on the request's span, returned in the
+//! `X-Request-Id` response header, and included in error bodies, which are
+//! rewritten from plain text to `{"error": ..., "request_id": ...}`.
+
+use axum::body::{Body, to_bytes};
+use axum::extract::Request;
+use axum::http::{HeaderValue, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use telemetry::REQUEST_ID_HEADER;
+use tracing::{Instrument, info_span, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Longest client-supplied request id that is accepted.
+const MAX_LEN: usize = 128;
+
+/// Largest error body that is rewritten to include the request id.
+const MAX_ERROR_BODY: usize = 64 * 1024;
+
+/// Body of error responses.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorBody {
+    /// What went wrong
+    pub error: String,
+    /// Id of the failed request, as in the `X-Request-Id` header
+    pub request_id: String,
+}
+
+/// The id of the current request, available to handlers as an extension.
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    /// Uses the client's id if it is short and printable, otherwise generates one.
+    fn from_request(req: &Request) -> Self {
+        let supplied = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .filter(|id| {
+                !id.is_empty() && id.len() <= MAX_LEN && id.bytes().all(|b| b.is_ascii_graphic())
+            });
+        Self(supplied.map_or_else(|| Uuid::new_v4().to_string(), str::to_string))
+    }
+}
+
+/// Assigns the request id and runs the request in a span carrying it.
+///
+/// The span continues the trace of the request's `traceparent` header, if any.
+pub(crate) async fn middleware(mut req: Request, next: Next) -> Response {
+    let request_id = RequestId::from_request(&req);
+    let span = info_span!(
+        "http.request",
+        method = %req.method(),
+        path = %req.uri().path(),
+        request_id = %request_id.0,
+    );
+    telemetry::continue_trace(
+        &span,
+        req.headers()
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_bytes())),
+    );
+    req.extensions_mut().insert(request_id.clone());
+
+    let response = next.run(req).instrument(span).await;
+    let mut response = if response.status().is_client_error() || response.status().is_server_error()
+    {
+        with_request_id_in_body(response, &request_id).await
+    } else {
+        response
+    };
+    if let Ok(value) = HeaderValue::from_str(&request_id.0) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+/// Rewrites a plain-text error body as JSON with the request id.
+async fn with_request_id_in_body(response: Response, request_id: &RequestId) -> Response {
+    let is_text = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|ct| ct.starts_with("text/plain"));
+    if !is_text {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let message = match to_bytes(body, MAX_ERROR_BODY).await {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        Err(e) => {
+            warn!("Failed to read error body: {e}");
+            return (parts.status, Body::empty()).into_response();
+        }
+    };
+    parts.headers.remove(header::CONTENT_LENGTH);
+    let body = ErrorBody {
+        error: message,
+        request_id: request_id.0.clone(),
+    };
+    (parts, axum::Json(body)).into_response()
+}
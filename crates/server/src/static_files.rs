@@ -0,0 +1,367 @@
+//! Static file serving for the web UI.
+//!
+//! Files are served from a directory on disk or, with the `embed-static` feature,
+//! from assets compiled into the binary. Request paths are percent-decoded and
+//! normalized; `..` segments and hidden files are refused, and resolved disk paths
+//! must stay inside the canonicalized static directory.
+//!
+//! Responses carry a strong ETag (content hash), `Last-Modified` and
+//! `Cache-Control`, and conditional requests are answered with `304`. When the
+//! client accepts it, a precompressed `.br` or `.gz` sibling of the file is served
+//! instead, with the matching `Content-Encoding`. Files read from disk are cached
+//! in memory and re-read only when their modification time or size changes.
+
+use axum::body::{Body, Bytes};
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use percent_encoding::percent_decode_str;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+/// `Cache-Control` of HTML pages, which must pick up new asset versions.
+const HTML_CACHE_CONTROL: &str = "no-cache";
+/// `Cache-Control` of all other assets.
+const ASSET_CACHE_CONTROL: &str = "public, max-age=3600";
+
+/// Precompressed variants, in order of preference: encoding and file suffix.
+const PRECOMPRESSED: [(&str, &str); 2] = [("br", ".br"), ("gzip", ".gz")];
+
+#[cfg(feature = "embed-static")]
+#[derive(rust_embed::Embed)]
+#[folder = "../../static"]
+struct EmbeddedAssets;
+
+/// Where static files come from.
+enum Source {
+    Disk(PathBuf),
+    #[cfg(feature = "embed-static")]
+    Embedded,
+}
+
+/// A loaded file and the metadata its response headers are built from.
+struct StaticFile {
+    data: Bytes,
+    etag: String,
+    last_modified: Option<SystemTime>,
+    /// Size and modification time on disk, to detect changed files.
+    stamp: Option<(u64, SystemTime)>,
+}
+
+/// Serves static files with caching headers and precompressed variants.
+pub struct StaticFiles {
+    source: Source,
+    cache: RwLock<HashMap<String, Arc<StaticFile>>>,
+}
+
+impl StaticFiles {
+    /// Serves files from `dir`.
+    pub fn disk(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            source: Source::Disk(dir.into()),
+            cache: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Serves the files of the `static` directory compiled into the binary.
+    #[cfg(feature = "embed-static")]
+    pub fn embedded() -> Self {
+        Self {
+            source: Source::Embedded,
+            cache: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Answers a request for `uri_path`.
+    pub async fn serve(&self, method: &Method, uri_path: &str, headers: &HeaderMap) -> Response {
+        if method != Method::GET && method != Method::HEAD {
+            return (
+                StatusCode::METHOD_NOT_ALLOWED,
+                [(header::ALLOW, "GET, HEAD")],
+                "method not allowed",
+            )
+                .into_response();
+        }
+        let Some(path) = normalize(uri_path) else {
+            warn!("Refused static path {uri_path}");
+            return (StatusCode::NOT_FOUND, "File not found").into_response();
+        };
+
+        let accepted = accepted_encodings(headers);
+        let mut found = None;
+        for (encoding, suffix) in PRECOMPRESSED {
+            if accepted.contains(&encoding)
+                && let Some(file) = self.load(&format!("{path}{suffix}")).await
+            {
+                found = Some((file, Some(encoding)));
+                break;
+            }
+        }
+        if found.is_none() {
+            found = self.load(&path).await.map(|file| (file, None));
+        }
+        let Some((file, encoding)) = found else {
+            debug!("Static file not found: {path}");
+            return (StatusCode::NOT_FOUND, "File not found").into_response();
+        };
+
+        let content_type = mime_guess::from_path(&path).first_or_octet_stream();
+        let cache_control = if content_type.essence_str() == "text/html" {
+            HTML_CACHE_CONTROL
+        } else {
+            ASSET_CACHE_CONTROL
+        };
+
+        let mut response_headers = HeaderMap::new();
+        response_headers.insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static(cache_control),
+        );
+        response_headers.insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+        if let Ok(etag) = HeaderValue::from_str(&file.etag) {
+            response_headers.insert(header::ETAG, etag);
+        }
+        if let Some(last_modified) = file.last_modified
+            && let Ok(value) = HeaderValue::from_str(&httpdate::fmt_http_date(last_modified))
+        {
+            response_headers.insert(header::LAST_MODIFIED, value);
+        }
+
+        if not_modified(headers, &file) {
+            return (StatusCode::NOT_MODIFIED, response_headers).into_response();
+        }
+
+        if let Ok(value) = HeaderValue::from_str(content_type.as_ref()) {
+            response_headers.insert(header::CONTENT_TYPE, value);
+        }
+        if let Some(encoding) = encoding {
+            response_headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding));
+        }
+        (
+            StatusCode::OK,
+            response_headers,
+            Body::from(file.data.clone()),
+        )
+            .into_response()
+    }
+
+    /// Loads a file by its normalized relative path.
+    async fn load(&self, path: &str) -> Option<Arc<StaticFile>> {
+        match &self.source {
+            Source::Disk(dir) => self.load_from_disk(dir, path).await,
+            #[cfg(feature = "embed-static")]
+            Source::Embedded => self.load_embedded(path),
+        }
+    }
+
+    async fn load_from_disk(&self, dir: &PathBuf, path: &str) -> Option<Arc<StaticFile>> {
+        let root = tokio::fs::canonicalize(dir).await.ok()?;
+        let file_path = tokio::fs::canonicalize(root.join(path)).await.ok()?;
+        // Symlinks must not lead out of the static directory either
+        if !file_path.starts_with(&root) {
+            warn!(
+                "Refused static path {path} resolving outside {}",
+                root.display()
+            );
+            return None;
+        }
+        let metadata = tokio::fs::metadata(&file_path).await.ok()?;
+        if !metadata.is_file() {
+            return None;
+        }
+        let stamp = metadata
+            .modified()
+            .ok()
+            .map(|mtime| (metadata.len(), mtime));
+
+        if let Some(cached) = self.cached(path)
+            && stamp.is_some()
+            && cached.stamp == stamp
+        {
+            return Some(cached);
+        }
+
+        let data = tokio::fs::read(&file_path).await.ok()?;
+        let file = Arc::new(StaticFile {
+            etag: etag(&Sha256::digest(&data)),
+            data: Bytes::from(data),
+            last_modified: stamp.map(|(_, mtime)| mtime),
+            stamp,
+        });
+        self.cache
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(path.to_string(), file.clone());
+        Some(file)
+    }
+
+    #[cfg(feature = "embed-static")]
+    fn load_embedded(&self, path: &str) -> Option<Arc<StaticFile>> {
+        if let Some(cached) = self.cached(path) {
+            return Some(cached);
+        }
+        let asset = <EmbeddedAssets as rust_embed::RustEmbed>::get(path)?;
+        let file = Arc::new(StaticFile {
+            etag: etag(&asset.metadata.sha256_hash()),
+            last_modified: asset
+                .metadata
+                .last_modified()
+                .map(|secs| UNIX_EPOCH + std::time::Duration::from_secs(secs)),
+            data: Bytes::from(asset.data.into_owned()),
+            stamp: None,
+        });
+        self.cache
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(path.to_string(), file.clone());
+        Some(file)
+    }
+
+    fn cached(&self, path: &str) -> Option<Arc<StaticFile>> {
+        self.cache
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(path)
+            .cloned()
+    }
+}
+
+/// Turns a request path into a relative file path, or `None` if it is not allowed.
+///
+/// Directories map to their `index.html`.
+fn normalize(uri_path: &str) -> Option<String> {
+    let decoded = percent_decode_str(uri_path).decode_utf8().ok()?;
+    let mut segments = Vec::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => return None,
+            s if s.starts_with('.') || s.contains(['\\', '\0']) => return None,
+            s => segments.push(s),
+        }
+    }
+    if segments.is_empty() || decoded.ends_with('/') {
+        segments.push("index.html");
+    }
+    Some(segments.join("/"))
+}
+
+/// Content codings the client accepts, ignoring those it disables with `q=0`.
+fn accepted_encodings(headers: &HeaderMap) -> Vec<&str> {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|coding| {
+            let mut parts = coding.split(';').map(str::trim);
+            let name = parts.next()?;
+            let disabled = parts.any(|p| {
+                p.strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+            (!disabled && !name.is_empty()).then_some(name)
+        })
+        .collect()
+}
+
+/// Formats a content hash as a strong ETag.
+fn etag(hash: &[u8]) -> String {
+    format!("\"{}\"", hex::encode(&hash[..16]))
+}
+
+/// Whether the conditional request headers allow a `304` answer.
+///
+/// `If-None-Match` takes precedence over `If-Modified-Since`.
+fn not_modified(headers: &HeaderMap, file: &StaticFile) -> bool {
+    if let Some(if_none_match) = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+    {
+        return if_none_match.split(',').map(str::trim).any(|tag| {
+            tag == "*" || tag == file.etag || tag.strip_prefix("W/") == Some(&file.etag)
+        });
+    }
+    let since = headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| httpdate::parse_http_date(v).ok());
+    match (since, file.last_modified) {
+        // HTTP dates have whole-second precision
+        (Some(since), Some(modified)) => {
+            let modified = modified
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs());
+            let since = since.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+            modified <= since
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("/").as_deref(), Some("index.html"));
+        assert_eq!(normalize("/css/app.css").as_deref(), Some("css/app.css"));
+        assert_eq!(normalize("/docs/").as_deref(), Some("docs/index.html"));
+        assert_eq!(normalize("/a//./b.js").as_deref(), Some("a/b.js"));
+        assert_eq!(normalize("/../etc/passwd"), None);
+        assert_eq!(normalize("/%2e%2e/etc/passwd"), None);
+        assert_eq!(normalize("/a/..%2f..%2fsecret"), None);
+        assert_eq!(normalize("/.env"), None);
+        assert_eq!(normalize("/a%5c..%5csecret"), None);
+    }
+
+    #[tokio::test]
+    async fn test_serves_binary_files_with_caching_headers() {
+        let dir = std::env::temp_dir().join(format!("static-files-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let png = [0x89, b'P', b'N', b'G', 0xff, 0x00];
+        std::fs::write(dir.join("logo.png"), png).unwrap();
+        std::fs::write(dir.join("app.js"), "plain").unwrap();
+        std::fs::write(dir.join("app.js.gz"), "gzipped").unwrap();
+        let files = StaticFiles::disk(&dir);
+
+        let response = files
+            .serve(&Method::GET, "/logo.png", &HeaderMap::new())
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+        assert_eq!(
+            response.headers()[header::CACHE_CONTROL],
+            ASSET_CACHE_CONTROL
+        );
+        assert!(response.headers().contains_key(header::LAST_MODIFIED));
+        let etag = response.headers()[header::ETAG].clone();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body.as_ref(), png);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, etag);
+        let response = files.serve(&Method::GET, "/logo.png", &headers).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_ENCODING, "br;q=0, gzip".parse().unwrap());
+        let response = files.serve(&Method::GET, "/app.js", &headers).await;
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/javascript");
+
+        let response = files
+            .serve(&Method::GET, "/../logo.png", &HeaderMap::new())
+            .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
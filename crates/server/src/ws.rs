@@ -0,0 +1,352 @@
+//! Subscriptions to order changes over WebSocket.
+//!
+//! `GET /api/orders/ws` upgrades to a WebSocket on which a client subscribes to
+//! single orders by `order_uid`, or to all orders of a `customer_id`, with JSON
+//! text messages such as `{"action": "subscribe", "order_uid": "..."}`. Whenever
+//! the order service creates or updates a matching order, the client gets a
+//! `change` message with the order. Following a customer needs the `pii:read`
+//! scope, and each connection may hold a limited number of subscriptions.
+//!
+//! The server pings every client at a fixed interval and drops connections that
+//! did not answer the previous ping. A client reading slower than orders change
+//! gets a `resync` message instead of the changes it missed. All connections are
+//! closed when the server shuts down.
+
+use crate::auth::Scope;
+use crate::rate_limit::ConcurrencyPermit;
+use crate::request_id::ErrorBody;
+use crate::{AppState, Principal, RequestId, audit};
+use axum::Extension;
+use axum::extract::State;
+use axum::extract::ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade, close_code};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use model::{Order, PiiView};
+use serde::{Deserialize, Serialize};
+use service::{OrderChange, OrderChanges};
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::watch;
+use tokio::time::{Instant, MissedTickBehavior};
+use tracing::{debug, error, info, warn};
+
+/// Route of the WebSocket, also the resource named in audit records.
+const RESOURCE: &str = "/api/orders/ws";
+
+/// Largest message accepted from a client; requests are tiny.
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+/// The order change notifications as served to WebSocket clients.
+pub(crate) struct OrderSubscriptions {
+    changes: OrderChanges,
+    max_subscriptions: usize,
+    ping_interval: Duration,
+    closed: watch::Sender<bool>,
+}
+
+impl OrderSubscriptions {
+    pub(crate) fn new(
+        changes: OrderChanges,
+        max_subscriptions: usize,
+        ping_interval: Duration,
+    ) -> Self {
+        Self {
+            changes,
+            max_subscriptions: max_subscriptions.max(1),
+            // A zero interval would spin
+            ping_interval: ping_interval.max(Duration::from_secs(1)),
+            closed: watch::channel(false).0,
+        }
+    }
+
+    /// Closes all connections, so graceful shutdown does not wait for them.
+    pub(crate) fn close(&self) {
+        self.closed.send_replace(true);
+    }
+
+    /// Resolves once [`OrderSubscriptions::close`] was called.
+    fn closed(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut closed = self.closed.subscribe();
+        async move {
+            // Only an explicit close counts; the sender is dropped with the router
+            if closed.wait_for(|closed| *closed).await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+/// What a client subscribes to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Topic {
+    /// A single order.
+    OrderUid(String),
+    /// All orders of a customer.
+    CustomerId(String),
+}
+
+impl Topic {
+    fn matches(&self, order: &Order) -> bool {
+        match self {
+            Self::OrderUid(uid) => order.order_uid == *uid,
+            Self::CustomerId(id) => order.customer_id == *id,
+        }
+    }
+
+    /// The scope needed to subscribe, beyond `orders:read` for the route.
+    ///
+    /// Following a customer reveals which orders they place, so it is limited to
+    /// callers that may see customer PII.
+    fn required_scope(&self) -> Option<Scope> {
+        match self {
+            Self::OrderUid(_) => None,
+            Self::CustomerId(_) => Some(Scope::PiiRead),
+        }
+    }
+}
+
+/// A message from the client.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum Request {
+    Subscribe(Topic),
+    Unsubscribe(Topic),
+}
+
+/// A message to the client.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Reply<'a> {
+    Subscribed(&'a Topic),
+    Unsubscribed(&'a Topic),
+    Change {
+        /// `created` or `updated`
+        change: String,
+        order: &'a Order,
+    },
+    /// Changes were dropped because the client fell behind.
+    Resync {
+        missed: u64,
+    },
+    Error {
+        message: String,
+    },
+}
+
+impl Reply<'_> {
+    fn into_message(self) -> Message {
+        let text = serde_json::to_string(&self).unwrap_or_else(|e| {
+            error!("Failed to serialize WebSocket reply: {}", e);
+            r#"{"type":"error","message":"internal error"}"#.to_string()
+        });
+        Message::text(text)
+    }
+}
+
+/// Subscribe to changes of orders over a WebSocket.
+///
+/// Send `{"action": "subscribe", "order_uid": "..."}` or `{"action":
+/// "subscribe", "customer_id": "..."}` (the latter needs the `pii:read` scope),
+/// and `"action": "unsubscribe"` to stop. Replies are `subscribed`,
+/// `unsubscribed` and `error` messages; each created or updated order matching
+/// a subscription arrives as `{"type": "change", "change": "created" |
+/// "updated", "order": {...}}`. A `resync` message (`{"missed": n}`) means
+/// changes were dropped because the client fell behind. Clients must answer
+/// pings. Customer PII is masked unless the caller has the `pii:read` scope.
+#[utoipa::path(
+    get,
+    path = "/api/orders/ws",
+    tag = "orders",
+    responses(
+        (status = 101, description = "Switched to the WebSocket protocol"),
+        (status = 400, description = "Not a WebSocket handshake", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Missing scope", body = ErrorBody),
+        (status = 404, description = "Subscriptions are not enabled", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded", body = ErrorBody),
+    ),
+    security(("api_key" = ["orders:read"]), ("bearer" = ["orders:read"]))
+)]
+pub(crate) async fn handle_order_ws(
+    State(state): State<AppState>,
+    principal: Option<Extension<Principal>>,
+    Extension(request_id): Extension<RequestId>,
+    permit: Option<Extension<ConcurrencyPermit>>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let Some(subscriptions) = state.order_subscriptions else {
+        return (StatusCode::NOT_FOUND, "order subscriptions are not enabled").into_response();
+    };
+    let principal = principal.map(|Extension(p)| p);
+    ws.max_message_size(MAX_MESSAGE_SIZE)
+        .on_upgrade(move |socket| async move {
+            // The connection counts against the concurrency limit until it closes
+            let _permit = permit;
+            info!("Order subscription connection opened");
+            Connection::new(subscriptions, principal, request_id)
+                .run(socket)
+                .await;
+            info!("Order subscription connection closed");
+        })
+}
+
+/// State of one client connection.
+struct Connection {
+    subscriptions: Arc<OrderSubscriptions>,
+    principal: Option<Principal>,
+    request_id: RequestId,
+    view: PiiView,
+    topics: HashSet<Topic>,
+}
+
+impl Connection {
+    fn new(
+        subscriptions: Arc<OrderSubscriptions>,
+        principal: Option<Principal>,
+        request_id: RequestId,
+    ) -> Self {
+        // Without authentication there is no caller to restrict, as on the other routes
+        let view = principal
+            .as_ref()
+            .map_or(PiiView::Full, Principal::pii_view);
+        Self {
+            subscriptions,
+            principal,
+            request_id,
+            view,
+            topics: HashSet::new(),
+        }
+    }
+
+    async fn run(mut self, mut socket: WebSocket) {
+        let mut changes = self.subscriptions.changes.subscribe();
+        let mut closed = Box::pin(self.subscriptions.closed());
+        let interval = self.subscriptions.ping_interval;
+        let mut ping = tokio::time::interval_at(Instant::now() + interval, interval);
+        ping.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut awaiting_pong = false;
+
+        loop {
+            let reply = tokio::select! {
+                () = &mut closed => {
+                    let _ = socket
+                        .send(Message::Close(Some(CloseFrame {
+                            code: close_code::AWAY,
+                            reason: "server shutting down".into(),
+                        })))
+                        .await;
+                    return;
+                }
+                _ = ping.tick() => {
+                    if awaiting_pong {
+                        warn!("Order subscription client missed a ping, disconnecting");
+                        return;
+                    }
+                    awaiting_pong = true;
+                    Message::Ping(Default::default())
+                }
+                message = socket.recv() => match message {
+                    Some(Ok(Message::Text(text))) => self.handle_request(&text),
+                    Some(Ok(Message::Binary(_))) => Reply::Error {
+                        message: "requests must be JSON text messages".into(),
+                    }
+                    .into_message(),
+                    Some(Ok(Message::Pong(_))) => {
+                        awaiting_pong = false;
+                        continue;
+                    }
+                    // Pings are answered by the socket itself
+                    Some(Ok(Message::Ping(_))) => continue,
+                    Some(Ok(Message::Close(_))) | None => return,
+                    Some(Err(e)) => {
+                        debug!("Order subscription connection failed: {}", e);
+                        return;
+                    }
+                },
+                change = changes.recv() => match change {
+                    Ok(change) => match self.deliver(&change) {
+                        Some(message) => message,
+                        None => continue,
+                    },
+                    Err(RecvError::Lagged(missed)) if !self.topics.is_empty() => {
+                        warn!("Order subscription client fell behind by {missed} changes");
+                        Reply::Resync { missed }.into_message()
+                    }
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return,
+                },
+            };
+            if socket.send(reply).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Applies a client request and returns the reply.
+    fn handle_request(&mut self, text: &str) -> Message {
+        let request = match serde_json::from_str::<Request>(text) {
+            Ok(request) => request,
+            Err(e) => {
+                return Reply::Error {
+                    message: format!("invalid request: {e}"),
+                }
+                .into_message();
+            }
+        };
+        match request {
+            Request::Subscribe(topic) => match self.subscribe(topic.clone()) {
+                Ok(()) => Reply::Subscribed(&topic).into_message(),
+                Err(message) => Reply::Error { message }.into_message(),
+            },
+            Request::Unsubscribe(topic) => {
+                self.topics.remove(&topic);
+                Reply::Unsubscribed(&topic).into_message()
+            }
+        }
+    }
+
+    /// Adds a subscription, if the caller may hold it.
+    fn subscribe(&mut self, topic: Topic) -> Result<(), String> {
+        if let Some(scope) = topic.required_scope()
+            && let Some(principal) = &self.principal
+            && !principal.has_scope(scope)
+        {
+            warn!(
+                "Rejected order subscription of {}: missing scope {}",
+                principal.name, scope
+            );
+            return Err(format!("missing scope {scope}"));
+        }
+        let max = self.subscriptions.max_subscriptions;
+        if !self.topics.contains(&topic) && self.topics.len() >= max {
+            return Err(format!("subscription limit of {max} reached"));
+        }
+        self.topics.insert(topic);
+        Ok(())
+    }
+
+    /// The message announcing `change`, if the client subscribed to the order.
+    fn deliver(&self, change: &OrderChange) -> Option<Message> {
+        if !self.topics.iter().any(|topic| topic.matches(&change.order)) {
+            return None;
+        }
+        if self.view == PiiView::Full {
+            let actor = self
+                .principal
+                .as_ref()
+                .map_or("anonymous", |p| p.name.as_str());
+            audit::unmasked_access(actor, Some(&self.request_id.0), RESOURCE, 1);
+        }
+        let order = change.order.view(self.view);
+        Some(
+            Reply::Change {
+                change: change.kind.to_string(),
+                order: &order,
+            }
+            .into_message(),
+        )
+    }
+}
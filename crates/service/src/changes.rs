@@ -0,0 +1,93 @@
+//! Notifications of orders created or updated through the service.
+//!
+//! [`OrderServiceImpl`](crate::OrderServiceImpl) publishes an [`OrderChange`] after
+//! every committed save, whichever path the order came in by (Kafka consumer,
+//! replay or HTTP update). Subscribers receive them over a bounded broadcast
+//! channel; one that falls more than the channel's capacity behind loses the
+//! oldest changes.
+
+use model::Order;
+use std::fmt;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+/// How an order changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    /// The order was saved for the first time.
+    Created,
+    /// A stored order was replaced.
+    Updated,
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Created => "created",
+            Self::Updated => "updated",
+        })
+    }
+}
+
+/// A committed change of an order.
+#[derive(Debug, Clone)]
+pub struct OrderChange {
+    pub kind: ChangeKind,
+    /// The order as saved.
+    pub order: Arc<Order>,
+}
+
+/// Broadcasts order changes to any number of subscribers.
+///
+/// Clones share the same channel.
+#[derive(Debug, Clone)]
+pub struct OrderChanges {
+    sender: broadcast::Sender<OrderChange>,
+}
+
+impl OrderChanges {
+    /// Creates a channel buffering up to `capacity` changes per subscriber.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            sender: broadcast::channel(capacity.max(1)).0,
+        }
+    }
+
+    /// Notifies all current subscribers; without subscribers the change is dropped.
+    pub fn publish(&self, kind: ChangeKind, order: &Order) {
+        if self.sender.receiver_count() == 0 {
+            return;
+        }
+        let _ = self.sender.send(OrderChange {
+            kind,
+            order: Arc::new(order.clone()),
+        });
+    }
+
+    /// Subscribes to changes published from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<OrderChange> {
+        self.sender.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_subscribers_receive_changes_published_after_subscribing() {
+        let changes = OrderChanges::new(4);
+        let order = Order {
+            order_uid: "b563feb7b2b84b6test".into(),
+            ..Default::default()
+        };
+        changes.publish(ChangeKind::Created, &order);
+
+        let mut receiver = changes.clone().subscribe();
+        changes.publish(ChangeKind::Updated, &order);
+        let change = receiver.recv().await.unwrap();
+        assert_eq!(change.kind, ChangeKind::Updated);
+        assert_eq!(change.order.order_uid, order.order_uid);
+        assert!(receiver.try_recv().is_err());
+    }
+}
@@ -0,0 +1,174 @@
+//! Business KPI metrics recorded for every newly saved order.
+//!
+//! Label values come from order data, so each labelled dimension is restricted to
+//! a configured allow-list; values outside it are reported as [`OTHER`].
+
+use model::Order;
+use prometheus::{Histogram, HistogramOpts, IntCounterVec, Opts, Registry};
+use std::collections::HashSet;
+
+/// Label value for anything not on the allow-list.
+pub const OTHER: &str = "other";
+
+/// Label values that may appear in the KPI metrics, per dimension.
+#[derive(Debug, Clone, Default)]
+pub struct KpiAllowList {
+    pub delivery_services: HashSet<String>,
+    pub entries: HashSet<String>,
+    pub locales: HashSet<String>,
+    pub currencies: HashSet<String>,
+}
+
+/// Returns `value` if it is allowed, [`OTHER`] otherwise.
+fn bounded<'a>(allowed: &HashSet<String>, value: &'a str) -> &'a str {
+    if allowed.contains(value) {
+        value
+    } else {
+        OTHER
+    }
+}
+
+/// Order volume, revenue and basket metrics.
+pub struct BusinessMetrics {
+    allow_list: KpiAllowList,
+    /// Saved orders, by delivery service, entry and locale.
+    orders_total: IntCounterVec,
+    /// Sum of `Payment.amount` of saved orders, by currency.
+    revenue_total: IntCounterVec,
+    /// Number of items per saved order.
+    items_per_order: Histogram,
+    /// `Payment.goods_total` per saved order.
+    goods_total: Histogram,
+    /// Average item `sale` percentage per saved order.
+    sale_percent: Histogram,
+}
+
+impl BusinessMetrics {
+    /// Creates the KPI metrics and registers them in `registry`.
+    pub fn new(registry: &Registry, allow_list: KpiAllowList) -> prometheus::Result<Self> {
+        let orders_total = IntCounterVec::new(
+            Opts::new("orders_saved_total", "Total number of saved orders"),
+            &["delivery_service", "entry", "locale"],
+        )?;
+        let revenue_total = IntCounterVec::new(
+            Opts::new(
+                "orders_revenue_total",
+                "Sum of payment amounts of saved orders",
+            ),
+            &["currency"],
+        )?;
+        let items_per_order = Histogram::with_opts(
+            HistogramOpts::new("orders_items_per_order", "Number of items per saved order")
+                .buckets(vec![1.0, 2.0, 3.0, 5.0, 10.0, 20.0, 50.0, 100.0]),
+        )?;
+        let goods_total = Histogram::with_opts(
+            HistogramOpts::new("orders_goods_total", "Goods total per saved order")
+                .buckets(prometheus::exponential_buckets(10.0, 4.0, 8)?),
+        )?;
+        let sale_percent = Histogram::with_opts(
+            HistogramOpts::new(
+                "orders_sale_percent",
+                "Average item sale percentage per saved order",
+            )
+            .buckets(vec![0.0, 5.0, 10.0, 20.0, 30.0, 50.0, 75.0, 100.0]),
+        )?;
+
+        registry.register(Box::new(orders_total.clone()))?;
+        registry.register(Box::new(revenue_total.clone()))?;
+        registry.register(Box::new(items_per_order.clone()))?;
+        registry.register(Box::new(goods_total.clone()))?;
+        registry.register(Box::new(sale_percent.clone()))?;
+
+        Ok(Self {
+            allow_list,
+            orders_total,
+            revenue_total,
+            items_per_order,
+            goods_total,
+            sale_percent,
+        })
+    }
+
+    /// Records a newly saved order.
+    pub fn record(&self, order: &Order) {
+        let allow = &self.allow_list;
+        self.orders_total
+            .with_label_values(&[
+                bounded(&allow.delivery_services, &order.delivery_service),
+                bounded(&allow.entries, &order.entry),
+                bounded(&allow.locales, &order.locale),
+            ])
+            .inc();
+        self.revenue_total
+            .with_label_values(&[bounded(&allow.currencies, &order.payment.currency)])
+            .inc_by(order.payment.amount.max(0) as u64);
+        self.items_per_order.observe(order.items.len() as f64);
+        self.goods_total
+            .observe(f64::from(order.payment.goods_total));
+        if !order.items.is_empty() {
+            let sale_sum: f64 = order.items.iter().map(|item| f64::from(item.sale)).sum();
+            self.sale_percent
+                .observe(sale_sum / order.items.len() as f64);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::{Item, Payment};
+
+    #[test]
+    fn test_labels_outside_allow_list_are_other() {
+        let registry = Registry::new();
+        let metrics = BusinessMetrics::new(
+            &registry,
+            KpiAllowList {
+                delivery_services: HashSet::from(["meest".to_string()]),
+                entries: HashSet::from(["WBIL".to_string()]),
+                locales: HashSet::from(["en".to_string()]),
+                currencies: HashSet::from(["USD".to_string()]),
+            },
+        )
+        .unwrap();
+
+        let order = Order {
+            delivery_service: "meest".into(),
+            entry: "WBIL".into(),
+            locale: "xx-unknown".into(),
+            payment: Payment {
+                currency: "XYZ".into(),
+                amount: 1500,
+                goods_total: 1300,
+                ..Default::default()
+            },
+            items: vec![
+                Item {
+                    sale: 30,
+                    ..Default::default()
+                },
+                Item {
+                    sale: 10,
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        metrics.record(&order);
+        metrics.record(&order);
+
+        assert_eq!(
+            metrics
+                .orders_total
+                .with_label_values(&["meest", "WBIL", OTHER])
+                .get(),
+            2
+        );
+        assert_eq!(
+            metrics.revenue_total.with_label_values(&[OTHER]).get(),
+            3000
+        );
+        assert_eq!(metrics.items_per_order.get_sample_sum(), 4.0);
+        assert_eq!(metrics.sale_percent.get_sample_sum(), 40.0);
+    }
+}
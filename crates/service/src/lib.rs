@@ -6,20 +6,36 @@
 //!
 //! # Features
 //! - Atomic saving of [`Order`]s (and related entities) in a single transaction.
-//! - Validation of input data before persistence.
+//! - Bulk saving of many orders per transaction with multi-row inserts.
+//! - Batch saving with per-order savepoints, so one bad order does not fail the batch.
+//! - Configurable handling of orders that already exist ([`DuplicatePolicy`]).
+//! - Validation of input data before persistence, with optional stricter
+//!   [`ValidationProfile`]s for untrusted sources.
 //! - Dependency injection for testability and loose coupling.
 //! - Async-first API suitable for scalable web applications.
 //! - Well-typed error handling via [`ServiceError`].
+//! - Optional business KPI metrics for saved orders ([`BusinessMetrics`]).
+//! - Optional notifications of created and updated orders ([`OrderChanges`]).
+
+mod changes;
+mod kpi;
+
+pub use changes::{ChangeKind, OrderChange, OrderChanges};
+pub use kpi::{BusinessMetrics, KpiAllowList};
 
 use anyhow::Result;
 use async_trait::async_trait;
+use deadpool_postgres::tokio_postgres::Transaction;
 use deadpool_postgres::{Pool, PoolError};
-use model::Order;
+use model::{Delivery, Item, Order, Payment};
 use repository::{
     DeliveriesRepository, ItemsRepository, OrdersRepository, PaymentsRepository, RepositoryError,
 };
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
 use thiserror::Error;
-use tracing::instrument;
+use tracing::{debug, instrument};
 
 /// The main error type for all operations in [`OrderService`] and [`OrderServiceImpl`].
 #[derive(Debug, Error)]
@@ -38,6 +54,152 @@ pub enum ServiceError {
     Unexpected(String),
 }
 
+/// What to do when an order with the same `order_uid` is already stored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Reject the order; the insert fails with a unique-constraint error.
+    #[default]
+    Fail,
+    /// Keep the stored order and report the new one as skipped.
+    Skip,
+    /// Replace the stored order and all its related entities.
+    Overwrite,
+}
+
+impl FromStr for DuplicatePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "fail" => Ok(Self::Fail),
+            "skip" => Ok(Self::Skip),
+            "overwrite" => Ok(Self::Overwrite),
+            other => Err(format!(
+                "unknown duplicate policy '{other}' (expected fail, skip or overwrite)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for DuplicatePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Fail => "fail",
+            Self::Skip => "skip",
+            Self::Overwrite => "overwrite",
+        })
+    }
+}
+
+/// Checks the fields every saved order must have.
+///
+/// This is the validation [`OrderService`] applies before saving; it is public so
+/// orders accepted over HTTP can be rejected before they are published.
+///
+/// # Errors
+/// Returns [`ServiceError::InvalidOrder`] if any required field is missing or incorrect.
+pub fn validate_order(order: &Order) -> Result<(), ServiceError> {
+    if order.order_uid.is_empty() {
+        return Err(ServiceError::InvalidOrder("order_uid is empty".into()));
+    }
+    if order.items.is_empty() {
+        return Err(ServiceError::InvalidOrder("order has no items".into()));
+    }
+    if order.delivery.name.is_empty() || order.delivery.phone.is_empty() {
+        return Err(ServiceError::InvalidOrder("invalid delivery data".into()));
+    }
+    Ok(())
+}
+
+/// Extra checks applied to orders from a particular source, on top of the
+/// validation every saved order goes through.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ValidationProfile {
+    /// No checks beyond the built-in validation.
+    #[default]
+    Default,
+    /// Also requires a customer, a three-letter currency, payment totals that add
+    /// up, and items that belong to the order's shipment.
+    Strict,
+}
+
+impl ValidationProfile {
+    /// Checks `order` against the profile.
+    ///
+    /// # Errors
+    /// Returns [`ServiceError::InvalidOrder`] describing the first failed check.
+    pub fn validate(&self, order: &Order) -> Result<(), ServiceError> {
+        if *self == Self::Default {
+            return Ok(());
+        }
+        let invalid = |msg: &str| Err(ServiceError::InvalidOrder(msg.into()));
+        if order.customer_id.is_empty() {
+            return invalid("customer_id is empty");
+        }
+        let currency = &order.payment.currency;
+        if currency.len() != 3 || !currency.chars().all(|c| c.is_ascii_uppercase()) {
+            return invalid("payment currency is not a three-letter code");
+        }
+        let payment = &order.payment;
+        if payment.amount != payment.goods_total + payment.delivery_cost + payment.custom_fee {
+            return invalid("payment amount does not match goods, delivery and fees");
+        }
+        if order
+            .items
+            .iter()
+            .any(|item| item.track_number != order.track_number)
+        {
+            return invalid("item track_number does not match the order");
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ValidationProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "default" => Ok(Self::Default),
+            "strict" => Ok(Self::Strict),
+            other => Err(format!(
+                "unknown validation profile '{other}' (expected default or strict)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ValidationProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Default => "default",
+            Self::Strict => "strict",
+        })
+    }
+}
+
+/// Options for [`OrderService::save_order_with`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SaveOptions {
+    /// How to treat an order that is already stored.
+    pub duplicate_policy: DuplicatePolicy,
+}
+
+/// What [`OrderService::save_order_with`] did with an order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaveOutcome {
+    /// The order was new and has been inserted.
+    Inserted,
+    /// The order already existed and was left untouched.
+    Skipped,
+    /// The order already existed and has been replaced.
+    Overwritten,
+}
+
+/// Decides whether a stored order passes a condition, e.g. in
+/// [`OrderService::update_order_if`].
+pub type OrderPredicate<'a> = dyn Fn(&Order) -> bool + Send + Sync + 'a;
+
 /// Trait describing business operations for order management.
 ///
 /// Service implementations are expected to guarantee atomicity and data integrity
@@ -55,6 +217,66 @@ pub trait OrderService: Send + Sync {
     /// a connection cannot be obtained.
     async fn save_order(&self, order: &Order) -> Result<(), ServiceError>;
 
+    /// Atomically persists the order, handling an existing order per `options`.
+    ///
+    /// # Arguments
+    /// * `order` - The order to save.
+    /// * `options` - How to treat an order with the same `order_uid` that is already stored.
+    ///
+    /// # Returns
+    /// Whether the order was inserted, skipped or overwritten.
+    ///
+    /// # Errors
+    /// Same as [`OrderService::save_order`]; with [`DuplicatePolicy::Fail`] an existing
+    /// order results in [`ServiceError::Db`].
+    async fn save_order_with(
+        &self,
+        order: &Order,
+        options: &SaveOptions,
+    ) -> Result<SaveOutcome, ServiceError>;
+
+    /// Replaces a stored order, provided it has not changed since the caller read it.
+    ///
+    /// The stored order is locked and then loaded inside the saving transaction, and
+    /// only replaced if `is_current` accepts it, so of two updates based on the
+    /// same copy only the first succeeds.
+    ///
+    /// # Arguments
+    /// * `order` - The new order; it replaces the stored order with the same `order_uid`.
+    /// * `is_current` - Whether the stored order is the copy the caller based its update on.
+    ///
+    /// # Returns
+    /// `false` if `is_current` rejected the stored order; nothing is written then.
+    ///
+    /// # Errors
+    /// Same as [`OrderService::save_order`]; an order that is not stored results in
+    /// [`ServiceError::Db`] with [`RepositoryError::NotFound`].
+    async fn update_order_if(
+        &self,
+        order: &Order,
+        is_current: &OrderPredicate<'_>,
+    ) -> Result<bool, ServiceError>;
+
+    /// Persists a batch of orders in one transaction, isolating failures per order.
+    ///
+    /// Each order is written under its own savepoint, so an invalid or conflicting
+    /// order is rolled back on its own while the rest of the batch is committed.
+    ///
+    /// # Arguments
+    /// * `orders` - The orders to save.
+    ///
+    /// # Returns
+    /// One result per input order, in the same order.
+    ///
+    /// # Errors
+    /// Returns an error only if the batch as a whole fails: [`ServiceError::Pool`] if
+    /// a connection cannot be obtained, or [`ServiceError::Unexpected`] if the
+    /// transaction cannot be started or committed. In that case nothing is saved.
+    async fn save_order_batch(
+        &self,
+        orders: &[Order],
+    ) -> Result<Vec<Result<(), ServiceError>>, ServiceError>;
+
     /// Retrieves the full order by its unique ID, including all related entities.
     ///
     /// # Arguments
@@ -75,6 +297,8 @@ pub struct OrderServiceImpl<R1, R2, R3, R4> {
     deliveries_repo: R2,
     payments_repo: R3,
     items_repo: R4,
+    metrics: Option<BusinessMetrics>,
+    changes: Option<OrderChanges>,
 }
 
 impl<R1, R2, R3, R4> OrderServiceImpl<R1, R2, R3, R4>
@@ -107,6 +331,38 @@ where
             deliveries_repo,
             payments_repo,
             items_repo,
+            metrics: None,
+            changes: None,
+        }
+    }
+
+    /// Records [`BusinessMetrics`] for every order this service newly saves.
+    pub fn with_metrics(mut self, metrics: BusinessMetrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Publishes every order this service creates or updates to `changes`.
+    pub fn with_changes(mut self, changes: OrderChanges) -> Self {
+        self.changes = Some(changes);
+        self
+    }
+
+    /// Records the KPIs of newly saved orders, if metrics are enabled, and
+    /// announces them as created.
+    fn record_saved<'a>(&self, orders: impl IntoIterator<Item = &'a Order>) {
+        for order in orders {
+            if let Some(metrics) = &self.metrics {
+                metrics.record(order);
+            }
+            self.notify(ChangeKind::Created, order);
+        }
+    }
+
+    /// Announces a committed change, if notifications are enabled.
+    fn notify(&self, kind: ChangeKind, order: &Order) {
+        if let Some(changes) = &self.changes {
+            changes.publish(kind, order);
         }
     }
 
@@ -114,19 +370,90 @@ where
     ///
     /// Returns [`ServiceError::InvalidOrder`] if any required field is missing or incorrect.
     fn validate_order(&self, order: &Order) -> Result<(), ServiceError> {
-        if order.order_uid.is_empty() {
-            return Err(ServiceError::InvalidOrder("order_uid is empty".into()));
-        }
-        if order.items.is_empty() {
-            return Err(ServiceError::InvalidOrder("order has no items".into()));
-        }
-        if order.delivery.name.is_empty() || order.delivery.phone.is_empty() {
-            return Err(ServiceError::InvalidOrder("invalid delivery data".into()));
-        }
+        validate_order(order)
+    }
+
+    /// Inserts one order and its related entities within `tx`.
+    async fn insert_order_tx(
+        &self,
+        tx: &Transaction<'_>,
+        order: &Order,
+    ) -> Result<(), ServiceError> {
+        self.orders_repo.insert_tx(tx, order).await?;
+        self.deliveries_repo
+            .insert_tx(tx, &order.delivery, &order.order_uid)
+            .await?;
+        self.payments_repo
+            .insert_tx(tx, &order.payment, &order.order_uid)
+            .await?;
+        self.items_repo
+            .insert_tx(tx, &order.items, &order.order_uid)
+            .await?;
+        Ok(())
+    }
+
+    /// Replaces a stored order and its related entities within `tx`.
+    ///
+    /// The order row is updated rather than deleted and re-inserted, so a
+    /// transaction waiting for its lock finds the new row instead of none.
+    async fn replace_order_tx(
+        &self,
+        tx: &Transaction<'_>,
+        order: &Order,
+    ) -> Result<(), ServiceError> {
+        self.items_repo
+            .delete_by_order_id_tx(tx, &order.order_uid)
+            .await?;
+        self.payments_repo
+            .delete_by_order_id_tx(tx, &order.order_uid)
+            .await?;
+        self.deliveries_repo
+            .delete_by_order_id_tx(tx, &order.order_uid)
+            .await?;
+        self.orders_repo.update_tx(tx, order).await?;
+        self.deliveries_repo
+            .insert_tx(tx, &order.delivery, &order.order_uid)
+            .await?;
+        self.payments_repo
+            .insert_tx(tx, &order.payment, &order.order_uid)
+            .await?;
+        self.items_repo
+            .insert_tx(tx, &order.items, &order.order_uid)
+            .await?;
+        Ok(())
+    }
+
+    /// Inserts many orders and their related entities within `tx`, one statement per table.
+    async fn insert_orders_tx(
+        &self,
+        tx: &Transaction<'_>,
+        orders: &[Order],
+    ) -> Result<(), ServiceError> {
+        let deliveries: Vec<(&str, &Delivery)> = orders
+            .iter()
+            .map(|o| (o.order_uid.as_str(), &o.delivery))
+            .collect();
+        let payments: Vec<(&str, &Payment)> = orders
+            .iter()
+            .map(|o| (o.order_uid.as_str(), &o.payment))
+            .collect();
+        let items: Vec<(&str, &[Item])> = orders
+            .iter()
+            .map(|o| (o.order_uid.as_str(), o.items.as_slice()))
+            .collect();
+
+        self.orders_repo.insert_many_tx(tx, orders).await?;
+        self.deliveries_repo.insert_many_tx(tx, &deliveries).await?;
+        self.payments_repo.insert_many_tx(tx, &payments).await?;
+        self.items_repo.insert_many_tx(tx, &items).await?;
         Ok(())
     }
 }
 
+fn duplicate_uid_error(order_uid: &str) -> ServiceError {
+    ServiceError::InvalidOrder(format!("duplicate order_uid in batch: {order_uid}"))
+}
+
 #[async_trait]
 impl<R1, R2, R3, R4> OrderService for OrderServiceImpl<R1, R2, R3, R4>
 where
@@ -152,24 +479,182 @@ where
             .await
             .map_err(|e| ServiceError::Unexpected(format!("Begin transaction failed: {e}")))?;
 
-        self.orders_repo.insert_tx(&tx, order).await?;
-        self.deliveries_repo
-            .insert_tx(&tx, &order.delivery, &order.order_uid)
-            .await?;
-        self.payments_repo
-            .insert_tx(&tx, &order.payment, &order.order_uid)
-            .await?;
-        self.items_repo
-            .insert_tx(&tx, &order.items, &order.order_uid)
-            .await?;
+        self.insert_order_tx(&tx, order).await?;
 
         tx.commit()
             .await
             .map_err(|e| ServiceError::Unexpected(format!("Commit failed: {e}")))?;
+        self.record_saved([order]);
 
         Ok(())
     }
 
+    /// Saves the order in a single DB transaction, applying the duplicate policy.
+    ///
+    /// With [`DuplicatePolicy::Skip`] and [`DuplicatePolicy::Overwrite`] the existing
+    /// order row is locked first, so concurrent saves of the same order are serialized.
+    #[instrument(skip(self, order, options), fields(policy = %options.duplicate_policy))]
+    async fn save_order_with(
+        &self,
+        order: &Order,
+        options: &SaveOptions,
+    ) -> Result<SaveOutcome, ServiceError> {
+        self.validate_order(order)?;
+
+        let mut client = self.db_pool.get().await.map_err(ServiceError::from)?;
+        let tx = client
+            .transaction()
+            .await
+            .map_err(|e| ServiceError::Unexpected(format!("Begin transaction failed: {e}")))?;
+
+        let outcome = match options.duplicate_policy {
+            DuplicatePolicy::Fail => SaveOutcome::Inserted,
+            policy => {
+                if !self.orders_repo.exists_tx(&tx, &order.order_uid).await? {
+                    SaveOutcome::Inserted
+                } else if policy == DuplicatePolicy::Skip {
+                    debug!("Order {} already exists, skipping", order.order_uid);
+                    return Ok(SaveOutcome::Skipped);
+                } else {
+                    SaveOutcome::Overwritten
+                }
+            }
+        };
+        if outcome == SaveOutcome::Overwritten {
+            self.replace_order_tx(&tx, order).await?;
+        } else {
+            self.insert_order_tx(&tx, order).await?;
+        }
+
+        tx.commit()
+            .await
+            .map_err(|e| ServiceError::Unexpected(format!("Commit failed: {e}")))?;
+        // An overwritten order was already counted when it was first saved
+        if outcome == SaveOutcome::Inserted {
+            self.record_saved([order]);
+        } else {
+            self.notify(ChangeKind::Updated, order);
+        }
+
+        Ok(outcome)
+    }
+
+    /// Locks the order row, then compares and replaces the order in one transaction.
+    ///
+    /// Every overwrite of an order takes the same row lock, so while it is held the
+    /// committed order cannot change and is loaded through the repositories.
+    #[instrument(skip(self, order, is_current))]
+    async fn update_order_if(
+        &self,
+        order: &Order,
+        is_current: &OrderPredicate<'_>,
+    ) -> Result<bool, ServiceError> {
+        self.validate_order(order)?;
+
+        let mut client = self.db_pool.get().await.map_err(ServiceError::from)?;
+        let tx = client
+            .transaction()
+            .await
+            .map_err(|e| ServiceError::Unexpected(format!("Begin transaction failed: {e}")))?;
+
+        if !self.orders_repo.exists_tx(&tx, &order.order_uid).await? {
+            return Err(RepositoryError::NotFound.into());
+        }
+        let current = self.get_order_by_id(&order.order_uid).await?;
+        if !is_current(&current) {
+            debug!("Order {} has changed, not updating", order.order_uid);
+            return Ok(false);
+        }
+        self.replace_order_tx(&tx, order).await?;
+
+        tx.commit()
+            .await
+            .map_err(|e| ServiceError::Unexpected(format!("Commit failed: {e}")))?;
+        self.notify(ChangeKind::Updated, order);
+
+        Ok(true)
+    }
+
+    /// Saves a batch in one transaction, falling back to per-order savepoints on failure.
+    ///
+    /// Valid orders are first written with multi-row inserts under a single savepoint.
+    /// If that fails (e.g. one order conflicts with an existing row), the savepoint is
+    /// rolled back and the orders are retried one by one, each under its own savepoint,
+    /// so only the offending orders are reported as failed.
+    #[instrument(skip(self, orders), fields(batch_size = orders.len()))]
+    async fn save_order_batch(
+        &self,
+        orders: &[Order],
+    ) -> Result<Vec<Result<(), ServiceError>>, ServiceError> {
+        let mut results: Vec<Result<(), ServiceError>> = Vec::with_capacity(orders.len());
+        let mut seen = HashSet::new();
+        for order in orders {
+            let result = self.validate_order(order).and_then(|()| {
+                if seen.insert(order.order_uid.as_str()) {
+                    Ok(())
+                } else {
+                    Err(duplicate_uid_error(&order.order_uid))
+                }
+            });
+            results.push(result);
+        }
+
+        let valid: Vec<Order> = orders
+            .iter()
+            .zip(&results)
+            .filter(|(_, result)| result.is_ok())
+            .map(|(order, _)| order.clone())
+            .collect();
+        if valid.is_empty() {
+            return Ok(results);
+        }
+
+        let mut client = self.db_pool.get().await.map_err(ServiceError::from)?;
+        let mut tx = client
+            .transaction()
+            .await
+            .map_err(|e| ServiceError::Unexpected(format!("Begin transaction failed: {e}")))?;
+
+        let savepoint_error =
+            |e| ServiceError::Unexpected(format!("Savepoint operation failed: {e}"));
+
+        let bulk = tx.savepoint("batch_bulk").await.map_err(savepoint_error)?;
+        match self.insert_orders_tx(&bulk, &valid).await {
+            Ok(()) => bulk.commit().await.map_err(savepoint_error)?,
+            Err(e) => {
+                debug!("Bulk insert failed, retrying orders one by one: {e}");
+                bulk.rollback().await.map_err(savepoint_error)?;
+
+                for (order, result) in orders.iter().zip(results.iter_mut()) {
+                    if result.is_err() {
+                        continue;
+                    }
+                    let savepoint = tx.savepoint("batch_order").await.map_err(savepoint_error)?;
+                    match self.insert_order_tx(&savepoint, order).await {
+                        Ok(()) => savepoint.commit().await.map_err(savepoint_error)?,
+                        Err(e) => {
+                            savepoint.rollback().await.map_err(savepoint_error)?;
+                            *result = Err(e);
+                        }
+                    }
+                }
+            }
+        }
+
+        tx.commit()
+            .await
+            .map_err(|e| ServiceError::Unexpected(format!("Commit failed: {e}")))?;
+        self.record_saved(
+            orders
+                .iter()
+                .zip(&results)
+                .filter(|(_, result)| result.is_ok())
+                .map(|(order, _)| order),
+        );
+
+        Ok(results)
+    }
+
     /// Loads a full order with delivery, payment, and items by its unique order_uid.
     ///
     /// Returns [`ServiceError::Db`] if the order or any related entity is not found.
@@ -188,3 +673,53 @@ where
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::{Item, Payment};
+
+    /// An order that passes every strict check.
+    fn strict_order() -> Order {
+        Order {
+            order_uid: "strict-1".into(),
+            track_number: "WBILMTESTTRACK".into(),
+            customer_id: "test".into(),
+            payment: Payment {
+                currency: "USD".into(),
+                amount: 1817,
+                goods_total: 317,
+                delivery_cost: 1500,
+                custom_fee: 0,
+                ..Default::default()
+            },
+            items: vec![Item {
+                track_number: "WBILMTESTTRACK".into(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_strict_profile_rejects_each_failed_check() {
+        let strict = ValidationProfile::Strict;
+        assert!(strict.validate(&strict_order()).is_ok());
+
+        let rejects = |breakage: fn(&mut Order), check: &str| {
+            let mut order = strict_order();
+            breakage(&mut order);
+            let err = strict.validate(&order).unwrap_err();
+            assert!(
+                matches!(&err, ServiceError::InvalidOrder(msg) if msg.contains(check)),
+                "expected the {check} check to fail, got {err}"
+            );
+            assert!(ValidationProfile::Default.validate(&order).is_ok());
+        };
+        rejects(|o| o.customer_id.clear(), "customer_id");
+        rejects(|o| o.payment.currency = "usd".into(), "currency");
+        rejects(|o| o.payment.currency = "USDT".into(), "currency");
+        rejects(|o| o.payment.custom_fee = 1, "amount");
+        rejects(|o| o.items[0].track_number = "OTHER".into(), "track_number");
+    }
+}
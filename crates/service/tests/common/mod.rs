@@ -0,0 +1,63 @@
+//! Shared setup of the database-backed service tests.
+//!
+//! The tests are ignored unless run with `--ignored`; see `test_db` for the required
+//! `DATABASE_URL`. Every test uses its own `order_uid` prefix and deletes its rows
+//! afterwards.
+
+#![allow(dead_code)]
+
+use deadpool_postgres::tokio_postgres::Client;
+use model::Order;
+use repository::{
+    PgDeliveriesRepository, PgItemsRepository, PgOrdersRepository, PgPaymentsRepository,
+};
+use service::OrderServiceImpl;
+use test_db::{connect, database_url, pool};
+
+pub type Service = OrderServiceImpl<
+    PgOrdersRepository,
+    PgDeliveriesRepository,
+    PgPaymentsRepository,
+    PgItemsRepository,
+>;
+
+/// Builds the service over `DATABASE_URL`, with a separate client for checking rows.
+pub async fn service() -> (Service, Client) {
+    let dsn = database_url();
+    let service = OrderServiceImpl::new(
+        pool(&dsn),
+        PgOrdersRepository::new(connect(&dsn).await),
+        PgDeliveriesRepository::new(connect(&dsn).await),
+        PgPaymentsRepository::new(connect(&dsn).await),
+        PgItemsRepository::new(connect(&dsn).await),
+    );
+    (service, connect(&dsn).await)
+}
+
+/// A valid order with one item.
+pub fn order(uid: &str) -> Order {
+    test_db::order(uid, 1)
+}
+
+pub async fn stored_uids(db: &Client, prefix: &str) -> Vec<String> {
+    db.query(
+        "SELECT order_uid FROM orders WHERE order_uid LIKE $1 || '%' ORDER BY order_uid",
+        &[&prefix],
+    )
+    .await
+    .unwrap()
+    .iter()
+    .map(|row| row.get(0))
+    .collect()
+}
+
+pub async fn cleanup(db: &Client, prefix: &str) {
+    for table in ["items", "payments", "deliveries", "orders"] {
+        db.execute(
+            &format!("DELETE FROM {table} WHERE order_uid LIKE $1 || '%'"),
+            &[&prefix],
+        )
+        .await
+        .unwrap();
+    }
+}
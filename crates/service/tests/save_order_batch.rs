@@ -0,0 +1,128 @@
+//! Bulk insert and savepoint fallback of `OrderService::save_order_batch` against a
+//! real database.
+//!
+//! See `common` for the required `DATABASE_URL`.
+
+mod common;
+
+use chrono::{DateTime, Utc};
+use common::{cleanup, order, service, stored_uids};
+use model::{Item, Order};
+use service::{OrderService, ServiceError};
+
+#[tokio::test]
+#[ignore = "needs a migrated database in DATABASE_URL"]
+async fn test_batch_is_saved_in_bulk() {
+    let (service, db) = service().await;
+    let prefix = format!("batch-bulk-{}-", std::process::id());
+    cleanup(&db, &prefix).await;
+
+    let orders: Vec<Order> = (0..3).map(|i| order(&format!("{prefix}{i}"))).collect();
+    let results = service.save_order_batch(&orders).await.unwrap();
+
+    assert!(results.iter().all(Result::is_ok));
+    assert_eq!(stored_uids(&db, &prefix).await.len(), 3);
+    cleanup(&db, &prefix).await;
+}
+
+#[tokio::test]
+#[ignore = "needs a migrated database in DATABASE_URL"]
+async fn test_bulk_insert_stores_every_column() {
+    let (service, db) = service().await;
+    let prefix = format!("batch-columns-{}-", std::process::id());
+    cleanup(&db, &prefix).await;
+
+    // Postgres keeps microseconds, so the timestamp must not carry nanoseconds
+    let date_created = DateTime::<Utc>::from_timestamp_micros(1_700_000_000_123_456).unwrap();
+    let orders: Vec<Order> = (0..3)
+        .map(|i| {
+            let mut order = order(&format!("{prefix}{i}"));
+            order.customer_id = format!("customer-{i}");
+            order.sm_id = i;
+            order.date_created = date_created;
+            order.source_topic = Some("orders".into());
+            order.delivery.city = format!("City {i}");
+            order.payment.amount = 100 * (i + 1);
+            order.payment.payment_dt = 1_637_907_727 + i64::from(i);
+            // A different number of items per order, so the item arrays are uneven
+            order.items = (0..=i)
+                .map(|n| Item {
+                    chrt_id: n,
+                    track_number: order.track_number.clone(),
+                    price: 10 * (n + 1),
+                    rid: format!("{prefix}{i}-{n}"),
+                    name: format!("Item {n}"),
+                    sale: n,
+                    size: "0".into(),
+                    total_price: 10 * (n + 1),
+                    nm_id: 1000 + n,
+                    brand: "Brand".into(),
+                    status: 202,
+                })
+                .collect();
+            order
+        })
+        .collect();
+    let results = service.save_order_batch(&orders).await.unwrap();
+
+    assert!(results.iter().all(Result::is_ok));
+    for order in &orders {
+        let stored = service.get_order_by_id(&order.order_uid).await.unwrap();
+        assert_eq!(&stored, order);
+    }
+    cleanup(&db, &prefix).await;
+}
+
+#[tokio::test]
+#[ignore = "needs a migrated database in DATABASE_URL"]
+async fn test_conflicting_order_falls_back_to_savepoints() {
+    let (service, db) = service().await;
+    let prefix = format!("batch-conflict-{}-", std::process::id());
+    cleanup(&db, &prefix).await;
+
+    // Already stored, so the bulk insert of the batch fails on its primary key
+    service
+        .save_order(&order(&format!("{prefix}1")))
+        .await
+        .unwrap();
+
+    let orders: Vec<Order> = (0..3).map(|i| order(&format!("{prefix}{i}"))).collect();
+    let results = service.save_order_batch(&orders).await.unwrap();
+
+    assert!(results[0].is_ok());
+    assert!(matches!(results[1], Err(ServiceError::Db(_))));
+    assert!(results[2].is_ok());
+    assert_eq!(
+        stored_uids(&db, &prefix).await,
+        [0, 1, 2].map(|i| format!("{prefix}{i}"))
+    );
+    cleanup(&db, &prefix).await;
+}
+
+#[tokio::test]
+#[ignore = "needs a migrated database in DATABASE_URL"]
+async fn test_invalid_and_duplicate_orders_are_rejected_individually() {
+    let (service, db) = service().await;
+    let prefix = format!("batch-invalid-{}-", std::process::id());
+    cleanup(&db, &prefix).await;
+
+    let mut invalid = order(&format!("{prefix}1"));
+    invalid.items.clear();
+    let orders = vec![
+        order(&format!("{prefix}0")),
+        invalid,
+        order(&format!("{prefix}0")),
+        order(&format!("{prefix}2")),
+    ];
+    let results = service.save_order_batch(&orders).await.unwrap();
+
+    assert!(results[0].is_ok());
+    assert!(matches!(results[1], Err(ServiceError::InvalidOrder(_))));
+    assert!(matches!(results[2], Err(ServiceError::InvalidOrder(_))));
+    assert!(results[3].is_ok());
+    assert_eq!(
+        stored_uids(&db, &prefix).await,
+        [format!("{prefix}0"), format!("{prefix}2")]
+    );
+    cleanup(&db, &prefix).await;
+}
@@ -0,0 +1,84 @@
+//! Conditional updates through `OrderService::update_order_if` against a real database.
+//!
+//! See `common` for the required `DATABASE_URL`.
+
+mod common;
+
+use common::{cleanup, order, service};
+use repository::RepositoryError;
+use service::{OrderService, ServiceError};
+
+#[tokio::test]
+#[ignore = "needs a migrated database in DATABASE_URL"]
+async fn test_update_replaces_current_order() {
+    let (service, db) = service().await;
+    let prefix = format!("update-current-{}-", std::process::id());
+    cleanup(&db, &prefix).await;
+    let uid = format!("{prefix}0");
+    service.save_order(&order(&uid)).await.unwrap();
+
+    let mut changed = order(&uid);
+    changed.track_number = "CHANGED".to_string();
+    changed.items[0].name = "Changed item".to_string();
+    let updated = service
+        .update_order_if(&changed, &|current| current.track_number == "TESTTRACK")
+        .await
+        .unwrap();
+
+    assert!(updated);
+    let stored = service.get_order_by_id(&uid).await.unwrap();
+    assert_eq!(stored.track_number, "CHANGED");
+    assert_eq!(stored.items.len(), 1);
+    assert_eq!(stored.items[0].name, "Changed item");
+    cleanup(&db, &prefix).await;
+}
+
+#[tokio::test]
+#[ignore = "needs a migrated database in DATABASE_URL"]
+async fn test_concurrent_updates_of_same_copy_conflict() {
+    let (service, db) = service().await;
+    let prefix = format!("update-race-{}-", std::process::id());
+    cleanup(&db, &prefix).await;
+    let uid = format!("{prefix}0");
+    service.save_order(&order(&uid)).await.unwrap();
+
+    // Both callers read the original order and update based on it
+    let is_original = |current: &model::Order| current.track_number == "TESTTRACK";
+    let mut first = order(&uid);
+    first.track_number = "FIRST".to_string();
+    let mut second = order(&uid);
+    second.track_number = "SECOND".to_string();
+    let (first_updated, second_updated) = tokio::join!(
+        service.update_order_if(&first, &is_original),
+        service.update_order_if(&second, &is_original),
+    );
+
+    let (first_updated, second_updated) = (first_updated.unwrap(), second_updated.unwrap());
+    assert!(
+        first_updated != second_updated,
+        "exactly one update applies"
+    );
+    let winner = if first_updated { "FIRST" } else { "SECOND" };
+    assert_eq!(
+        service.get_order_by_id(&uid).await.unwrap().track_number,
+        winner
+    );
+    cleanup(&db, &prefix).await;
+}
+
+#[tokio::test]
+#[ignore = "needs a migrated database in DATABASE_URL"]
+async fn test_update_of_unknown_order_is_not_found() {
+    let (service, db) = service().await;
+    let prefix = format!("update-unknown-{}-", std::process::id());
+    cleanup(&db, &prefix).await;
+
+    let result = service
+        .update_order_if(&order(&format!("{prefix}0")), &|_| true)
+        .await;
+
+    assert!(matches!(
+        result,
+        Err(ServiceError::Db(RepositoryError::NotFound))
+    ));
+}
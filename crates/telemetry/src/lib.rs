@@ -0,0 +1,182 @@
+//! Tracing setup and W3C trace context propagation.
+//!
+//! [`layer`] builds the OpenTelemetry layer of the `tracing` subscriber, which gives
+//! every span a trace and span id. Spans are exported over OTLP/HTTP when a
+//! collector endpoint is configured; without one trace ids are still generated and
+//! propagated, just not exported.
+//!
+//! Trace context crosses process boundaries as a `traceparent` header (plus
+//! `tracestate` when present): [`inject`] renders the current span's context as
+//! headers, and [`continue_trace`] / [`link_trace`] attach a span to the context
+//! carried by incoming headers. The [`REQUEST_ID_HEADER`] correlates log lines of
+//! one HTTP request with the Kafka messages it produces.
+
+use anyhow::{Context as _, Result};
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::{TraceContextExt, TracerProvider as _};
+use opentelemetry::{Context, global};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::trace::Tracer;
+use std::collections::HashMap;
+use tracing::{Span, Subscriber};
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Header carrying the id of the HTTP request an operation belongs to, both on
+/// HTTP requests and responses and on the Kafka messages they produce.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Tracing settings.
+#[derive(Debug, Clone)]
+pub struct TelemetryConfig {
+    /// `service.name` reported with exported spans.
+    pub service_name: String,
+    /// OTLP/HTTP traces endpoint, e.g. `http://otel-collector:4318/v1/traces`
+    /// (`None` disables export).
+    pub otlp_endpoint: Option<String>,
+}
+
+/// Flushes and shuts down span export when dropped.
+pub struct TelemetryGuard {
+    provider: SdkTracerProvider,
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        if let Err(e) = self.provider.shutdown() {
+            eprintln!("Failed to shut down trace export: {e}");
+        }
+    }
+}
+
+/// Builds the OpenTelemetry subscriber layer and installs the global trace context
+/// propagator.
+///
+/// Keep the returned guard alive for the lifetime of the process so pending
+/// spans are exported on shutdown.
+///
+/// # Errors
+/// Returns an error if the exporter cannot be built.
+pub fn layer<S>(config: &TelemetryConfig) -> Result<(OpenTelemetryLayer<S, Tracer>, TelemetryGuard)>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let resource = Resource::builder()
+        .with_service_name(config.service_name.clone())
+        .build();
+    let mut provider = SdkTracerProvider::builder().with_resource(resource);
+    if let Some(endpoint) = &config.otlp_endpoint {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()
+            .context("Failed to build OTLP span exporter")?;
+        provider = provider.with_batch_exporter(exporter);
+    }
+    let provider = provider.build();
+
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let tracer = provider.tracer(config.service_name.clone());
+
+    Ok((
+        tracing_opentelemetry::layer().with_tracer(tracer),
+        TelemetryGuard { provider },
+    ))
+}
+
+/// Returns the trace context headers (`traceparent`, `tracestate`) of `span`.
+///
+/// Empty if the span is disabled or no subscriber is installed.
+pub fn inject(span: &Span) -> HashMap<String, String> {
+    let mut headers = HashMap::new();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&span.context(), &mut headers);
+    });
+    headers
+}
+
+/// Makes `span` a child of the trace carried by `headers`.
+///
+/// Has no effect if the headers carry no valid trace context.
+pub fn continue_trace<'a>(span: &Span, headers: impl IntoIterator<Item = (&'a str, &'a [u8])>) {
+    let parent = extract(headers);
+    if parent.span().span_context().is_valid() {
+        span.set_parent(parent);
+    }
+}
+
+/// Links `span` to the trace carried by `headers`, e.g. to relate a batch span to
+/// each message it processes.
+pub fn link_trace<'a>(span: &Span, headers: impl IntoIterator<Item = (&'a str, &'a [u8])>) {
+    let linked = extract(headers);
+    let span_context = linked.span().span_context().clone();
+    if span_context.is_valid() {
+        span.add_link(span_context);
+    }
+}
+
+fn extract<'a>(headers: impl IntoIterator<Item = (&'a str, &'a [u8])>) -> Context {
+    let headers = HeaderMap(
+        headers
+            .into_iter()
+            .filter_map(|(k, v)| Some((k.to_ascii_lowercase(), std::str::from_utf8(v).ok()?)))
+            .collect(),
+    );
+    global::get_text_map_propagator(|propagator| propagator.extract(&headers))
+}
+
+/// Message headers with lowercased keys, as read by the propagator.
+struct HeaderMap<'a>(Vec<(String, &'a str)>);
+
+impl Extractor for HeaderMap<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.iter().find(|(k, _)| k == key).map(|(_, v)| *v)
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.iter().map(|(k, _)| k.as_str()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing::info_span;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn test_trace_context_round_trip() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let provider = SdkTracerProvider::builder().build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let producer = info_span!("produce");
+            let headers = inject(&producer);
+            let traceparent = &headers["traceparent"];
+            let trace_id = producer.context().span().span_context().trace_id();
+            assert!(traceparent.contains(&trace_id.to_string()), "{traceparent}");
+
+            let consumer = info_span!("consume");
+            continue_trace(
+                &consumer,
+                headers.iter().map(|(k, v)| (k.as_str(), v.as_bytes())),
+            );
+            assert_eq!(
+                consumer.context().span().span_context().trace_id(),
+                trace_id
+            );
+
+            let unrelated = info_span!("unrelated");
+            continue_trace(&unrelated, [("traceparent", &b"garbage"[..])]);
+            assert_ne!(
+                unrelated.context().span().span_context().trace_id(),
+                trace_id
+            );
+        });
+    }
+}
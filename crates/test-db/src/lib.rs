@@ -0,0 +1,78 @@
+//! Shared setup of the tests and benchmarks that run against a real database.
+//!
+//! They need a migrated database in `DATABASE_URL`. The tests are `#[ignore]`d, so a
+//! plain `cargo test` reports them as skipped; run them with
+//! `DATABASE_URL="host=localhost user=orders_user password=securepassword dbname=orders_db" cargo test -- --ignored`.
+
+use chrono::Utc;
+use deadpool_postgres::{Manager, ManagerConfig, Pool, RecyclingMethod};
+use model::{Delivery, Item, Order, Payment};
+use tokio_postgres::{Client, Config as PgConfig, NoTls};
+
+/// Connection string of the test database.
+///
+/// # Panics
+/// Panics if `DATABASE_URL` is not set.
+pub fn database_url() -> String {
+    std::env::var("DATABASE_URL").expect("DATABASE_URL must point to a migrated database")
+}
+
+/// Opens a connection to `dsn`, driving it on the current Tokio runtime.
+///
+/// # Panics
+/// Panics if the database cannot be reached.
+pub async fn connect(dsn: &str) -> Client {
+    let (client, connection) = tokio_postgres::connect(dsn, NoTls)
+        .await
+        .expect("Failed to connect to DATABASE_URL");
+    tokio::spawn(connection);
+    client
+}
+
+/// A small connection pool for `dsn`.
+///
+/// # Panics
+/// Panics if `dsn` is not a valid connection string.
+pub fn pool(dsn: &str) -> Pool {
+    let pg_config: PgConfig = dsn.parse().expect("Invalid DATABASE_URL");
+    let manager = Manager::from_config(
+        pg_config,
+        NoTls,
+        ManagerConfig {
+            recycling_method: RecyclingMethod::Fast,
+        },
+    );
+    Pool::builder(manager).max_size(2).build().unwrap()
+}
+
+/// A valid order with `item_count` items, identified by `uid`.
+pub fn order(uid: &str, item_count: usize) -> Order {
+    Order {
+        order_uid: uid.to_string(),
+        track_number: "TESTTRACK".to_string(),
+        entry: "TEST".to_string(),
+        delivery: Delivery {
+            name: "Test User".to_string(),
+            phone: "+1000000000".to_string(),
+            ..Default::default()
+        },
+        payment: Payment {
+            transaction: uid.to_string(),
+            currency: "USD".to_string(),
+            ..Default::default()
+        },
+        items: (0..item_count)
+            .map(|i| Item {
+                chrt_id: i as i32,
+                track_number: "TESTTRACK".to_string(),
+                price: 100,
+                rid: format!("{uid}-{i}"),
+                name: "Test item".to_string(),
+                total_price: 100,
+                ..Default::default()
+            })
+            .collect(),
+        date_created: Utc::now(),
+        ..Default::default()
+    }
+}
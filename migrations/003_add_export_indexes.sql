@@ -0,0 +1,5 @@
+CREATE INDEX IF NOT EXISTS orders_date_created_order_uid_idx
+    ON orders (date_created, order_uid);
+
+CREATE INDEX IF NOT EXISTS items_order_uid_idx
+    ON items (order_uid);